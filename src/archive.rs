@@ -0,0 +1,201 @@
+//! Shared implementation for archives that store files uncompressed at an offset, such as
+//! Quake paks and Doom wads. The drivers only have to parse their directory format.
+
+use crate::stream::SubReader;
+use crate::{Buffer, DirEntry, EntryType, InternalError, MsgSender, ReadSeek};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{self, Cursor, Seek, SeekFrom};
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads a fixed size name that is padded with zeros
+pub(crate) fn read_name(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+pub(crate) fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// Where the archive data comes from
+pub(crate) enum ArchiveSource {
+    File(String),
+    /// Used for archives inside other mounts
+    Memory(Buffer),
+}
+
+impl ArchiveSource {
+    pub fn reader(&self) -> io::Result<Box<dyn ReadSeek>> {
+        match self {
+            ArchiveSource::File(filename) => Ok(Box::new(File::open(filename)?)),
+            ArchiveSource::Memory(data) => Ok(Box::new(Cursor::new(data.clone()))),
+        }
+    }
+}
+
+/// Files of an archive with their offsets and sizes
+pub(crate) struct FlatArchive {
+    source: ArchiveSource,
+    files: BTreeMap<String, (u64, u64)>,
+    dirs: HashSet<String>,
+}
+
+impl FlatArchive {
+    pub fn empty() -> FlatArchive {
+        FlatArchive {
+            source: ArchiveSource::Memory(Buffer::new()),
+            files: BTreeMap::new(),
+            dirs: HashSet::new(),
+        }
+    }
+
+    /// Reads the directory of an archive with `parse`, which gets a reader for the whole
+    /// archive and returns the files as path, offset and size.
+    pub fn new<F>(source: ArchiveSource, parse: F) -> io::Result<FlatArchive>
+    where
+        F: FnOnce(&mut dyn ReadSeek) -> io::Result<Vec<(String, u64, u64)>>,
+    {
+        let mut reader = source.reader()?;
+        let len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut files = BTreeMap::new();
+        let mut dirs = HashSet::new();
+
+        // Later files replace earlier ones with the same name
+        for (path, offset, size) in parse(&mut reader)? {
+            if offset.checked_add(size).is_none_or(|end| end > len) {
+                return Err(invalid_data("file outside of the archive"));
+            }
+
+            let path = path.trim_matches('/');
+
+            let mut parent = path;
+            while let Some(end) = parent.rfind('/') {
+                parent = &parent[..end];
+                dirs.insert(parent.to_owned());
+            }
+
+            files.insert(path.to_owned(), (offset, size));
+        }
+
+        Ok(FlatArchive {
+            source,
+            files,
+            dirs,
+        })
+    }
+
+    fn find_file(&self, path: &str) -> Result<(u64, u64), InternalError> {
+        match self.files.get(path) {
+            Some(&file) => Ok(file),
+            None if self.has_entry(path) == EntryType::Directory => {
+                Err(InternalError::NotFile { path: path.into() })
+            }
+            None => Err(InternalError::PathNotFound { path: path.into() }),
+        }
+    }
+
+    pub fn has_entry(&self, path: &str) -> EntryType {
+        if self.files.contains_key(path) {
+            EntryType::File
+        } else if path.is_empty() || self.dirs.contains(path.trim_end_matches('/')) {
+            EntryType::Directory
+        } else {
+            EntryType::NotFound
+        }
+    }
+
+    pub fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let (offset, size) = self.find_file(path)?;
+
+        match &self.source {
+            ArchiveSource::Memory(data) => {
+                send_msg.finish(size)?;
+                Ok(data.slice(offset as usize..(offset + size) as usize))
+            }
+            ArchiveSource::File(filename) => {
+                let mut file = SubReader::new(File::open(filename)?, offset, size)?;
+                send_msg.read_all(&mut file, Some(size))
+            }
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    pub fn map_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let (offset, size) = self.find_file(path)?;
+
+        let filename = match &self.source {
+            ArchiveSource::File(filename) => filename,
+            ArchiveSource::Memory(_) => return self.load_file(path, send_msg),
+        };
+
+        // SAFETY: The mapping is only read through the slice of the file. Truncating the
+        // archive while it's mapped raises SIGBUS and modifying it changes the slice, as
+        // documented on `Evfs::load_file_mapped`.
+        let map = unsafe { memmap2::Mmap::map(&File::open(filename)?)? };
+        let data = Buffer::from_owner(map).slice(offset as usize..(offset + size) as usize);
+
+        send_msg.finish(size)?;
+        Ok(data)
+    }
+
+    pub fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        let (offset, size) = self.find_file(path)?;
+        let reader = self.source.reader()?;
+        Ok(Box::new(SubReader::new(reader, offset, size)?))
+    }
+
+    pub fn read_dir(&self, path: &str) -> Vec<DirEntry> {
+        let prefix = match path.trim_end_matches('/') {
+            "" => String::new(),
+            dir => format!("{}/", dir),
+        };
+
+        let mut entries = BTreeMap::new();
+
+        for name in self.files.keys() {
+            let rest = match name.strip_prefix(&prefix) {
+                Some(rest) => rest,
+                None => continue,
+            };
+
+            match rest.find('/') {
+                Some(end) => entries.insert(&rest[..end], EntryType::Directory),
+                None => entries.insert(rest, EntryType::File),
+            };
+        }
+
+        entries
+            .into_iter()
+            .map(|(name, entry_type)| DirEntry {
+                name: name.to_owned(),
+                entry_type,
+            })
+            .collect()
+    }
+}
+
+/// Reads `len` bytes at `offset` of the archive. Sizes come from headers that may be corrupt so
+/// they are checked against the size of the archive first.
+pub(crate) fn read_at(reader: &mut dyn ReadSeek, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let archive_len = reader.seek(SeekFrom::End(0))?;
+
+    if offset
+        .checked_add(len as u64)
+        .is_none_or(|end| end > archive_len)
+    {
+        return Err(invalid_data("archive is truncated"));
+    }
+
+    let mut data = vec![0; len];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
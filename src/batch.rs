@@ -0,0 +1,182 @@
+use crate::{Buffer, Handle, Progress, RecvMsg, VfsError};
+use crossbeam_channel::TryRecvError;
+
+/// How a batch deals with files that fail to load
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchMode {
+    /// Stop the batch at the first failure. Files that haven't finished are dropped.
+    FailFast,
+    /// Keep loading the remaining files and report each failure
+    CollectAll,
+}
+
+/// Reported by `BatchHandle::update` when a file in the batch has finished
+pub enum BatchEvent {
+    /// A file was loaded. `index` is the position of the file in the list given to `load_batch`
+    Loaded {
+        index: usize,
+        path: String,
+        data: Buffer,
+    },
+    /// A file failed to load
+    Failed {
+        index: usize,
+        path: String,
+        error: VfsError,
+    },
+}
+
+#[derive(PartialEq)]
+enum State {
+    Loading,
+    Loaded,
+    Failed,
+}
+
+struct BatchEntry {
+    path: String,
+    /// Dropped when the batch is aborted so the workers stop loading the file
+    handle: Option<Handle>,
+    progress: Progress,
+    state: State,
+}
+
+/// Returned by `Evfs::load_batch`. Tracks a group of loads and aggregates their progress so
+/// a single progress bar can be shown for all of them.
+pub struct BatchHandle {
+    entries: Vec<BatchEntry>,
+    mode: BatchMode,
+    aborted: bool,
+}
+
+impl BatchHandle {
+    pub(crate) fn new(loads: Vec<(String, Handle)>, mode: BatchMode) -> BatchHandle {
+        let entries = loads
+            .into_iter()
+            .map(|(path, handle)| BatchEntry {
+                path,
+                handle: Some(handle),
+                progress: Progress {
+                    bytes_done: 0,
+                    bytes_total: None,
+                },
+                state: State::Loading,
+            })
+            .collect();
+
+        BatchHandle {
+            entries,
+            mode,
+            aborted: false,
+        }
+    }
+
+    /// Processes the messages received since the last call and returns the files that
+    /// finished. This never blocks.
+    pub fn update(&mut self) -> Vec<BatchEvent> {
+        let mut events = Vec::new();
+
+        if self.aborted {
+            return events;
+        }
+
+        for (index, entry) in self.entries.iter_mut().enumerate() {
+            while let (State::Loading, Some(handle)) = (&entry.state, &entry.handle) {
+                let msg = match handle.recv.try_recv() {
+                    Ok(msg) => msg,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        RecvMsg::Error(VfsError::FileError(std::io::Error::new(
+                            std::io::ErrorKind::BrokenPipe,
+                            "The load finished without sending any data",
+                        )))
+                    }
+                };
+
+                match msg {
+                    RecvMsg::ReadProgress(progress) => entry.progress = progress,
+                    RecvMsg::ReadDone(data) => {
+                        entry.state = State::Loaded;
+                        events.push(BatchEvent::Loaded {
+                            index,
+                            path: entry.path.clone(),
+                            data,
+                        });
+                    }
+                    RecvMsg::Error(error) => {
+                        entry.state = State::Failed;
+                        events.push(BatchEvent::Failed {
+                            index,
+                            path: entry.path.clone(),
+                            error,
+                        });
+                    }
+                    _ => (),
+                }
+            }
+
+            if entry.state == State::Failed && self.mode == BatchMode::FailFast {
+                self.aborted = true;
+                break;
+            }
+        }
+
+        if self.aborted {
+            for entry in &mut self.entries {
+                entry.handle = None;
+            }
+        }
+
+        events
+    }
+
+    /// Combined progress of all files in the batch. The total is only known once every file
+    /// has reported its size. Files that failed are not included.
+    pub fn progress(&self) -> Progress {
+        let mut bytes_total = Some(0);
+        let mut bytes_done = 0;
+
+        for entry in self.entries.iter().filter(|e| e.state != State::Failed) {
+            bytes_done += entry.progress.bytes_done;
+            bytes_total = match (bytes_total, entry.progress.bytes_total) {
+                (Some(sum), Some(total)) => Some(sum + total),
+                _ => None,
+            };
+        }
+
+        Progress {
+            bytes_done,
+            bytes_total,
+        }
+    }
+
+    /// Number of files in the batch
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if the batch has no files
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of files that have been loaded
+    pub fn loaded_count(&self) -> usize {
+        self.count(State::Loaded)
+    }
+
+    /// Number of files that failed to load
+    pub fn failed_count(&self) -> usize {
+        self.count(State::Failed)
+    }
+
+    /// Returns true when all files have finished or the batch stopped because of a failure
+    /// in `BatchMode::FailFast`
+    pub fn is_done(&self) -> bool {
+        self.aborted || self.entries.iter().all(|e| e.state != State::Loading)
+    }
+
+    fn count(&self, state: State) -> usize {
+        self.entries.iter().filter(|e| e.state == state).count()
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+use std::ops::{Bound, Deref, RangeBounds};
+use std::sync::Arc;
+
+type Owner = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+/// Reference counted, sliceable view of loaded data.
+///
+/// Cloning or slicing a `Buffer` never copies the underlying bytes, so the same data can be
+/// handed to several consumers (or kept in a cache) without extra allocations. The storage
+/// can be anything that derefs to bytes, such as a `Vec<u8>` or a memory mapping.
+#[derive(Clone)]
+pub struct Buffer {
+    owner: Owner,
+    offset: usize,
+    len: usize,
+}
+
+impl Buffer {
+    /// Creates an empty buffer
+    pub fn new() -> Buffer {
+        Buffer::from_static(&[])
+    }
+
+    /// Creates a buffer that points to static data without copying it
+    pub fn from_static(data: &'static [u8]) -> Buffer {
+        Buffer::from_owner(data)
+    }
+
+    /// Creates a buffer that keeps `owner` alive for as long as any view of it exists
+    pub fn from_owner<T>(owner: T) -> Buffer
+    where
+        T: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        let len = owner.as_ref().len();
+
+        Buffer {
+            owner: Arc::new(owner),
+            offset: 0,
+            len,
+        }
+    }
+
+    /// Number of bytes in the buffer
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the buffer has no data
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns a new buffer that shares the storage of this one for the given range.
+    ///
+    /// Panics if the range is out of bounds, the same way slicing a `[u8]` does.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Buffer {
+        let start = match range.start_bound() {
+            Bound::Included(&s) => s,
+            Bound::Excluded(&s) => s + 1,
+            Bound::Unbounded => 0,
+        };
+
+        let end = match range.end_bound() {
+            Bound::Included(&e) => e + 1,
+            Bound::Excluded(&e) => e,
+            Bound::Unbounded => self.len,
+        };
+
+        assert!(
+            start <= end && end <= self.len,
+            "range {}..{} out of bounds for buffer of length {}",
+            start,
+            end,
+            self.len
+        );
+
+        Buffer {
+            owner: self.owner.clone(),
+            offset: self.offset + start,
+            len: end - start,
+        }
+    }
+}
+
+impl Default for Buffer {
+    fn default() -> Buffer {
+        Buffer::new()
+    }
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &(*self.owner).as_ref()[self.offset..self.offset + self.len]
+    }
+}
+
+impl AsRef<[u8]> for Buffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for Buffer {
+    fn from(data: Vec<u8>) -> Buffer {
+        Buffer::from_owner(data)
+    }
+}
+
+impl From<Box<[u8]>> for Buffer {
+    fn from(data: Box<[u8]>) -> Buffer {
+        Buffer::from_owner(data)
+    }
+}
+
+impl From<&'static [u8]> for Buffer {
+    fn from(data: &'static [u8]) -> Buffer {
+        Buffer::from_static(data)
+    }
+}
+
+impl PartialEq for Buffer {
+    fn eq(&self, other: &Buffer) -> bool {
+        self[..] == other[..]
+    }
+}
+
+impl Eq for Buffer {}
+
+impl fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Buffer").field("len", &self.len).finish()
+    }
+}
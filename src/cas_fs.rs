@@ -0,0 +1,247 @@
+use crate::manifest::{to_hex, HashAlgorithm, Manifest, ManifestEntry};
+use crate::{
+    Buffer, DirEntry, EntryType, Evfs, InternalError, Metadata, MsgSender, RecvMsg, VfsDriver,
+    VfsError,
+};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Directory of the objects in a store
+const OBJECTS_DIR: &str = "objects";
+/// Extension of manifest files, which are stored in the root of the store
+const MANIFEST_EXT: &str = "manifest";
+
+/// Returns the file of an object in the store. Objects are spread over directories named after
+/// the first byte of their hash so no directory gets too large.
+fn object_path(objects: &Path, hash: &[u8]) -> PathBuf {
+    let hex = to_hex(hash);
+    objects.join(&hex[..2]).join(&hex[2..])
+}
+
+/// Driver for content-addressed stores where files are stored by their hash and a `Manifest`
+/// maps paths to hashes. Identical files are only stored once, so several versions of a game
+/// can share a store and only the files that changed take up more space.
+///
+/// A store is a directory with the objects in `objects/ab/cdef...` and the manifests of the
+/// versions next to it, such as `store/1.0.manifest`. Mounting a manifest mounts its version
+/// with `Evfs::mount`. Use `CasFs::open` for manifests that have been parsed with
+/// `Manifest::parse_signed` and `import` to add files to a store.
+///
+/// Objects are checked against their hash when they are loaded so a corrupted store fails
+/// with `InternalError::VerificationFailed`.
+pub struct CasFs {
+    objects: PathBuf,
+    manifest: Arc<Manifest>,
+    /// Files of the manifest together with their parent directories
+    index: BTreeMap<String, EntryType>,
+}
+
+impl CasFs {
+    pub fn new() -> CasFs {
+        CasFs {
+            objects: PathBuf::new(),
+            manifest: Arc::new(Manifest::new(HashAlgorithm::Sha256)),
+            index: BTreeMap::new(),
+        }
+    }
+
+    /// Creates a driver for the store at `store` with the files listed in `manifest`. Fails if
+    /// a hash in the manifest doesn't have the length of its hash algorithm.
+    pub fn open(store: impl AsRef<Path>, manifest: Manifest) -> Result<CasFs, VfsError> {
+        let mut index = BTreeMap::new();
+        let hash_len = manifest.algorithm().len();
+
+        for (path, entry) in manifest.iter() {
+            if entry.hash.len() != hash_len {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid hash for {} in the manifest", path),
+                )
+                .into());
+            }
+
+            let mut parent = path;
+            while let Some(end) = parent.rfind('/') {
+                parent = &parent[..end];
+                index.insert(parent.to_owned(), EntryType::Directory);
+            }
+
+            index.insert(path.to_owned(), EntryType::File);
+        }
+
+        Ok(CasFs {
+            objects: store.as_ref().join(OBJECTS_DIR),
+            manifest: Arc::new(manifest),
+            index,
+        })
+    }
+
+    fn find_file(&self, path: &str) -> Result<&ManifestEntry, InternalError> {
+        match self.manifest.get(path) {
+            Some(entry) => Ok(entry),
+            None if self.has_entry(path) == EntryType::Directory => {
+                Err(InternalError::NotFile { path: path.into() })
+            }
+            None => Err(InternalError::PathNotFound { path: path.into() }),
+        }
+    }
+}
+
+impl Default for CasFs {
+    fn default() -> CasFs {
+        CasFs::new()
+    }
+}
+
+/// Waits for a load started by `import`
+fn wait_for_load(vfs: &Evfs, path: &str) -> Result<Buffer, VfsError> {
+    let handle = vfs.load_file(path);
+
+    loop {
+        let msg = handle.recv.recv().map_err(|_| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "The load was dropped by evfs")
+        })?;
+
+        match msg {
+            RecvMsg::ReadDone(data) => return Ok(data),
+            RecvMsg::Error(e) => return Err(e),
+            _ => (),
+        }
+    }
+}
+
+/// Adds all files below `dir` in `vfs` to the store at `store` and returns a manifest of them
+/// with paths relative to `dir`. Objects that are already in the store aren't written again.
+/// The manifest has to be saved in the store (for example with `Manifest::to_bytes` as
+/// `store/1.0.manifest`) to be mounted.
+pub fn import(
+    vfs: &Evfs,
+    dir: &str,
+    store: impl AsRef<Path>,
+    algorithm: HashAlgorithm,
+) -> Result<Manifest, VfsError> {
+    let objects = store.as_ref().join(OBJECTS_DIR);
+    let mut manifest = Manifest::new(algorithm);
+
+    for entry in vfs.walk(dir).files_only().entries()? {
+        let data = wait_for_load(vfs, &entry.path)?;
+        let path = entry.path[dir.trim_end_matches('/').len()..].trim_start_matches('/');
+
+        manifest.add(path, &data);
+        let hash = &manifest.get(path).unwrap().hash;
+        let object = object_path(&objects, hash);
+
+        if object.exists() {
+            continue;
+        }
+
+        // Written to a temporary file first so an interrupted import can't leave a partial
+        // object behind
+        let parent = object.parent().unwrap();
+        std::fs::create_dir_all(parent)?;
+        let temp_object = object.with_extension("tmp");
+        std::fs::write(&temp_object, &data)?;
+        std::fs::rename(&temp_object, &object)?;
+    }
+
+    Ok(manifest)
+}
+
+impl VfsDriver for CasFs {
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        let metadata = std::fs::metadata(source)?;
+        let source = Path::new(source);
+
+        let is_manifest = source.extension().is_some_and(|ext| ext == MANIFEST_EXT);
+        let has_objects = source
+            .parent()
+            .is_some_and(|store| store.join(OBJECTS_DIR).is_dir());
+
+        if metadata.is_file() && is_manifest && has_objects {
+            Ok(())
+        } else {
+            Err(VfsError::UnsupportedMount {
+                mount: source.to_string_lossy().into_owned(),
+            })
+        }
+    }
+
+    fn new_from_path(&self, filename: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        let manifest = Manifest::parse(&std::fs::read(filename)?)?;
+        let store = Path::new(filename)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+
+        Ok(Box::new(CasFs::open(store, manifest)?))
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let entry = self.find_file(path)?;
+        let mut file = File::open(object_path(&self.objects, &entry.hash))?;
+
+        // Manifests mounted by path aren't signed, so the size in it is only compared with the
+        // object after it has been read
+        let len = file.metadata()?.len();
+        let data = send_msg.read_all(&mut file, Some(len))?;
+
+        self.manifest.verify(path, &data)?;
+        Ok(data)
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        match self.index.get(path.trim_matches('/')) {
+            _ if path.trim_matches('/').is_empty() => EntryType::Directory,
+            Some(&entry_type) => entry_type,
+            None => EntryType::NotFound,
+        }
+    }
+
+    ///
+    /// Sizes come from the manifest so the objects don't have to be read.
+    fn metadata(&self, path: &str) -> Result<Metadata, InternalError> {
+        let (entry_type, len) = match self.has_entry(path) {
+            EntryType::File => (EntryType::File, self.find_file(path)?.size),
+            EntryType::Directory => (EntryType::Directory, 0),
+            EntryType::NotFound => return Err(InternalError::PathNotFound { path: path.into() }),
+        };
+
+        Ok(Metadata {
+            entry_type,
+            len,
+            modified: None,
+        })
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        let prefix = match path.trim_matches('/') {
+            "" => String::new(),
+            dir => format!("{}/", dir),
+        };
+
+        Ok(self
+            .index
+            .range(prefix.clone()..)
+            .take_while(|(name, _)| name.starts_with(&prefix))
+            .filter(|(name, _)| !name[prefix.len()..].contains('/'))
+            .map(|(name, &entry_type)| DirEntry {
+                name: name[prefix.len()..].to_owned(),
+                entry_type,
+            })
+            .collect())
+    }
+
+    fn can_decompress(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    fn supports_file_ext(&self, _file_ext: &str) -> bool {
+        false
+    }
+}
@@ -0,0 +1,264 @@
+use crate::stream::{DecoderFactory, DecoderStream};
+use crate::{Buffer, DirEntry, EntryType, InternalError, MsgSender, ReadSeek, VfsDriver, VfsError};
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+use std::path::Path;
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    #[cfg(feature = "xz-fs")]
+    Xz,
+    #[cfg(feature = "bzip2-fs")]
+    Bzip2,
+}
+
+impl Format {
+    const ALL: &'static [Format] = &[
+        #[cfg(feature = "xz-fs")]
+        Format::Xz,
+        #[cfg(feature = "bzip2-fs")]
+        Format::Bzip2,
+    ];
+
+    fn magic(self) -> &'static [u8] {
+        match self {
+            #[cfg(feature = "xz-fs")]
+            Format::Xz => &[0xfd, b'7', b'z', b'X', b'Z', 0],
+            #[cfg(feature = "bzip2-fs")]
+            Format::Bzip2 => b"BZh",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            #[cfg(feature = "xz-fs")]
+            Format::Xz => "xz",
+            #[cfg(feature = "bzip2-fs")]
+            Format::Bzip2 => "bz2",
+        }
+    }
+
+    fn detect(data: &[u8]) -> Option<Format> {
+        Format::ALL
+            .iter()
+            .copied()
+            .find(|format| data.starts_with(format.magic()))
+    }
+
+    fn from_extension(file_ext: &str) -> Option<Format> {
+        Format::ALL
+            .iter()
+            .copied()
+            .find(|format| file_ext.eq_ignore_ascii_case(format.extension()))
+    }
+
+    fn decoder<'a, R: Read + Send + 'a>(self, reader: R) -> Box<dyn Read + Send + 'a> {
+        match self {
+            // Files made by `xz` and `pxz` can have several streams after each other
+            #[cfg(feature = "xz-fs")]
+            Format::Xz => Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)),
+            #[cfg(feature = "bzip2-fs")]
+            Format::Bzip2 => Box::new(bzip2::read::MultiBzDecoder::new(reader)),
+        }
+    }
+}
+
+/// Where the compressed data comes from
+enum Source {
+    /// Compressed file on disk. The uncompressed size isn't stored in the file so it's found by
+    /// decompressing it the first time it's needed.
+    File {
+        filename: String,
+        format: Format,
+        len: Mutex<Option<u64>>,
+    },
+    /// Uncompressed data. Used for files inside other mounts as those are already loaded.
+    Memory(Buffer),
+}
+
+/// Driver for single files compressed with xz or bzip2. The driver has one file that is named
+/// after the compressed file without its extension so `level.bin.xz` contains `level.bin`.
+/// When mounted directly the file is in the mount directory, and inside other mounts it's
+/// loaded as `data/level.bin.xz/level.bin`.
+pub struct CompressedFs {
+    name: String,
+    source: Source,
+}
+
+impl CompressedFs {
+    pub fn new() -> CompressedFs {
+        CompressedFs {
+            name: String::new(),
+            source: Source::Memory(Buffer::default()),
+        }
+    }
+
+    fn check_file(&self, path: &str) -> Result<(), InternalError> {
+        match self.has_entry(path) {
+            EntryType::File => Ok(()),
+            EntryType::Directory => Err(InternalError::NotFile { path: path.into() }),
+            EntryType::NotFound => Err(InternalError::PathNotFound { path: path.into() }),
+        }
+    }
+
+    /// Returns the uncompressed size of a file on disk, decompressing it once if needed
+    fn file_len(filename: &str, format: Format, len: &Mutex<Option<u64>>) -> io::Result<u64> {
+        let mut len = len.lock().unwrap();
+
+        if let Some(len) = *len {
+            return Ok(len);
+        }
+
+        let mut decoder = format.decoder(BufReader::new(File::open(filename)?));
+        let file_len = io::copy(&mut decoder, &mut io::sink())?;
+        *len = Some(file_len);
+
+        Ok(file_len)
+    }
+}
+
+impl Default for CompressedFs {
+    fn default() -> CompressedFs {
+        CompressedFs::new()
+    }
+}
+
+/// Name of the uncompressed file, which is the compressed file name without its extension
+fn uncompressed_name(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+impl VfsDriver for CompressedFs {
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        let metadata = std::fs::metadata(source)?;
+        let file_ext = Path::new(source)
+            .extension()
+            .map(|ext| ext.to_string_lossy())
+            .unwrap_or_default();
+
+        if metadata.is_file() && Format::from_extension(&file_ext).is_some() {
+            Ok(())
+        } else {
+            Err(VfsError::UnsupportedMount {
+                mount: source.into(),
+            })
+        }
+    }
+
+    fn new_from_path(&self, filename: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        let mut header = [0; 6];
+        let read = File::open(filename)?.read(&mut header)?;
+
+        let format = Format::detect(&header[..read]).ok_or_else(|| VfsError::UnsupportedMount {
+            mount: filename.into(),
+        })?;
+
+        Ok(Box::new(CompressedFs {
+            name: uncompressed_name(filename),
+            source: Source::File {
+                filename: filename.into(),
+                format,
+                len: Mutex::new(None),
+            },
+        }))
+    }
+
+    ///
+    /// The data is decompressed into memory as nested files are already loaded.
+    fn new_from_data(&self, path: &str, data: Buffer) -> Result<Box<dyn VfsDriver>, InternalError> {
+        let format = Format::detect(&data)
+            .ok_or_else(|| InternalError::DecompressorNotFound { path: path.into() })?;
+
+        let mut output_data = Vec::new();
+        format
+            .decoder(Cursor::new(data))
+            .read_to_end(&mut output_data)?;
+
+        Ok(Box::new(CompressedFs {
+            name: uncompressed_name(path),
+            source: Source::Memory(output_data.into()),
+        }))
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        self.check_file(path)?;
+
+        match &self.source {
+            Source::Memory(data) => {
+                send_msg.finish(data.len() as u64)?;
+                Ok(data.clone())
+            }
+            Source::File {
+                filename,
+                format,
+                len,
+            } => {
+                let mut decoder = format.decoder(BufReader::new(File::open(filename)?));
+                let data = send_msg.read_all(&mut decoder, *len.lock().unwrap())?;
+                *len.lock().unwrap() = Some(data.len() as u64);
+                Ok(data)
+            }
+        }
+    }
+
+    ///
+    /// Files on disk are decompressed while reading so seeking backwards is slow.
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        self.check_file(path)?;
+
+        match &self.source {
+            Source::Memory(data) => Ok(Box::new(Cursor::new(data.clone()))),
+            Source::File {
+                filename,
+                format,
+                len,
+            } => {
+                let file_len = Self::file_len(filename, *format, len)?;
+                let filename = filename.clone();
+                let format = *format;
+
+                let factory: DecoderFactory =
+                    Box::new(move || Ok(format.decoder(BufReader::new(File::open(&filename)?))));
+
+                Ok(Box::new(DecoderStream::new(factory, file_len)?))
+            }
+        }
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        if path.is_empty() {
+            EntryType::Directory
+        } else if !self.name.is_empty() && path == self.name {
+            EntryType::File
+        } else {
+            EntryType::NotFound
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        if !path.trim_end_matches('/').is_empty() {
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![DirEntry {
+            name: self.name.clone(),
+            entry_type: EntryType::File,
+        }])
+    }
+
+    fn can_decompress(&self, data: &[u8]) -> bool {
+        Format::detect(data).is_some()
+    }
+
+    fn supports_file_ext(&self, file_ext: &str) -> bool {
+        Format::from_extension(file_ext).is_some()
+    }
+}
@@ -0,0 +1,472 @@
+use crate::registry::rejection_reason;
+use crate::{
+    ArcDriver, Buffer, DirEntry, EntryType, Evfs, InternalError, Metadata, Mount, MsgSender,
+    ReadSeek, VfsDriver, VfsError,
+};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Format of a mount configuration
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Picks the format from the extension of `path`
+    pub fn from_path(path: &str) -> Option<ConfigFormat> {
+        let ext = Path::new(path).extension()?.to_string_lossy();
+
+        if ext.eq_ignore_ascii_case("toml") {
+            Some(ConfigFormat::Toml)
+        } else if ext.eq_ignore_ascii_case("json") {
+            Some(ConfigFormat::Json)
+        } else {
+            None
+        }
+    }
+}
+
+/// Error in a mount configuration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Line of the error, starting at 1
+    pub line: usize,
+    pub message: String,
+    /// Text of the line
+    pub context: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {}\n    {}",
+            self.line, self.message, self.context
+        )
+    }
+}
+
+pub(crate) fn format_errors(errors: &[ConfigError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlConfig {
+    #[serde(alias = "mounts", default)]
+    mount: Vec<toml::Spanned<toml::Value>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonConfig<'a> {
+    #[serde(borrow, alias = "mounts", default)]
+    mount: Vec<&'a RawValue>,
+}
+
+/// A mount in the configuration
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MountEntry {
+    target: String,
+    source: String,
+    /// Name of the driver to use instead of picking one from the source
+    driver: Option<String>,
+    /// Mounts with a higher priority are searched first when they overlay each other
+    #[serde(default)]
+    priority: i64,
+    #[serde(default)]
+    read_only: bool,
+    /// Operating systems (as in `std::env::consts::OS`) to mount on. All if missing.
+    platforms: Option<Vec<String>>,
+    #[serde(default)]
+    options: MountOptions,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MountOptions {
+    #[serde(default)]
+    case_insensitive: bool,
+    /// Headers sent with every request of the `http` driver
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// Password of encrypted entries for the `zip` driver
+    password: Option<String>,
+}
+
+/// Mount entries with their location in the text. Entries that can't be read are errors
+/// with the line of the error.
+type Entries = Vec<(Range<usize>, Result<MountEntry, (usize, String)>)>;
+
+/// Key of an entry that an error is about and the message
+type EntryError = (&'static str, String);
+
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// Finds the line where `key` is set in the entry at `span`
+fn key_line(text: &str, span: &Range<usize>, key: &str) -> Option<usize> {
+    let entry = &text[span.clone()];
+
+    entry.match_indices(key).find_map(|(start, _)| {
+        let before = entry[..start].chars().next_back();
+        let after = entry[start + key.len()..]
+            .trim_start_matches(['"', ' ', '\t'])
+            .chars()
+            .next();
+
+        let is_key = !before.is_some_and(|c| c.is_alphanumeric() || c == '_')
+            && matches!(after, Some('=') | Some(':'));
+
+        if is_key {
+            Some(line_of(text, span.start + start))
+        } else {
+            None
+        }
+    })
+}
+
+fn config_error(text: &str, line: usize, message: String) -> ConfigError {
+    ConfigError {
+        line,
+        message,
+        context: text.lines().nth(line - 1).unwrap_or("").trim().to_owned(),
+    }
+}
+
+/// Removes the position serde_json adds to its messages as it's reported separately
+fn json_message(e: &serde_json::Error) -> String {
+    let message = e.to_string();
+
+    match message.rfind(" at line ") {
+        Some(end) => message[..end].to_owned(),
+        None => message,
+    }
+}
+
+fn parse_toml(text: &str) -> Result<Entries, ConfigError> {
+    let config: TomlConfig = toml::from_str(text).map_err(|e| {
+        let line = e.span().map_or(1, |span| line_of(text, span.start));
+        config_error(text, line, e.message().to_owned())
+    })?;
+
+    Ok(config
+        .mount
+        .into_iter()
+        .map(|entry| {
+            let span = entry.span();
+            let entry = entry.into_inner().try_into::<MountEntry>().map_err(|e| {
+                // Values lose their location so errors about a key point at the key
+                let line = e
+                    .message()
+                    .split('`')
+                    .nth(1)
+                    .and_then(|key| key_line(text, &span, key))
+                    .unwrap_or_else(|| line_of(text, span.start));
+                (line, e.message().to_owned())
+            });
+            (span, entry)
+        })
+        .collect())
+}
+
+fn parse_json(text: &str) -> Result<Entries, ConfigError> {
+    let config: JsonConfig = serde_json::from_str(text)
+        .map_err(|e| config_error(text, e.line().max(1), json_message(&e)))?;
+
+    Ok(config
+        .mount
+        .into_iter()
+        .map(|entry| {
+            let entry = entry.get();
+            let start = entry.as_ptr() as usize - text.as_ptr() as usize;
+            let entry_result = serde_json::from_str(entry)
+                .map_err(|e| (line_of(text, start) + e.line().max(1) - 1, json_message(&e)));
+            (start..start + entry.len(), entry_result)
+        })
+        .collect())
+}
+
+/// Creates a driver for the options that have to be set when a driver is created. Returns
+/// `None` if the installed driver named `name` can be used as it is.
+fn driver_with_options(
+    name: &str,
+    options: &MountOptions,
+) -> Result<Option<ArcDriver>, EntryError> {
+    if !options.headers.is_empty() && name != "http" {
+        let message = "The `headers` option needs the `http` driver".into();
+        return Err(("headers", message));
+    }
+
+    if options.password.is_some() && name != "zip" {
+        let message = "The `password` option needs the `zip` driver".into();
+        return Err(("password", message));
+    }
+
+    #[cfg(feature = "zip-fs")]
+    {
+        if let Some(password) = &options.password {
+            let zip_fs = crate::ZipFs::new().password(password.as_bytes());
+            return Ok(Some(Arc::new(Box::new(zip_fs))));
+        }
+    }
+
+    #[cfg(feature = "http-fs")]
+    {
+        if !options.headers.is_empty() {
+            let http_fs = options
+                .headers
+                .iter()
+                .fold(crate::HttpFs::new(), |http, (name, value)| {
+                    http.header(name, value)
+                });
+            return Ok(Some(Arc::new(Box::new(http_fs))));
+        }
+    }
+
+    Ok(None)
+}
+
+fn source_error(source: &str, e: VfsError) -> EntryError {
+    let message = match e {
+        VfsError::UnknownDriver { .. } => return ("driver", e.to_string()),
+        VfsError::NoDriverSupport { .. } => e.to_string(),
+        e => format!("Unable to mount `{}`: {}", source, rejection_reason(e)),
+    };
+
+    ("source", message)
+}
+
+fn create_mount(
+    vfs: &Evfs,
+    entry: &MountEntry,
+    base_dir: Option<&Path>,
+) -> Result<Mount, EntryError> {
+    if !entry.target.starts_with('/') {
+        let message = format!("The target `{}` has to start with a /", entry.target);
+        return Err(("target", message));
+    }
+
+    // Relative sources in a configuration file are relative to the file
+    let source = match base_dir {
+        Some(dir) if !entry.source.contains("://") && Path::new(&entry.source).is_relative() => {
+            dir.join(&entry.source).to_string_lossy().into_owned()
+        }
+        _ => entry.source.clone(),
+    };
+
+    let (source, driver) = match &entry.driver {
+        Some(name) => match driver_with_options(name, &entry.options)? {
+            Some(driver) => Evfs::new_driver(&driver, &source),
+            None => vfs.create_named_driver(name, &source),
+        }
+        .map_err(|e| source_error(&source, e))?,
+        None if !entry.options.headers.is_empty() || entry.options.password.is_some() => {
+            let message = "The `headers` and `password` options need a `driver`".into();
+            return Err(("options", message));
+        }
+        None => vfs
+            .create_driver(&entry.target, &source)
+            .map_err(|e| source_error(&source, e))?,
+    };
+
+    let driver = if entry.read_only || entry.options.case_insensitive {
+        Box::new(OptionsFs {
+            inner: driver,
+            read_only: entry.read_only,
+            case_insensitive: entry.options.case_insensitive,
+        })
+    } else {
+        driver
+    };
+
+    Ok(Mount {
+        target: entry.target.clone(),
+        source,
+        driver: Arc::new(driver),
+    })
+}
+
+/// Creates the mounts described by `text`, sorted by priority. Nothing is mounted if there
+/// are any errors and all of them are returned.
+pub(crate) fn create_mounts(
+    vfs: &Evfs,
+    text: &str,
+    format: ConfigFormat,
+    base_dir: Option<&Path>,
+) -> Result<Vec<Mount>, VfsError> {
+    let entries = match format {
+        ConfigFormat::Toml => parse_toml(text),
+        ConfigFormat::Json => parse_json(text),
+    }
+    .map_err(|e| VfsError::InvalidConfig { errors: vec![e] })?;
+
+    let mut mounts = Vec::new();
+    let mut errors = Vec::new();
+
+    for (span, entry) in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err((line, message)) => {
+                errors.push(config_error(text, line, message));
+                continue;
+            }
+        };
+
+        let platforms = entry.platforms.as_deref().unwrap_or_default();
+        if !platforms.is_empty() && !platforms.iter().any(|p| p == std::env::consts::OS) {
+            continue;
+        }
+
+        match create_mount(vfs, &entry, base_dir) {
+            Ok(mount) => mounts.push((entry.priority, mount)),
+            Err((key, message)) => {
+                let line = key_line(text, &span, key).unwrap_or_else(|| line_of(text, span.start));
+                errors.push(config_error(text, line, message));
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(VfsError::InvalidConfig { errors });
+    }
+
+    // Mounts at the same location are searched in the order they are mounted
+    mounts.sort_by_key(|(priority, _)| Reverse(*priority));
+    Ok(mounts.into_iter().map(|(_, mount)| mount).collect())
+}
+
+/// Applies the options of a mount that the drivers don't handle themselves
+struct OptionsFs {
+    inner: Box<dyn VfsDriver>,
+    read_only: bool,
+    case_insensitive: bool,
+}
+
+impl OptionsFs {
+    /// Returns `path` with the case used by the driver if it's case-insensitive
+    fn resolve<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        if !self.case_insensitive || self.inner.has_entry(path) != EntryType::NotFound {
+            return Cow::Borrowed(path);
+        }
+
+        let mut resolved = String::new();
+
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            let entries = self.inner.read_dir(&resolved).unwrap_or_default();
+            let name = entries
+                .iter()
+                .find(|entry| entry.name.to_lowercase() == name.to_lowercase())
+                .map_or(name, |entry| entry.name.as_str());
+
+            if !resolved.is_empty() {
+                resolved.push('/');
+            }
+            resolved.push_str(name);
+        }
+
+        Cow::Owned(resolved)
+    }
+
+    fn check_writable(&self, path: &str) -> Result<(), InternalError> {
+        if self.read_only {
+            Err(InternalError::ReadOnly { path: path.into() })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl VfsDriver for OptionsFs {
+    fn is_remote(&self) -> bool {
+        self.inner.is_remote()
+    }
+
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        Err(VfsError::UnsupportedMount {
+            mount: source.into(),
+        })
+    }
+
+    fn new_from_path(&self, path: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        Err(VfsError::UnsupportedMount { mount: path.into() })
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        self.inner.load_file(&self.resolve(path), send_msg)
+    }
+
+    fn map_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        self.inner.map_file(&self.resolve(path), send_msg)
+    }
+
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        self.inner.open(&self.resolve(path))
+    }
+
+    fn load_range(
+        &self,
+        path: &str,
+        offset: u64,
+        len: u64,
+        send_msg: &MsgSender,
+    ) -> Result<Buffer, InternalError> {
+        self.inner
+            .load_range(&self.resolve(path), offset, len, send_msg)
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        self.inner.has_entry(&self.resolve(path))
+    }
+
+    fn metadata(&self, path: &str) -> Result<Metadata, InternalError> {
+        self.inner.metadata(&self.resolve(path))
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        self.inner.read_dir(&self.resolve(path))
+    }
+
+    fn can_decompress(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    fn supports_file_ext(&self, _file_ext: &str) -> bool {
+        false
+    }
+
+    fn write_file(&self, path: &str, data: Buffer) -> Result<(), InternalError> {
+        self.check_writable(path)?;
+        self.inner.write_file(&self.resolve(path), data)
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), InternalError> {
+        self.check_writable(path)?;
+        self.inner.create_dir(&self.resolve(path))
+    }
+
+    fn remove(&self, path: &str) -> Result<(), InternalError> {
+        self.check_writable(path)?;
+        self.inner.remove(&self.resolve(path))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), InternalError> {
+        self.check_writable(to)?;
+        self.inner.rename(&self.resolve(from), to)
+    }
+}
@@ -0,0 +1,318 @@
+//! Files compiled into the executable.
+//!
+//! A directory is embedded by calling `EmbedBuilder` from a build script, which generates a
+//! table of `include_bytes!` entries:
+//!
+//! ```text
+//! // build.rs
+//! fn main() {
+//!     let out_dir = std::env::var("OUT_DIR").unwrap();
+//!     evfs::embedded_fs::EmbedBuilder::new("assets")
+//!         .compress(true)
+//!         .write(std::path::Path::new(&out_dir).join("assets.rs"))
+//!         .unwrap();
+//! }
+//!
+//! // main.rs
+//! include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+//!
+//! vfs.install_driver(Arc::new(Box::new(EmbeddedFs::new(&EMBEDDED_FILES))));
+//! vfs.mount("/builtin", "embedded://")?;
+//! ```
+//!
+//! In debug builds the mount reads from the original directory instead (if it still exists) so
+//! assets can be changed without rebuilding. Paths are the same in both cases.
+
+use crate::stream::{DecoderFactory, DecoderStream};
+use crate::{Buffer, DirEntry, EntryType, InternalError, MsgSender, ReadSeek, VfsDriver, VfsError};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Cursor, Write};
+use std::path::{Path, PathBuf};
+
+/// Source used to mount the default embedded directory
+pub const EMBEDDED_SCHEME: &str = "embedded://";
+
+/// A file in an `EmbeddedDir`
+pub struct EmbeddedFile {
+    /// Path relative to the embedded directory using `/` as separator
+    pub path: &'static str,
+    pub data: &'static [u8],
+    /// The data is compressed with deflate
+    pub compressed: bool,
+    /// Uncompressed size of the file
+    pub len: u64,
+}
+
+/// Table of files generated by `EmbedBuilder`
+pub struct EmbeddedDir {
+    /// The directory the files were embedded from
+    pub root: &'static str,
+    /// Files sorted by path
+    pub files: &'static [EmbeddedFile],
+}
+
+/// Driver for files that are compiled into the executable. Uncompressed files are returned
+/// without copying.
+#[derive(Clone)]
+pub struct EmbeddedFs {
+    dir: &'static EmbeddedDir,
+    source: String,
+    prefer_disk: bool,
+}
+
+impl EmbeddedFs {
+    /// Creates a driver that is mounted with the source `embedded://`
+    pub fn new(dir: &'static EmbeddedDir) -> EmbeddedFs {
+        EmbeddedFs::with_name("", dir)
+    }
+
+    /// Creates a driver that is mounted with the source `embedded://<name>`. This is used when
+    /// several directories are embedded.
+    pub fn with_name(name: &str, dir: &'static EmbeddedDir) -> EmbeddedFs {
+        EmbeddedFs {
+            dir,
+            source: format!("{}{}", EMBEDDED_SCHEME, name),
+            prefer_disk: cfg!(debug_assertions),
+        }
+    }
+
+    /// Mount the directory the files were embedded from instead when it exists. This defaults
+    /// to true in debug builds. Requires the `local-fs` feature.
+    pub fn prefer_disk(mut self, prefer_disk: bool) -> EmbeddedFs {
+        self.prefer_disk = prefer_disk;
+        self
+    }
+
+    fn find_file(&self, path: &str) -> Result<&'static EmbeddedFile, InternalError> {
+        let files = self.dir.files;
+
+        match files.binary_search_by(|file| file.path.cmp(path)) {
+            Ok(index) => Ok(&files[index]),
+            Err(_) if self.has_entry(path) == EntryType::Directory => {
+                Err(InternalError::NotFile { path: path.into() })
+            }
+            Err(_) => Err(InternalError::PathNotFound { path: path.into() }),
+        }
+    }
+}
+
+fn dir_prefix(path: &str) -> String {
+    match path.trim_end_matches('/') {
+        "" => String::new(),
+        dir => format!("{}/", dir),
+    }
+}
+
+impl VfsDriver for EmbeddedFs {
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        if source == self.source {
+            Ok(())
+        } else {
+            Err(VfsError::UnsupportedMount {
+                mount: source.into(),
+            })
+        }
+    }
+
+    fn new_from_path(&self, _path: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        #[cfg(feature = "local-fs")]
+        {
+            if self.prefer_disk && Path::new(self.dir.root).is_dir() {
+                return crate::LocalFs::new().new_from_path(self.dir.root);
+            }
+        }
+
+        Ok(Box::new(self.clone()))
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let file = self.find_file(path)?;
+
+        if file.compressed {
+            let mut decoder = flate2::read::DeflateDecoder::new(file.data);
+            send_msg.read_all(&mut decoder, Some(file.len))
+        } else {
+            send_msg.finish(file.len)?;
+            Ok(Buffer::from_static(file.data))
+        }
+    }
+
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        let file = self.find_file(path)?;
+
+        if file.compressed {
+            let factory: DecoderFactory =
+                Box::new(move || Ok(Box::new(flate2::read::DeflateDecoder::new(file.data))));
+            Ok(Box::new(DecoderStream::new(factory, file.len)?))
+        } else {
+            Ok(Box::new(Cursor::new(file.data)))
+        }
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        let prefix = dir_prefix(path);
+
+        if self
+            .dir
+            .files
+            .binary_search_by(|f| f.path.cmp(path))
+            .is_ok()
+        {
+            EntryType::File
+        } else if prefix.is_empty() || self.dir.files.iter().any(|f| f.path.starts_with(&prefix)) {
+            EntryType::Directory
+        } else {
+            EntryType::NotFound
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        let prefix = dir_prefix(path);
+        let mut entries = BTreeMap::new();
+
+        for file in self.dir.files {
+            let rest = match file.path.strip_prefix(&prefix) {
+                Some(rest) => rest,
+                None => continue,
+            };
+
+            match rest.find('/') {
+                Some(end) => entries.insert(&rest[..end], EntryType::Directory),
+                None => entries.insert(rest, EntryType::File),
+            };
+        }
+
+        Ok(entries
+            .into_iter()
+            .map(|(name, entry_type)| DirEntry {
+                name: name.to_owned(),
+                entry_type,
+            })
+            .collect())
+    }
+
+    fn can_decompress(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    fn supports_file_ext(&self, _file_ext: &str) -> bool {
+        false
+    }
+}
+
+/// Generates the Rust source for embedding a directory. Meant to be used from build scripts.
+pub struct EmbedBuilder {
+    dir: PathBuf,
+    name: String,
+    compress: bool,
+}
+
+impl EmbedBuilder {
+    /// Embeds all files in `dir` and its sub directories
+    pub fn new(dir: impl AsRef<Path>) -> EmbedBuilder {
+        EmbedBuilder {
+            dir: dir.as_ref().to_owned(),
+            name: "EMBEDDED_FILES".to_owned(),
+            compress: false,
+        }
+    }
+
+    /// Name of the generated `EmbeddedDir` static. Defaults to `EMBEDDED_FILES`.
+    pub fn name(mut self, name: &str) -> EmbedBuilder {
+        self.name = name.to_owned();
+        self
+    }
+
+    /// Compress the files with deflate. Files that don't get smaller are stored as they are.
+    pub fn compress(mut self, compress: bool) -> EmbedBuilder {
+        self.compress = compress;
+        self
+    }
+
+    /// Writes the generated source to `output`. Compressed files are written next to it.
+    /// Also tells cargo to rerun the build script when the directory changes.
+    pub fn write(self, output: impl AsRef<Path>) -> io::Result<()> {
+        let output = output.as_ref();
+        let root = std::fs::canonicalize(&self.dir)?;
+        let data_dir = output.with_extension("data");
+
+        let mut files = Vec::new();
+        collect_files(&root, &root, &mut files)?;
+        files.sort();
+
+        let mut source = String::new();
+        let _ = writeln!(
+            source,
+            "// Generated by evfs::embedded_fs::EmbedBuilder. Do not edit."
+        );
+        let _ = writeln!(
+            source,
+            "pub static {}: ::evfs::embedded_fs::EmbeddedDir = ::evfs::embedded_fs::EmbeddedDir {{",
+            self.name
+        );
+        let _ = writeln!(source, "    root: {:?},", root.to_string_lossy());
+        let _ = writeln!(source, "    files: &[");
+
+        for (index, path) in files.iter().enumerate() {
+            let full_path = root.join(path);
+            let data = std::fs::read(&full_path)?;
+            let mut include_path = full_path;
+            let mut compressed = false;
+
+            if self.compress {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(&data)?;
+                let packed = encoder.finish()?;
+
+                if packed.len() < data.len() {
+                    std::fs::create_dir_all(&data_dir)?;
+                    include_path = data_dir.join(format!("{}.deflate", index));
+                    std::fs::write(&include_path, packed)?;
+                    compressed = true;
+                }
+            }
+
+            let _ = writeln!(
+                source,
+                "        ::evfs::embedded_fs::EmbeddedFile {{ path: {:?}, data: include_bytes!({:?}), compressed: {}, len: {} }},",
+                path,
+                include_path.to_string_lossy(),
+                compressed,
+                data.len()
+            );
+        }
+
+        let _ = writeln!(source, "    ],");
+        let _ = writeln!(source, "}};");
+
+        std::fs::write(output, source)?;
+        println!("cargo:rerun-if-changed={}", root.display());
+
+        Ok(())
+    }
+}
+
+/// Adds the paths of all files below `dir` relative to `root` using `/` as separator
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            let components: Vec<_> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push(components.join("/"));
+        }
+    }
+
+    Ok(())
+}
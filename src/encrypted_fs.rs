@@ -0,0 +1,317 @@
+use crate::{
+    Buffer, DirEntry, EntryType, InternalError, Metadata, MsgSender, RecvMsg, VfsDriver, VfsError,
+};
+use aes_gcm::aead::{self, Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{AeadCore, Aes256Gcm};
+use chacha20poly1305::ChaCha20Poly1305;
+use crossbeam_channel::unbounded;
+use std::io;
+use std::sync::Arc;
+
+const MAGIC: &[u8] = b"EVFE";
+const VERSION: u8 = 1;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// Magic, version, cipher and key id length followed by the key id and the nonce
+const MAX_HEADER_LEN: usize = MAGIC.len() + 3 + 255 + NONCE_LEN;
+
+/// 256-bit key used by both ciphers
+pub type Key = [u8; 32];
+
+/// Authenticated cipher used to encrypt a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn id(self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => 1,
+            Cipher::ChaCha20Poly1305 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Cipher> {
+        match id {
+            1 => Some(Cipher::Aes256Gcm),
+            2 => Some(Cipher::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    fn encrypt(self, key: &Key, nonce: &[u8], payload: Payload) -> Result<Vec<u8>, aead::Error> {
+        match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).encrypt(nonce.into(), payload),
+            Cipher::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new(key.into()).encrypt(nonce.into(), payload)
+            }
+        }
+    }
+
+    fn decrypt(self, key: &Key, nonce: &[u8], payload: Payload) -> Result<Vec<u8>, aead::Error> {
+        match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).decrypt(nonce.into(), payload),
+            Cipher::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new(key.into()).decrypt(nonce.into(), payload)
+            }
+        }
+    }
+}
+
+/// Supplies the keys used to decrypt files. Files store the id of the key they were encrypted
+/// with so keys can be rotated without re-encrypting everything. Closures taking the key id
+/// and the path of the file implement this.
+pub trait KeyProvider: Send + Sync {
+    /// Returns the key with the id `key_id` for the file `path`, or `None` if it isn't known
+    fn key(&self, key_id: &str, path: &str) -> Option<Key>;
+}
+
+impl<F> KeyProvider for F
+where
+    F: Fn(&str, &str) -> Option<Key> + Send + Sync,
+{
+    fn key(&self, key_id: &str, path: &str) -> Option<Key> {
+        self(key_id, path)
+    }
+}
+
+/// Header at the start of an encrypted file. The whole header is authenticated together with
+/// the data so the cipher or key id can't be changed without decryption failing.
+struct Header<'a> {
+    cipher: Cipher,
+    key_id: &'a str,
+    nonce: &'a [u8],
+    len: usize,
+}
+
+impl<'a> Header<'a> {
+    fn parse(path: &str, data: &'a [u8]) -> Result<Header<'a>, InternalError> {
+        let not_encrypted = || InternalError::AuthenticationFailed { path: path.into() };
+
+        if !data.starts_with(MAGIC) || data.len() < MAGIC.len() + 3 {
+            return Err(not_encrypted());
+        }
+
+        let version = data[MAGIC.len()];
+        let cipher = Cipher::from_id(data[MAGIC.len() + 1]);
+        let key_id_len = data[MAGIC.len() + 2] as usize;
+
+        let cipher = match cipher {
+            Some(cipher) if version == VERSION => cipher,
+            _ => return Err(InternalError::UnsupportedEncryption { path: path.into() }),
+        };
+
+        let key_id_start = MAGIC.len() + 3;
+        let nonce_start = key_id_start + key_id_len;
+        let len = nonce_start + NONCE_LEN;
+
+        if data.len() < len {
+            return Err(not_encrypted());
+        }
+
+        let key_id =
+            std::str::from_utf8(&data[key_id_start..nonce_start]).map_err(|_| not_encrypted())?;
+
+        Ok(Header {
+            cipher,
+            key_id,
+            nonce: &data[nonce_start..len],
+            len,
+        })
+    }
+}
+
+/// Encrypts `data` with `cipher` and `key`. The returned data starts with a header that has
+/// `key_id` so the key can be found again when decrypting. This is used by `EncryptedFs` when
+/// writing and can be used by tools that encrypt assets before they are packaged.
+pub fn encrypt(cipher: Cipher, key_id: &str, key: &Key, data: &[u8]) -> io::Result<Vec<u8>> {
+    if key_id.len() > 255 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The key id `{}` is longer than 255 bytes", key_id),
+        ));
+    }
+
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let mut output = Vec::with_capacity(MAX_HEADER_LEN + data.len() + TAG_LEN);
+    output.extend_from_slice(MAGIC);
+    output.extend_from_slice(&[VERSION, cipher.id(), key_id.len() as u8]);
+    output.extend_from_slice(key_id.as_bytes());
+    output.extend_from_slice(&nonce);
+
+    let payload = Payload {
+        msg: data,
+        aad: &output,
+    };
+
+    let encrypted = cipher
+        .encrypt(key, &nonce, payload)
+        .map_err(|_| io::Error::other("Unable to encrypt data"))?;
+
+    output.extend_from_slice(&encrypted);
+    Ok(output)
+}
+
+/// Decrypts data created by `encrypt`. `path` is only used for errors.
+pub fn decrypt(path: &str, data: &[u8], keys: &dyn KeyProvider) -> Result<Vec<u8>, InternalError> {
+    let header = Header::parse(path, data)?;
+
+    let key = keys
+        .key(header.key_id, path)
+        .ok_or_else(|| InternalError::KeyNotFound {
+            path: path.into(),
+            key_id: header.key_id.into(),
+        })?;
+
+    let payload = Payload {
+        msg: &data[header.len..],
+        aad: &data[..header.len],
+    };
+
+    header
+        .cipher
+        .decrypt(&key, header.nonce, payload)
+        .map_err(|_| InternalError::AuthenticationFailed { path: path.into() })
+}
+
+/// Wraps another driver and decrypts the files it loads, so assets can be encrypted at rest in
+/// any container such as a directory, a zip archive or an HTTP server. Each file is encrypted on
+/// its own with AES-256-GCM or ChaCha20-Poly1305 and decrypted in memory when it's loaded.
+/// Files that have been modified or that aren't encrypted fail with
+/// `InternalError::AuthenticationFailed`.
+///
+/// Archives inside the mount are decrypted before they are opened. Directory listings come from
+/// the wrapped driver as names aren't encrypted.
+///
+/// Only the header and the data are authenticated, not the path of the file. Files encrypted
+/// with the same key can be renamed or swapped with each other without decryption failing, so
+/// use a key per file (the path is passed to the `KeyProvider`) if that matters.
+///
+/// The mount is read-only unless a key for new files is set with `encrypt_with`, in which case
+/// written files are encrypted before they are passed on to the wrapped driver.
+pub struct EncryptedFs {
+    inner: Box<dyn VfsDriver>,
+    keys: Arc<dyn KeyProvider>,
+    /// Key id and cipher used when writing
+    write_key: Option<(String, Cipher)>,
+}
+
+impl EncryptedFs {
+    pub fn new(inner: Box<dyn VfsDriver>, keys: Arc<dyn KeyProvider>) -> EncryptedFs {
+        EncryptedFs {
+            inner,
+            keys,
+            write_key: None,
+        }
+    }
+
+    /// Encrypts written files with `cipher` and the key with the id `key_id`
+    pub fn encrypt_with(mut self, key_id: &str, cipher: Cipher) -> EncryptedFs {
+        self.write_key = Some((key_id.into(), cipher));
+        self
+    }
+
+    /// Reads the header of an encrypted file and returns its size
+    fn header_len(&self, path: &str) -> Result<usize, InternalError> {
+        let (send_msg, _recv_msg) = unbounded::<RecvMsg>();
+        let msg = MsgSender::new(send_msg, u64::MAX).without_progress();
+        let data = self
+            .inner
+            .load_range(path, 0, MAX_HEADER_LEN as u64, &msg)?;
+
+        Ok(Header::parse(path, &data)?.len)
+    }
+}
+
+impl VfsDriver for EncryptedFs {
+    fn is_remote(&self) -> bool {
+        self.inner.is_remote()
+    }
+
+    /// Encrypted mounts are only created with `Evfs::mount_encrypted` or `Evfs::mount_driver`
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        Err(VfsError::UnsupportedMount {
+            mount: source.into(),
+        })
+    }
+
+    fn new_from_path(&self, path: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        Err(VfsError::UnsupportedMount { mount: path.into() })
+    }
+
+    ///
+    /// Progress is reported in bytes of the encrypted file, which is a bit larger than the
+    /// returned data.
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let encrypted = self.inner.load_file(path, send_msg)?;
+        let data = decrypt(path, &encrypted, &*self.keys)?;
+
+        send_msg.finish(encrypted.len() as u64)?;
+        Ok(data.into())
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        self.inner.has_entry(path)
+    }
+
+    ///
+    /// The size is calculated from the size of the encrypted file and its header.
+    fn metadata(&self, path: &str) -> Result<Metadata, InternalError> {
+        let mut metadata = self.inner.metadata(path)?;
+
+        if metadata.entry_type == EntryType::File {
+            let overhead = (self.header_len(path)? + TAG_LEN) as u64;
+            metadata.len = metadata
+                .len
+                .checked_sub(overhead)
+                .ok_or_else(|| InternalError::AuthenticationFailed { path: path.into() })?;
+        }
+
+        Ok(metadata)
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        self.inner.read_dir(path)
+    }
+
+    fn can_decompress(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    fn supports_file_ext(&self, _file_ext: &str) -> bool {
+        false
+    }
+
+    fn write_file(&self, path: &str, data: Buffer) -> Result<(), InternalError> {
+        let (key_id, cipher) = self
+            .write_key
+            .as_ref()
+            .ok_or_else(|| InternalError::ReadOnly { path: path.into() })?;
+
+        let key = self
+            .keys
+            .key(key_id, path)
+            .ok_or_else(|| InternalError::KeyNotFound {
+                path: path.into(),
+                key_id: key_id.clone(),
+            })?;
+
+        let data = encrypt(*cipher, key_id, &key, &data)?;
+        self.inner.write_file(path, data.into())
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), InternalError> {
+        self.inner.create_dir(path)
+    }
+
+    fn remove(&self, path: &str) -> Result<(), InternalError> {
+        self.inner.remove(path)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), InternalError> {
+        self.inner.rename(from, to)
+    }
+}
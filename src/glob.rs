@@ -0,0 +1,108 @@
+const WILDCARDS: [char; 3] = ['*', '?', '['];
+
+/// Matches a virtual path against a glob pattern.
+///
+/// * `*` matches any number of characters except `/`
+/// * `**` matches any number of characters including `/`. `/**/` also matches a single `/`
+///   so `/assets/**/*.png` finds png files directly in `/assets` as well.
+/// * `?` matches a single character except `/`
+/// * `[abc]`, `[a-z]` and `[!abc]` match a single character in (or not in) the set
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+
+    match_from(&pattern, &path)
+}
+
+/// Returns true if the pattern contains any wildcard characters
+pub fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(WILDCARDS)
+}
+
+/// Returns the directory part of the pattern that comes before any wildcards. All paths that
+/// can match the pattern are inside this directory.
+pub fn literal_prefix(pattern: &str) -> &str {
+    let end = match pattern.find(WILDCARDS) {
+        Some(end) => end,
+        None => return pattern,
+    };
+
+    match pattern[..end].rfind('/') {
+        Some(0) => "/",
+        Some(slash) => &pattern[..slash],
+        None => "",
+    }
+}
+
+fn match_from(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+
+            // `**/` can match no directories at all
+            if rest.first() == Some(&'/') && match_from(&rest[1..], path) {
+                return true;
+            }
+
+            (0..=path.len()).any(|i| match_from(rest, &path[i..]))
+        }
+
+        Some('*') => {
+            let rest = &pattern[1..];
+
+            for i in 0..=path.len() {
+                if match_from(rest, &path[i..]) {
+                    return true;
+                }
+
+                if i < path.len() && path[i] == '/' {
+                    break;
+                }
+            }
+
+            false
+        }
+
+        Some('?') => !path.is_empty() && path[0] != '/' && match_from(&pattern[1..], &path[1..]),
+
+        Some('[') => match match_class(&pattern[1..], path.first()) {
+            Some((matched, len)) => matched && match_from(&pattern[len + 1..], &path[1..]),
+            // No closing `]` so treat `[` as a regular character
+            None => path.first() == Some(&'[') && match_from(&pattern[1..], &path[1..]),
+        },
+
+        Some(c) => path.first() == Some(c) && match_from(&pattern[1..], &path[1..]),
+    }
+}
+
+/// Matches a character class. `class` starts after the `[`. Returns if the character matched
+/// together with the length of the class including the closing `]`.
+fn match_class(class: &[char], c: Option<&char>) -> Option<(bool, usize)> {
+    let end = class.iter().skip(1).position(|&c| c == ']')? + 1;
+    let (negate, set) = match class[0] {
+        '!' | '^' => (true, &class[1..end]),
+        _ => (false, &class[..end]),
+    };
+
+    let c = match c {
+        Some(&c) if c != '/' => c,
+        _ => return Some((false, end + 1)),
+    };
+
+    let mut found = false;
+    let mut i = 0;
+
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == '-' {
+            found |= set[i] <= c && c <= set[i + 2];
+            i += 3;
+        } else {
+            found |= set[i] == c;
+            i += 1;
+        }
+    }
+
+    Some((found != negate, end + 1))
+}
@@ -1,13 +1,69 @@
-use crate::{EntryType, InternalError, RecvMsg, VfsDriver, VfsError};
+use crate::stream::seek_position;
+use crate::{Buffer, EntryType, InternalError, MsgSender, ReadSeek, VfsDriver, VfsError};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::{CONTENT_LENGTH, RANGE};
+use reqwest::StatusCode;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::path::Path;
 
+/// Number of bytes fetched for each range request when streaming
+const READ_AHEAD: u64 = 256 * 1024;
+
+fn http_error(e: reqwest::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// Headers sent with every request, such as authorization tokens
+type Headers = Vec<(String, String)>;
+
+fn with_headers(request: RequestBuilder, headers: &Headers) -> RequestBuilder {
+    headers.iter().fold(request, |request, (name, value)| {
+        request.header(name, value)
+    })
+}
+
 pub struct HttpFs {
     url: String,
+    headers: Headers,
 }
 
 impl HttpFs {
     pub fn new() -> HttpFs {
-        HttpFs { url: String::new() }
+        HttpFs {
+            url: String::new(),
+            headers: Headers::new(),
+        }
+    }
+
+    /// Creates a driver for the server at `url`. This is used to mount a server with its own
+    /// headers using `Evfs::mount_driver`.
+    pub fn open(url: &str) -> HttpFs {
+        HttpFs {
+            url: url.into(),
+            headers: Headers::new(),
+        }
+    }
+
+    /// Sends the header `name` with every request. Mounts created by this driver inherit the
+    /// headers.
+    pub fn header(mut self, name: &str, value: &str) -> HttpFs {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+impl HttpFs {
+    fn file_url(&self, path: &str) -> String {
+        Path::new(&self.url)
+            .join(path)
+            .to_string_lossy()
+            .to_string()
+    }
+}
+
+impl Default for HttpFs {
+    fn default() -> HttpFs {
+        HttpFs::new()
     }
 }
 
@@ -28,33 +84,99 @@ impl VfsDriver for HttpFs {
     }
 
     fn new_from_path(&self, url: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
-        Ok(Box::new(HttpFs { url: url.into() }))
+        Ok(Box::new(HttpFs {
+            url: url.into(),
+            headers: self.headers.clone(),
+        }))
     }
 
     ///
-    /// Read a file from the local filesystem.
-    /// TODO: Make the 5 meg size configurable
-    fn load_file(
+    /// Download a file from the server
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let p = self.file_url(path);
+
+        let mut response = with_headers(Client::new().get(&p), &self.headers)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .map_err(http_error)?;
+
+        let len = response.content_length();
+
+        send_msg.read_all(&mut response, len)
+    }
+
+    ///
+    /// Streams the file using HTTP range requests. The size of the file is fetched up front
+    /// with a HEAD request so seeking relative to the end works.
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        let url = self.file_url(path);
+        let client = Client::new();
+        let response = with_headers(client.head(&url), &self.headers)
+            .send()
+            .and_then(|r| r.error_for_status())
+            .map_err(http_error)?;
+
+        let len = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| io::Error::other(format!("No Content-Length returned for {}", url)))?;
+
+        Ok(Box::new(HttpStream {
+            client,
+            url,
+            headers: self.headers.clone(),
+            len,
+            pos: 0,
+            cache: Buffer::new(),
+            cache_start: 0,
+        }))
+    }
+
+    ///
+    /// Fetches only the requested range from the server
+    fn load_range(
         &self,
         path: &str,
-        send_msg: &crossbeam_channel::Sender<RecvMsg>,
-    ) -> Result<Box<[u8]>, InternalError> {
-        let path = Path::new(&self.url).join(path);
-        let t = path.to_string_lossy();
-        let p = t.to_string();
+        offset: u64,
+        len: u64,
+        send_msg: &MsgSender,
+    ) -> Result<Buffer, InternalError> {
+        if len == 0 {
+            return Ok(Buffer::new());
+        }
 
-        // TODO: Proper progress
-        send_msg.send(RecvMsg::ReadProgress(0.0))?;
-        let bytes = reqwest::blocking::get(&p).unwrap().bytes().unwrap();
-        send_msg.send(RecvMsg::ReadProgress(1.0))?;
+        let url = self.file_url(path);
 
-        // TODO: better way to do this?
-        let mut data = vec![0; bytes.len()];
-        data.copy_from_slice(bytes.as_ref());
+        send_msg.progress(0, Some(len))?;
 
-        //send_msg.send(RecvMsg::ReadDone(output_data.into_boxed_slice()))?;
+        let response = with_headers(Client::new().get(&url), &self.headers)
+            .header(
+                RANGE,
+                format!("bytes={}-{}", offset, offset.saturating_add(len - 1)),
+            )
+            .send()
+            .map_err(http_error)?;
 
-        Ok(data.into_boxed_slice())
+        // A range starting past the end of the file is an empty result and not an error
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(Buffer::new());
+        }
+
+        let response = response.error_for_status().map_err(http_error)?;
+
+        let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+        let data = Buffer::from_owner(response.bytes().map_err(http_error)?);
+
+        if partial {
+            Ok(data)
+        } else {
+            // Server doesn't support ranges and sent the whole file
+            let start = usize::min(offset as usize, data.len());
+            let end = usize::min(start.saturating_add(len as usize), data.len());
+            Ok(data.slice(start..end))
+        }
     }
 
     /// This is used to figure out if a certain mount can be done
@@ -85,3 +207,69 @@ impl VfsDriver for HttpFs {
         file_ext == "zip"
     }
 }
+
+/// Reads a remote file in `READ_AHEAD` sized blocks using range requests
+struct HttpStream {
+    client: Client,
+    url: String,
+    headers: Headers,
+    len: u64,
+    pos: u64,
+    /// The last fetched block which starts at `cache_start` in the file
+    cache: Buffer,
+    cache_start: u64,
+}
+
+impl HttpStream {
+    fn fetch(&mut self) -> io::Result<()> {
+        let end = u64::min(self.pos.saturating_add(READ_AHEAD), self.len) - 1;
+
+        let response = with_headers(self.client.get(&self.url), &self.headers)
+            .header(RANGE, format!("bytes={}-{}", self.pos, end))
+            .send()
+            .and_then(|r| r.error_for_status())
+            .map_err(http_error)?;
+
+        // Servers that don't support ranges reply with the whole file
+        let cache_start = if response.status() == StatusCode::PARTIAL_CONTENT {
+            self.pos
+        } else {
+            0
+        };
+
+        self.cache = Buffer::from_owner(response.bytes().map_err(http_error)?);
+        self.cache_start = cache_start;
+
+        Ok(())
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let cache_end = self.cache_start + self.cache.len() as u64;
+
+        if self.pos < self.cache_start || self.pos >= cache_end {
+            self.fetch()?;
+        }
+
+        let offset = (self.pos - self.cache_start) as usize;
+        let available = &self.cache[offset.min(self.cache.len())..];
+        let count = available.len().min(buf.len());
+
+        buf[..count].copy_from_slice(&available[..count]);
+        self.pos += count as u64;
+
+        Ok(count)
+    }
+}
+
+impl Seek for HttpStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_position(pos, self.pos, self.len)?;
+        Ok(self.pos)
+    }
+}
@@ -1,4 +1,3 @@
-use crossbeam_channel;
 use crossbeam_channel::unbounded;
 use log::*;
 use thiserror::Error;
@@ -9,14 +8,58 @@ use std::sync::Arc;
 use std::thread;
 
 //mod error;
+#[cfg(any(feature = "quake-pak-fs", feature = "wad-fs"))]
+mod archive;
+mod batch;
+mod buffer;
+mod glob;
+mod progress;
+mod registry;
+#[cfg(any(
+    feature = "zip-fs",
+    feature = "http-fs",
+    feature = "tar-fs",
+    feature = "embedded-fs",
+    feature = "pak-fs",
+    feature = "quake-pak-fs",
+    feature = "wad-fs",
+    feature = "xz-fs",
+    feature = "bzip2-fs"
+))]
+mod stream;
+mod sub_dir_fs;
 mod vfs_driver;
-
+mod view;
+mod walk;
+#[cfg(feature = "watch")]
+mod watch;
+
+pub use batch::{BatchEvent, BatchHandle, BatchMode};
+pub use buffer::Buffer;
+pub use progress::{MsgSender, Progress, DEFAULT_PROGRESS_GRANULARITY};
 //use error::VfsError;
-use vfs_driver::{EntryType, VfsDriver};
+pub use vfs_driver::{DirEntry, EntryType, Metadata, ReadSeek, VfsDriver};
+pub use view::EvfsView;
+pub use walk::{Walk, WalkEntry};
+
+use registry::Registry;
+use sub_dir_fs::SubDirFs;
+#[cfg(feature = "watch")]
+pub use watch::{WatchEvent, WatchEventKind, WatchHandle};
 
 pub enum RecvMsg {
-    ReadProgress(f32),
-    ReadDone(Box<[u8]>),
+    /// Sent every `Evfs::set_progress_granularity` bytes while loading. A final update with
+    /// all bytes done is always sent before the data is delivered.
+    ReadProgress(Progress),
+    ReadDone(Buffer),
+    /// Part of a file loaded with `Evfs::load_file_streaming`. `offset` is the position of
+    /// the data in the file.
+    Chunk {
+        offset: u64,
+        data: Buffer,
+    },
+    /// All chunks of a streamed file have been sent
+    StreamDone,
     Error(VfsError),
 }
 
@@ -30,15 +73,42 @@ pub struct Mount {
     driver: ArcDriver,
 }
 
+impl Mount {
+    /// The path in the virtual file system this mount is located at
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The (resolved) source the mount reads its data from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
+/// How the data of a file should be loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadMode {
+    /// Read the file into a newly allocated buffer
+    Read,
+    /// Memory map the file (if the driver supports it) and return a buffer borrowing the mapping
+    Mmap,
+    /// Send the data in chunks while the file is being read
+    Stream,
+}
+
 pub enum SendMsg {
     // TODO: Proper error
     //Error(String),
     /// Send messages
-    LoadFile(
+    LoadFile(String, LoadMode, Mounts, Vec<ArcDriver>, MsgSender),
+    /// Load a part of a file given by offset and length
+    LoadRange(String, u64, u64, Mounts, Vec<ArcDriver>, MsgSender),
+    /// Open a file for streaming
+    OpenFile(
         String,
         Mounts,
         Vec<ArcDriver>,
-        crossbeam_channel::Sender<RecvMsg>,
+        crossbeam_channel::Sender<Result<Box<dyn ReadSeek>, VfsError>>,
     ),
 }
 
@@ -47,10 +117,75 @@ pub mod local_fs;
 #[cfg(feature = "local-fs")]
 pub use local_fs::LocalFs;
 
+#[cfg(feature = "embedded-fs")]
+pub mod embedded_fs;
+#[cfg(feature = "embedded-fs")]
+pub use embedded_fs::EmbeddedFs;
+
+#[cfg(feature = "memory-fs")]
+pub mod memory_fs;
+#[cfg(feature = "memory-fs")]
+pub use memory_fs::MemoryFs;
+
 #[cfg(feature = "zip-fs")]
 pub mod zip_fs;
 #[cfg(feature = "zip-fs")]
-pub use zip_fs::ZipFs;
+pub use zip_fs::{PasswordProvider, ZipFs};
+
+#[cfg(feature = "pak-fs")]
+pub mod pak_fs;
+#[cfg(feature = "pak-fs")]
+pub use pak_fs::{PakBuilder, PakCompression, PakFs, DEFAULT_PAK_ALIGNMENT};
+
+#[cfg(feature = "quake-pak-fs")]
+pub mod quake_pak_fs;
+#[cfg(feature = "quake-pak-fs")]
+pub use quake_pak_fs::QuakePakFs;
+
+#[cfg(feature = "wad-fs")]
+pub mod wad_fs;
+#[cfg(feature = "wad-fs")]
+pub use wad_fs::WadFs;
+
+#[cfg(feature = "tar-fs")]
+pub mod tar_fs;
+#[cfg(feature = "tar-fs")]
+pub use tar_fs::TarFs;
+
+#[cfg(feature = "sevenz-fs")]
+pub mod sevenz_fs;
+#[cfg(feature = "sevenz-fs")]
+pub use sevenz_fs::SevenZipFs;
+
+#[cfg(any(feature = "xz-fs", feature = "bzip2-fs"))]
+pub mod compressed_fs;
+#[cfg(any(feature = "xz-fs", feature = "bzip2-fs"))]
+pub use compressed_fs::CompressedFs;
+
+#[cfg(feature = "encrypted-fs")]
+pub mod encrypted_fs;
+#[cfg(feature = "encrypted-fs")]
+pub use encrypted_fs::{Cipher, EncryptedFs, KeyProvider};
+
+#[cfg(feature = "manifest")]
+pub mod manifest;
+#[cfg(feature = "manifest")]
+pub use manifest::{HashAlgorithm, Manifest, VerifiedFs};
+
+#[cfg(feature = "cas-fs")]
+pub mod cas_fs;
+#[cfg(feature = "cas-fs")]
+pub use cas_fs::CasFs;
+
+#[cfg(feature = "patch-fs")]
+pub mod patch_fs;
+#[cfg(feature = "patch-fs")]
+pub use patch_fs::PatchFs;
+
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "config")]
+pub use config::{ConfigError, ConfigFormat};
 
 #[cfg(feature = "http-fs")]
 pub mod http_fs;
@@ -77,6 +212,50 @@ pub enum InternalError {
         /// The invalid path
         path: String,
     },
+    /// If trying to write to a driver that doesn't support it
+    #[error("The path `{path}` is read-only")]
+    ReadOnly {
+        /// The path that was written to
+        path: String,
+    },
+    /// If the password of an encrypted file is wrong or missing
+    #[error("Wrong or missing password for `{path}`")]
+    InvalidPassword {
+        /// The encrypted path
+        path: String,
+    },
+    /// If a file is encrypted with a method that isn't supported
+    #[error("The encryption of `{path}` is not supported")]
+    UnsupportedEncryption {
+        /// The encrypted path
+        path: String,
+    },
+    /// If the key an encrypted file was encrypted with isn't known
+    #[error("The key `{key_id}` for `{path}` was not found")]
+    KeyNotFound {
+        /// The encrypted path
+        path: String,
+        /// Id of the missing key
+        key_id: String,
+    },
+    /// If an encrypted file has been modified, isn't encrypted or the key is wrong
+    #[error("Unable to authenticate the encrypted data of `{path}`")]
+    AuthenticationFailed {
+        /// The encrypted path
+        path: String,
+    },
+    /// If a loaded file doesn't match the manifest of its mount or isn't in it
+    #[error("The data of `{path}` doesn't match the manifest")]
+    VerificationFailed {
+        /// The path that failed verification
+        path: String,
+    },
+    /// If a delta is for another version of the base file than the one in the base mount
+    #[error("The base of `{path}` doesn't match the patch")]
+    PatchBaseMismatch {
+        /// The patched path
+        path: String,
+    },
     /// If no mount was found
     #[error("Invalid mount `{path}`")]
     InvalidMount {
@@ -102,15 +281,34 @@ pub enum VfsError {
     },
 
     /// If trying to mount an invalid path
-    #[error("The mount `{mount}` is invalid for this driver. Has to be a directory")]
+    #[error("The mount `{mount}` isn't supported by this driver")]
     UnsupportedMount {
         /// The invalid path
         mount: String,
     },
 
-    /// If trying to mount an invalid path
-    #[error("There is no driver that supports the current mount")]
-    NoDriverSupport {},
+    /// If no driver can mount a source
+    #[error("No driver can mount `{mount}`{}", registry::format_rejections(.rejected))]
+    NoDriverSupport {
+        /// The source that was mounted
+        mount: String,
+        /// Names of the drivers that were tried and why they rejected the source
+        rejected: Vec<(String, String)>,
+    },
+
+    /// If a path used with an `EvfsView` leads outside of the view
+    #[error("The path `{path}` is outside of the view")]
+    OutsideView {
+        /// The path
+        path: String,
+    },
+
+    /// If a driver is referred to by a name that no installed driver has
+    #[error("There is no driver named `{name}`")]
+    UnknownDriver {
+        /// The unknown name
+        name: String,
+    },
 
     /// If trying to mount an invalid path
     #[error("No mount for `{path}` was found. Have you forgot to mount the path?")]
@@ -118,59 +316,224 @@ pub enum VfsError {
         /// The invalid path
         path: String,
     },
+
+    /// If the file doesn't exist in the mount
+    #[error("The path `{path}` is not found in mount")]
+    PathNotFound {
+        /// The invalid path
+        path: String,
+    },
+
+    /// If trying to load a directory
+    #[error("The path `{path}` is a directory and not a file")]
+    NotFile {
+        /// The invalid path
+        path: String,
+    },
+
+    /// If the path goes through a file that no driver knows how to read
+    #[error("Unable to find decompressor for `{path}`")]
+    DecompressorNotFound {
+        /// The invalid path
+        path: String,
+    },
+
+    /// If no mount of the path supports writing
+    #[error("The path `{path}` is read-only")]
+    ReadOnly {
+        /// The path that was written to
+        path: String,
+    },
+
+    /// If the password of an encrypted file is wrong or missing
+    #[error("Wrong or missing password for `{path}`")]
+    InvalidPassword {
+        /// The encrypted path
+        path: String,
+    },
+
+    /// If a file is encrypted with a method that isn't supported
+    #[error("The encryption of `{path}` is not supported")]
+    UnsupportedEncryption {
+        /// The encrypted path
+        path: String,
+    },
+
+    /// If the key an encrypted file was encrypted with isn't known
+    #[error("The key `{key_id}` for `{path}` was not found")]
+    KeyNotFound {
+        /// The encrypted path
+        path: String,
+        /// Id of the missing key
+        key_id: String,
+    },
+
+    /// If an encrypted file has been modified, isn't encrypted or the key is wrong
+    #[error("Unable to authenticate the encrypted data of `{path}`")]
+    AuthenticationFailed {
+        /// The encrypted path
+        path: String,
+    },
+
+    /// If a loaded file doesn't match the manifest of its mount or isn't in it
+    #[error("The data of `{path}` doesn't match the manifest")]
+    VerificationFailed {
+        /// The path that failed verification
+        path: String,
+    },
+
+    /// If a delta is for another version of the base file than the one in the base mount
+    #[error("The base of `{path}` doesn't match the patch")]
+    PatchBaseMismatch {
+        /// The patched path
+        path: String,
+    },
+
+    /// If the signature of a manifest is invalid
+    #[error("The signature of the manifest is invalid")]
+    InvalidSignature {},
+
+    /// If a mount configuration has errors. All errors that were found are listed.
+    #[cfg(feature = "config")]
+    #[error("Invalid mount configuration\n{}", config::format_errors(.errors))]
+    InvalidConfig {
+        /// The errors with the line they are on
+        errors: Vec<ConfigError>,
+    },
+
+    /// If trying to rename a file to a path in another mount
+    #[error("Unable to rename `{from}` to `{to}` as they are in different mounts")]
+    CrossMountRename {
+        /// The path that was renamed
+        from: String,
+        /// The new path
+        to: String,
+    },
+}
+
+impl From<InternalError> for VfsError {
+    fn from(e: InternalError) -> VfsError {
+        match e {
+            InternalError::PathNotFound { path } => VfsError::PathNotFound { path },
+            InternalError::NotFile { path } => VfsError::NotFile { path },
+            InternalError::DecompressorNotFound { path } => VfsError::DecompressorNotFound { path },
+            InternalError::ReadOnly { path } => VfsError::ReadOnly { path },
+            InternalError::InvalidPassword { path } => VfsError::InvalidPassword { path },
+            InternalError::UnsupportedEncryption { path } => {
+                VfsError::UnsupportedEncryption { path }
+            }
+            InternalError::KeyNotFound { path, key_id } => VfsError::KeyNotFound { path, key_id },
+            InternalError::AuthenticationFailed { path } => VfsError::AuthenticationFailed { path },
+            InternalError::VerificationFailed { path } => VfsError::VerificationFailed { path },
+            InternalError::PatchBaseMismatch { path } => VfsError::PatchBaseMismatch { path },
+            InternalError::InvalidMount { path } => VfsError::NoMountFound { path },
+            InternalError::FileError(e) => VfsError::FileError(e),
+            InternalError::SendError(_) => VfsError::FileError(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "The receiver of the request was dropped",
+            )),
+        }
+    }
 }
 
 pub struct Handle {
     pub recv: crossbeam_channel::Receiver<RecvMsg>,
 }
 
+/// Returned by `Evfs::open`. Receives the opened stream once the file has been found.
+pub struct StreamHandle {
+    pub recv: crossbeam_channel::Receiver<Result<Box<dyn ReadSeek>, VfsError>>,
+}
+
 pub struct Evfs {
-    drivers: Vec<ArcDriver>,
+    registry: Registry,
     pub mounts: Mounts,
+    progress_granularity: u64,
+    /// Password provider of the built-in `ZipFs`
+    #[cfg(feature = "zip-fs")]
+    zip_passwords: zip_fs::PasswordSlot,
     _msg_thread: thread::JoinHandle<()>,
     main_send: crossbeam_channel::Sender<SendMsg>,
 }
 
-fn handle_error(res: Result<(), InternalError>, msg: &crossbeam_channel::Sender<RecvMsg>) {
-    if let Err(e) = res {
-        match e {
-            InternalError::FileError(e) => {
-                let file_error = format!("{:#?}", e);
-                if let Err(send_err) = msg.send(RecvMsg::Error(e.into())) {
-                    error!(
-                        "evfs: Unable to send file error {:#?} to main thread due to {:#?}",
-                        file_error, send_err
-                    );
-                }
+fn handle_error(res: Result<(), InternalError>, msg: &MsgSender) {
+    match res {
+        // If sending failed the receiver is gone so there is nobody to report to
+        Ok(()) | Err(InternalError::SendError(_)) => (),
+        Err(e) => {
+            let file_error = format!("{:#?}", e);
+            if let Err(send_err) = msg.send(RecvMsg::Error(e.into())) {
+                error!(
+                    "evfs: Unable to send file error {:#?} to main thread due to {:#?}",
+                    file_error, send_err
+                );
             }
-
-            _ => (),
         }
     }
 }
 
-/// Find the initial mount point for a file
-fn get_intital_mount(path: &str, mount_points: &Mounts) -> Option<usize> {
-    // first find a mount point for this file. The way we do it is to strip down the path more and more
-    // such as if a starting point is /hello/this/has/many/dirs/file
+/// Find the mount points that contain a directory, in priority order
+fn get_mounts_for_dir(dir: &str, mount_points: &Mounts) -> Vec<usize> {
+    // first find a mount point for this dir. The way we do it is to strip down the path more and more
+    // such as if a starting point is /hello/this/has/many/dirs
     // /hello/this/has/many/dirs
     // /hello/this/has/many
     // /hello/this/has
     // ..
-    // In order to find the top-level mount point as it's possible to overlay file-systems
+    // In order to find the top-level mount point first as it's possible to overlay file-systems.
+    // Mounts at the same location are in the order they were mounted.
 
-    let dir = Path::new(path);
+    let mut found = Vec::new();
 
-    while let Some(dir) = dir.parent() {
+    for dir in Path::new(dir).ancestors() {
         let t = dir.to_string_lossy();
         for (i, mount) in mount_points.iter().enumerate() {
             if mount.target == t {
-                return Some(i);
+                found.push(i);
             }
         }
     }
 
-    None
+    found
+}
+
+/// Returns the path relative to the mount at `target`
+fn mount_relative_path<'a>(target: &str, path: &'a str) -> &'a str {
+    path[target.trim_end_matches('/').len()..].trim_start_matches('/')
+}
+
+/// Finds the mount for a file and returns it together with the path relative to the mount.
+/// If several mounts overlay the location of the file the first one that has it is used.
+fn find_mount<'a>(
+    path: &'a str,
+    mounts: &'a Mounts,
+) -> Result<(&'a Mount, &'a str), InternalError> {
+    let dir = Path::new(path)
+        .parent()
+        .map_or(Cow::Borrowed(""), |p| p.to_string_lossy());
+    let candidates = get_mounts_for_dir(&dir, mounts);
+
+    for &index in &candidates {
+        let mount = &mounts[index];
+        let file_path = mount_relative_path(&mount.target, path);
+
+        let (path_size, entry_type) = find_entry(&mount.driver, file_path);
+
+        // Parent directories existing isn't enough, some part of the path has to be a file
+        if entry_type == EntryType::File || path_size == file_path.len() {
+            return Ok((mount, file_path));
+        }
+    }
+
+    // Nothing has the file so let the top mount report the error
+    if let Some(&index) = candidates.first() {
+        let mount = &mounts[index];
+        Ok((mount, mount_relative_path(&mount.target, path)))
+    } else {
+        Err(InternalError::InvalidMount {
+            path: path.to_owned(),
+        })
+    }
 }
 
 // Looks for file entry for a driver
@@ -185,7 +548,7 @@ fn find_entry(driver: &ArcDriver, path: &str) -> (usize, EntryType) {
     loop {
         let t = dir.to_string_lossy();
 
-        if t == "" {
+        if t.is_empty() {
             break;
         }
 
@@ -207,92 +570,99 @@ fn find_entry(driver: &ArcDriver, path: &str) -> (usize, EntryType) {
     (0, EntryType::NotFound)
 }
 
+/// Creates a driver for the data of a file that the path continues into. Drivers are picked by
+/// the file extension or by detecting the format of the data.
 fn find_driver(
     current_path: &str,
-    file_data: &[u8],
-    drivers: &Vec<ArcDriver>,
-) -> Option<ArcDriver> {
-    // TODO: Figure out how to deal with finding by data or ext
+    file_data: Buffer,
+    drivers: &[ArcDriver],
+) -> Result<ArcDriver, InternalError> {
+    let file_ext = Path::new(current_path)
+        .extension()
+        .map(|ext| ext.to_string_lossy())
+        .unwrap_or_default();
+
     for driver in drivers {
-        if driver.supports_file_ext(current_path) {
-            return Some(driver.clone());
+        if !driver.supports_file_ext(&file_ext) && !driver.can_decompress(&file_data) {
+            continue;
         }
 
-        if driver.can_decompress(file_data) {
-            return Some(driver.clone());
+        // Drivers that can't read from memory (such as LocalFs) are skipped
+        match driver.new_from_data(current_path, file_data.clone()) {
+            Ok(new_driver) => return Ok(Arc::new(new_driver)),
+            Err(InternalError::DecompressorNotFound { .. }) => (),
+            Err(e) => return Err(e),
         }
     }
 
-    None
+    Err(InternalError::DecompressorNotFound {
+        path: current_path.to_owned(),
+    })
 }
 
-fn load_file(
+/// Walks the path of a file inside a mount. If the path goes through a file (such as an archive)
+/// the file is loaded and a driver that understands it is used for the rest of the path.
+/// Returns the driver that has the file together with the path of the file in that driver.
+fn resolve_file<'a>(
     mount: &Mount,
-    path: &str,
-    drivers: &Vec<ArcDriver>,
-    send_msg: &crossbeam_channel::Sender<RecvMsg>,
-) -> Result<(), InternalError> {
-    // used for "sliding window" of the path
-    let path_len = path.len();
-    let mut start_path = 0;
-    let mut end_path = path_len;
+    path: &'a str,
+    mode: LoadMode,
+    drivers: &[ArcDriver],
+    send_msg: &MsgSender,
+) -> Result<(ArcDriver, &'a str), InternalError> {
     let mut driver = mount.driver.clone();
-
-    // first strip the first pArcDriver of the path. We want to go from something like
-    // this is safe to do as we have found this mount point
-
-    //println!("starting to load file");
+    let mut current_path = path;
+    // Reported progress is only for the requested file
+    let send_msg = &send_msg.without_progress();
 
     // max 100 depth for saftey and not lock-up this code in case of error
     for _ in 0..100 {
-        let current_path = &path[start_path..end_path];
-
-        println!("find entry");
-
-        // Search for the entry with the current mount
-        let (path_size, entry_type) = find_entry(&mount.driver, current_path);
-
-        //println!("end find entry");
+        // Search for the entry with the current driver
+        let (path_size, entry_type) = find_entry(&driver, current_path);
 
         // Validate that some part of the path was actually found
         match entry_type {
             EntryType::NotFound => {
-                println!("load_file: NotFound");
                 return Err(InternalError::PathNotFound {
                     path: path.to_owned(),
                 });
             }
-            EntryType::Directory => {
-                //println!("load_file: Directory");
+            // A directory is only an error if it's the file we were asked for
+            EntryType::Directory if path_size == current_path.len() => {
                 return Err(InternalError::NotFile {
                     path: path.to_owned(),
                 });
             }
-            _ => (),
+            EntryType::Directory => {
+                return Err(InternalError::PathNotFound {
+                    path: path.to_owned(),
+                });
+            }
+            EntryType::File => (),
         }
 
-        let file_data = driver.load_file(current_path, send_msg)?;
-
-        // if we are at the end path we can return the file
-        // TODO: Auto-detect and try to decompress
-        if end_path == path_size {
-            //println!("file done");
-            send_msg.send(RecvMsg::ReadDone(file_data))?;
-            return Ok(());
+        // if we are at the end path we have found the file
+        if path_size == current_path.len() {
+            return Ok((driver, current_path));
         }
 
         // if we aren't at end here we have a multifile and need to find a decompressor file tho current file
-        if let Some(new_driver) = find_driver(current_path, &file_data, drivers) {
-            driver = new_driver;
-        } else {
-            return Err(InternalError::DecompressorNotFound {
+        let file_path = &current_path[..path_size];
+
+        // Files on the way to the one requested are never streamed
+        let file_data = match mode {
+            LoadMode::Mmap => driver.map_file(file_path, send_msg)?,
+            LoadMode::Read | LoadMode::Stream => driver.load_file(file_path, send_msg)?,
+        };
+
+        driver = find_driver(file_path, file_data, drivers).map_err(|e| match e {
+            InternalError::DecompressorNotFound { .. } => InternalError::DecompressorNotFound {
                 path: path.to_owned(),
-            });
-        }
+            },
+            e => e,
+        })?;
 
-        // set path window
-        start_path = path_size;
-        end_path = path_len;
+        current_path = current_path[path_size..].trim_start_matches('/');
     }
 
     Err(InternalError::DecompressorNotFound {
@@ -300,25 +670,128 @@ fn load_file(
     })
 }
 
+/// Finds the directory `dir` inside `driver`, opening the archives the path goes through the
+/// same way as `resolve_file`. Returns the driver that has the directory together with the
+/// path of the directory in it, which is empty if `dir` is an archive.
+fn resolve_dir(
+    driver: ArcDriver,
+    dir: &str,
+    drivers: &[ArcDriver],
+) -> Result<(ArcDriver, String), InternalError> {
+    let (send_msg, _recv_msg) = unbounded::<RecvMsg>();
+    let send_msg = MsgSender::new(send_msg, u64::MAX).without_progress();
+    let mut driver = driver;
+    let mut current_path = dir.trim_matches('/');
+
+    // max 100 depth for saftey the same way as `resolve_file`
+    for _ in 0..100 {
+        if current_path.is_empty() || driver.has_entry(current_path) == EntryType::Directory {
+            return Ok((driver, current_path.to_owned()));
+        }
+
+        let (path_size, entry_type) = find_entry(&driver, current_path);
+
+        if entry_type != EntryType::File {
+            return Err(InternalError::PathNotFound { path: dir.into() });
+        }
+
+        let file_path = &current_path[..path_size];
+        let file_data = driver.load_file(file_path, &send_msg)?;
+
+        driver = find_driver(file_path, file_data, drivers)?;
+        current_path = current_path[path_size..].trim_start_matches('/');
+    }
+
+    Err(InternalError::DecompressorNotFound { path: dir.into() })
+}
+
+fn load_file(
+    mount: &Mount,
+    path: &str,
+    mode: LoadMode,
+    drivers: &[ArcDriver],
+    send_msg: &MsgSender,
+) -> Result<(), InternalError> {
+    let (driver, file_path) = resolve_file(mount, path, mode, drivers, send_msg)?;
+
+    match mode {
+        LoadMode::Read => {
+            send_msg.send(RecvMsg::ReadDone(driver.load_file(file_path, send_msg)?))?
+        }
+        LoadMode::Mmap => {
+            send_msg.send(RecvMsg::ReadDone(driver.map_file(file_path, send_msg)?))?
+        }
+        LoadMode::Stream => {
+            driver.stream_file(file_path, send_msg)?;
+            send_msg.send(RecvMsg::StreamDone)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn load_range(
+    mount: &Mount,
+    path: &str,
+    offset: u64,
+    len: u64,
+    drivers: &[ArcDriver],
+    send_msg: &MsgSender,
+) -> Result<(), InternalError> {
+    let (driver, file_path) = resolve_file(mount, path, LoadMode::Read, drivers, send_msg)?;
+    let file_data = driver.load_range(file_path, offset, len, send_msg)?;
+
+    send_msg.finish(file_data.len() as u64)?;
+    send_msg.send(RecvMsg::ReadDone(file_data))
+}
+
+fn open_file(
+    mount: &Mount,
+    path: &str,
+    drivers: &[ArcDriver],
+) -> Result<Box<dyn ReadSeek>, InternalError> {
+    // Progress of archives loaded on the way to the file isn't reported when streaming
+    let (send_msg, _recv_msg) = unbounded::<RecvMsg>();
+    let send_msg = MsgSender::new(send_msg, DEFAULT_PROGRESS_GRANULARITY).without_progress();
+    let (driver, file_path) = resolve_file(mount, path, LoadMode::Read, drivers, &send_msg)?;
+    driver.open(file_path)
+}
+
 fn handle_msg(msg: &SendMsg) {
     match msg {
-        SendMsg::LoadFile(path, mounts, drivers, msg) => {
-            let res;
+        SendMsg::LoadFile(path, mode, mounts, drivers, msg) => {
+            let res = find_mount(path, mounts)
+                .and_then(|(mount, path)| load_file(mount, path, *mode, drivers, msg));
 
-            let driver_index = get_intital_mount(path, mounts);
+            //let res = loader.load_file(path, msg);
+            handle_error(res, msg);
+        }
 
-            if let Some(driver_index) = driver_index {
-                let driver = &mounts[driver_index];
-                res = load_file(driver, &path[driver.target.len() + 1..], drivers, msg);
-            } else {
-                res = Err(InternalError::InvalidMount {
-                    path: path.to_owned(),
-                });
-            }
+        SendMsg::LoadRange(path, offset, len, mounts, drivers, msg) => {
+            let res = find_mount(path, mounts)
+                .and_then(|(mount, path)| load_range(mount, path, *offset, *len, drivers, msg));
 
-            //let res = loader.load_file(path, msg);
             handle_error(res, msg);
         }
+
+        SendMsg::OpenFile(path, mounts, drivers, msg) => {
+            let res = find_mount(path, mounts)
+                .and_then(|(mount, path)| open_file(mount, path, drivers))
+                .map_err(VfsError::from);
+
+            if msg.send(res).is_err() {
+                trace!(
+                    "evfs: open of {} finished after the handle was dropped",
+                    path
+                );
+            }
+        }
+    }
+}
+
+impl Default for Evfs {
+    fn default() -> Evfs {
+        Evfs::new()
     }
 }
 
@@ -347,40 +820,108 @@ impl Evfs {
             })
             .unwrap();
 
-        let mut drivers: Vec<ArcDriver> = Vec::new();
+        let mut registry = Registry::default();
 
         #[cfg(feature = "local-fs")]
-        drivers.push(Arc::new(Box::new(LocalFs::new())));
+        registry.install("local", Arc::new(Box::new(LocalFs::new())));
 
         #[cfg(feature = "zip-fs")]
-        drivers.push(Arc::new(Box::new(ZipFs::new())));
+        let zip_fs = ZipFs::new();
+        #[cfg(feature = "zip-fs")]
+        let zip_passwords = zip_fs.password_slot();
+        #[cfg(feature = "zip-fs")]
+        registry.install("zip", Arc::new(Box::new(zip_fs)));
+
+        #[cfg(feature = "tar-fs")]
+        registry.install("tar", Arc::new(Box::new(TarFs::new())));
+
+        #[cfg(feature = "pak-fs")]
+        registry.install("pak", Arc::new(Box::new(PakFs::new())));
+
+        #[cfg(feature = "quake-pak-fs")]
+        registry.install("quake-pak", Arc::new(Box::new(QuakePakFs::new())));
+
+        #[cfg(feature = "wad-fs")]
+        registry.install("wad", Arc::new(Box::new(WadFs::new())));
+
+        #[cfg(feature = "sevenz-fs")]
+        registry.install("7z", Arc::new(Box::new(SevenZipFs::new())));
+
+        #[cfg(feature = "cas-fs")]
+        registry.install("cas", Arc::new(Box::new(CasFs::new())));
+
+        #[cfg(any(feature = "xz-fs", feature = "bzip2-fs"))]
+        registry.install("compressed", Arc::new(Box::new(CompressedFs::new())));
 
         #[cfg(feature = "http-fs")]
-        drivers.push(Arc::new(Box::new(HttpFs::new())));
+        registry.install("http", Arc::new(Box::new(HttpFs::new())));
+
+        registry.add_builtin_schemes();
 
         Evfs {
-            drivers,
+            registry,
             mounts: Vec::new(),
+            progress_granularity: DEFAULT_PROGRESS_GRANULARITY,
+            #[cfg(feature = "zip-fs")]
+            zip_passwords,
             _msg_thread: msg_thread,
             main_send,
         }
     }
 
+    /// Sets how many bytes are loaded between each `RecvMsg::ReadProgress` update.
+    /// Defaults to `DEFAULT_PROGRESS_GRANULARITY`.
+    pub fn set_progress_granularity(&mut self, bytes: u64) {
+        self.progress_granularity = bytes.max(1);
+    }
+
+    /// Sets the provider of passwords for encrypted entries in zip archives. This applies to
+    /// archives that are already mounted as well as archives inside other mounts, which are
+    /// named by their path inside the mount when the provider is asked for a password.
+    #[cfg(feature = "zip-fs")]
+    pub fn set_password_provider(&mut self, provider: Arc<dyn PasswordProvider>) {
+        *self
+            .zip_passwords
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = Some(provider);
+    }
+
+    /// Installs a driver that is offered every source that has no registered scheme. Use
+    /// `register_driver` to install a driver that can be chosen by scheme, extension or name.
     pub fn install_driver(&mut self, driver: ArcDriver) {
-        self.drivers.push(driver);
+        self.registry.install_unnamed(driver);
+    }
+
+    /// Installs a driver with a name that `register_scheme`, `register_extension` and
+    /// `mount_with` can refer to. A driver installed before with the same name (including the
+    /// built-in ones such as `"zip"` or `"http"`) is replaced.
+    pub fn register_driver(&mut self, name: &str, driver: ArcDriver) {
+        self.registry.install(name, driver);
+    }
+
+    /// Mounts sources starting with `scheme://` with the driver named `driver`. Remote drivers
+    /// get the whole source and others the path after the scheme, so `zip://data.bin` mounts
+    /// `data.bin` as a zip archive.
+    pub fn register_scheme(&mut self, scheme: &str, driver: &str) -> Result<(), VfsError> {
+        self.registry.set_scheme(scheme, driver)
+    }
+
+    /// Mounts files with the extension `ext` (such as `"tar.gz"`) with the driver named `driver`
+    pub fn register_extension(&mut self, ext: &str, driver: &str) -> Result<(), VfsError> {
+        self.registry.set_extension(ext, driver)
     }
 
     fn full_path<'a>(driver: &ArcDriver, source: &'a str) -> Result<Cow<'a, str>, VfsError> {
-        if driver.is_remote() {
+        // Sources with a scheme (such as `embedded://`) aren't paths on disk
+        if driver.is_remote() || source.contains("://") {
             Ok(Cow::Borrowed(source))
         } else {
-            let t;
             // special case for ""
-            if source == "" {
-                t = std::env::current_dir()?;
+            let t = if source.is_empty() {
+                std::env::current_dir()?
             } else {
-                t = std::fs::canonicalize(source)?;
-            }
+                std::fs::canonicalize(source)?
+            };
 
             // TODO: Fix me
             let t = t.to_str().unwrap();
@@ -389,109 +930,2624 @@ impl Evfs {
         }
     }
 
-    /// Mount a path in the virtual file system
-    pub fn mount(&mut self, target: &str, source: &str) -> Result<(), VfsError> {
-        for (i, driver) in self.drivers.iter().enumerate() {
-            if driver.can_mount(target, source).is_ok() {
-                let full_path = Self::full_path(driver, source)?;
-
-                self.mounts.push(Mount {
-                    target: target.into(),
-                    source: full_path.to_owned().to_string(),
-                    driver: Arc::new(self.drivers[i].new_from_path(&full_path)?),
-                });
-
-                return Ok(());
-            }
-        }
+    fn new_driver(
+        driver: &ArcDriver,
+        source: &str,
+    ) -> Result<(String, Box<dyn VfsDriver>), VfsError> {
+        let full_path = Self::full_path(driver, source)?;
+        let new_driver = driver.new_from_path(&full_path)?;
 
-        Err(VfsError::NoDriverSupport {})
+        Ok((full_path.into_owned(), new_driver))
     }
 
-    /// TODO: Error handling, etc, correct path, etc
-    pub fn load_file(&self, path: &str) -> Handle {
-        let mounts = self.mounts.clone();
-        let drivers = self.drivers.clone();
-        let (thread_send, main_recv) = unbounded::<RecvMsg>();
+    /// Scopes a driver to the directory `sub_path` in it for sources such as
+    /// `data.zip!/gfx`. Archives on the way to the directory are opened here.
+    fn scope_driver(
+        &self,
+        source: String,
+        driver: Box<dyn VfsDriver>,
+        sub_path: Option<&str>,
+    ) -> Result<(String, Box<dyn VfsDriver>), VfsError> {
+        let sub_path = match sub_path.map(|p| p.trim_matches('/')) {
+            Some(sub_path) if !sub_path.is_empty() => sub_path,
+            _ => return Ok((source, driver)),
+        };
+
+        let driver: ArcDriver = Arc::new(driver);
+
+        // Remote drivers can't tell directories from files so the path is used as it is
+        let (driver, dir) = if driver.is_remote() {
+            (driver, sub_path.to_owned())
+        } else {
+            resolve_dir(driver, sub_path, &self.registry.drivers())?
+        };
 
-        self.main_send
-            .send(SendMsg::LoadFile(path.into(), mounts, drivers, thread_send))
-            .unwrap();
+        // Directories on disk get their real path as source so they can be watched
+        let dir_on_disk = Path::new(&source).join(sub_path);
+        let source = if !driver.is_remote() && dir_on_disk.is_dir() {
+            dir_on_disk.to_string_lossy().into_owned()
+        } else {
+            format!("{}{}{}", source, sub_dir_fs::SUB_PATH_SEPARATOR, sub_path)
+        };
 
-        Handle { recv: main_recv }
+        Ok((source, Box::new(SubDirFs::new(driver, &dir))))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    #[cfg(feature = "local-fs")]
-    fn load_local_file() {
-        use super::*;
-        use std::{thread, time};
+    /// Creates a driver for `source` with the driver of its scheme or extension or the first
+    /// driver that can mount it. Returns the driver together with the resolved source.
+    fn create_driver(
+        &self,
+        target: &str,
+        source: &str,
+    ) -> Result<(String, Box<dyn VfsDriver>), VfsError> {
+        let (source, sub_path) = sub_dir_fs::split_sub_path(source);
+        let (source, driver) = self.find_driver_for(target, source)?;
+        self.scope_driver(source, driver, sub_path)
+    }
 
-        let mut vfs = Evfs::new();
-        vfs.mount("/test", "").unwrap();
-        let handle = vfs.load_file("/test/Cargo.toml");
+    fn find_driver_for(
+        &self,
+        target: &str,
+        source: &str,
+    ) -> Result<(String, Box<dyn VfsDriver>), VfsError> {
+        if let Some((driver, path)) = self.registry.for_scheme(source) {
+            return Self::new_driver(driver, path);
+        }
 
-        for _ in 0..10 {
-            match handle.recv.try_recv() {
-                Ok(data) => match data {
-                    RecvMsg::ReadProgress(p) => println!("ReadProgress {}", p),
-                    RecvMsg::ReadDone(_data) => {
-                        println!("File read done!");
-                    }
+        if let Some(driver) = self.registry.for_extension(source) {
+            if Path::new(source).is_file() {
+                return Self::new_driver(driver, source);
+            }
+        }
 
-                    RecvMsg::Error(e) => {
-                        panic!("main: error {:#?}", e);
-                    }
-                },
+        let mut rejected = Vec::new();
 
-                _ => (),
+        for (name, driver) in self.registry.iter() {
+            match driver.can_mount(target, source) {
+                Ok(()) => return Self::new_driver(driver, source),
+                Err(e) => rejected.push((name.clone(), registry::rejection_reason(e))),
             }
-
-            thread::sleep(time::Duration::from_millis(10));
         }
+
+        Err(VfsError::NoDriverSupport {
+            mount: source.into(),
+            rejected,
+        })
     }
 
-    #[test]
-    #[cfg(feature = "zip-fs")]
-    fn load_zip_file() {
-        use super::*;
-        use hex_literal::hex;
-        use sha1::*;
-        use std::{thread, time};
+    /// Creates a driver for `source` with the driver named `name` without checking if it can
+    /// mount it
+    fn create_named_driver(
+        &self,
+        name: &str,
+        source: &str,
+    ) -> Result<(String, Box<dyn VfsDriver>), VfsError> {
+        let (source, sub_path) = sub_dir_fs::split_sub_path(source);
+        let (source, driver) = Self::new_driver(self.registry.get(name)?, source)?;
+        self.scope_driver(source, driver, sub_path)
+    }
 
-        let mut vfs = Evfs::new();
-        vfs.mount("/data", "data/test_data.zip").unwrap();
-        let handle = vfs.load_file("/data/text.txt");
-        let mut file_done = false;
+    /// Mount a path in the virtual file system. A directory inside the source can be mounted
+    /// by adding it after `!/`, such as `data/base.zip!/gfx/textures`, which works for archives
+    /// inside archives and remote sources as well. Sources with a registered scheme (such as
+    /// `zip://` or `http://`) use the driver of the scheme and files with a registered extension
+    /// the driver of the extension. Other sources are offered to all drivers and
+    /// `VfsError::NoDriverSupport` lists why each of them rejected the source if none accepts it.
+    ///
+    /// Files compressed with xz or bzip2 are treated as a directory with the uncompressed file
+    /// in it, named without the extension. Mounting `data/level.bin.xz` at `/data` gives
+    /// `/data/level.bin`, and the same file inside another mount is decompressed when it's
+    /// loaded as `/data/level.bin.xz/level.bin`.
+    pub fn mount(&mut self, target: &str, source: &str) -> Result<(), VfsError> {
+        let (source, driver) = self.create_driver(target, source)?;
 
-        for _ in 0..10 {
-            match handle.recv.try_recv() {
-                Ok(data) => match data {
-                    RecvMsg::ReadProgress(p) => println!("ReadProgress {}", p),
-                    RecvMsg::ReadDone(data) => {
-                        let mut hasher = Sha1::new();
-                        hasher.update(data);
-                        let hash = hasher.finalize();
-                        assert_eq!(hash[..], hex!("f8cafd925b9a7a9af2e977606d500783ba118ec0"));
-                        file_done = true;
-                    }
+        self.mounts.push(Mount {
+            target: target.into(),
+            source,
+            driver: Arc::new(driver),
+        });
 
-                    RecvMsg::Error(e) => {
-                        panic!("main: error {:#?}", e);
+        Ok(())
+    }
+
+    /// Mounts a path with the driver named `driver` (such as `"zip"` for an archive that doesn't
+    /// have the `.zip` extension) instead of picking one from the source
+    pub fn mount_with(&mut self, target: &str, source: &str, driver: &str) -> Result<(), VfsError> {
+        let (source, driver) = self.create_named_driver(driver, source)?;
+
+        self.mounts.push(Mount {
+            target: target.into(),
+            source,
+            driver: Arc::new(driver),
+        });
+
+        Ok(())
+    }
+
+    /// Mounts a path the same way as `mount` but decrypts all files in it with keys from `keys`.
+    /// See `EncryptedFs` for details.
+    #[cfg(feature = "encrypted-fs")]
+    pub fn mount_encrypted(
+        &mut self,
+        target: &str,
+        source: &str,
+        keys: Arc<dyn KeyProvider>,
+    ) -> Result<(), VfsError> {
+        let (source, driver) = self.create_driver(target, source)?;
+
+        self.mounts.push(Mount {
+            target: target.into(),
+            source,
+            driver: Arc::new(Box::new(EncryptedFs::new(driver, keys))),
+        });
+
+        Ok(())
+    }
+
+    /// Mounts a path the same way as `mount` but verifies every loaded file against
+    /// `manifest`. See `VerifiedFs` for details.
+    #[cfg(feature = "manifest")]
+    pub fn mount_verified(
+        &mut self,
+        target: &str,
+        source: &str,
+        manifest: Arc<Manifest>,
+    ) -> Result<(), VfsError> {
+        let (source, driver) = self.create_driver(target, source)?;
+
+        self.mounts.push(Mount {
+            target: target.into(),
+            source,
+            driver: Arc::new(Box::new(VerifiedFs::new(driver, manifest))),
+        });
+
+        Ok(())
+    }
+
+    /// Mounts `patch` over `base` at `target`. Files in the patch replace the ones in the base
+    /// and deltas in the patch are applied to the base files. See `PatchFs` for details.
+    #[cfg(feature = "patch-fs")]
+    pub fn mount_patch(&mut self, target: &str, base: &str, patch: &str) -> Result<(), VfsError> {
+        let (_, base_driver) = self.create_driver(target, base)?;
+        let (source, patch_driver) = self.create_driver(target, patch)?;
+
+        self.mounts.push(Mount {
+            target: target.into(),
+            source,
+            driver: Arc::new(Box::new(PatchFs::new(base_driver, patch_driver))),
+        });
+
+        Ok(())
+    }
+
+    /// Mounts everything described in the TOML or JSON file at `path`, picking the format from
+    /// the extension. Each mount has a `target` and a `source` and optionally a `driver`, a
+    /// `priority`, `read_only`, `platforms` and `options` such as HTTP `headers` or
+    /// `case_insensitive`:
+    ///
+    /// ```toml
+    /// [[mount]]
+    /// target = "/data"
+    /// source = "data.zip"
+    /// priority = 10
+    /// options = { case_insensitive = true }
+    /// ```
+    ///
+    /// Relative sources are relative to the file. Mounts with a higher priority are searched
+    /// first. Nothing is mounted if any entry is invalid, and all errors are returned with
+    /// their lines in `VfsError::InvalidConfig`.
+    #[cfg(feature = "config")]
+    pub fn mount_from_config(&mut self, path: &str) -> Result<(), VfsError> {
+        let format = ConfigFormat::from_path(path).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("`{}` isn't a .toml or .json file", path),
+            )
+        })?;
+
+        let text = std::fs::read_to_string(path)?;
+        let base_dir = std::path::Path::new(path).parent();
+        let mounts = config::create_mounts(self, &text, format, base_dir)?;
+
+        self.mounts.extend(mounts);
+        Ok(())
+    }
+
+    /// Mounts everything described in `text` the same way as `mount_from_config`. Relative
+    /// sources are relative to the current directory.
+    #[cfg(feature = "config")]
+    pub fn mount_from_config_str(
+        &mut self,
+        text: &str,
+        format: ConfigFormat,
+    ) -> Result<(), VfsError> {
+        let mounts = config::create_mounts(self, text, format, None)?;
+
+        self.mounts.extend(mounts);
+        Ok(())
+    }
+
+    /// Mounts an already created driver, such as a `MemoryFs`, in the virtual file system
+    pub fn mount_driver(&mut self, target: &str, driver: Box<dyn VfsDriver>) {
+        self.mounts.push(Mount {
+            target: target.into(),
+            source: String::new(),
+            driver: Arc::new(driver),
+        });
+    }
+
+    /// Returns the type, size and modification time of a file or directory. Files inside
+    /// archives that are themselves inside a mount aren't supported.
+    pub fn metadata(&self, path: &str) -> Result<Metadata, VfsError> {
+        let (mount, file_path) = find_mount(path, &self.mounts)?;
+        Ok(mount.driver.metadata(file_path)?)
+    }
+
+    /// Writes a file to the first mount of its directory that supports writing. Parent
+    /// directories may have to be created first depending on the driver.
+    pub fn write_file(&self, path: &str, data: impl Into<Buffer>) -> Result<(), VfsError> {
+        let data = data.into();
+        self.write_to_mount(path, |driver, path| driver.write_file(path, data.clone()))
+    }
+
+    /// Creates a directory in the first mount of its parent that supports writing
+    pub fn create_dir(&self, path: &str) -> Result<(), VfsError> {
+        self.write_to_mount(path, |driver, path| driver.create_dir(path))
+    }
+
+    /// Removes a file or directory from the mount it's loaded from
+    pub fn remove(&self, path: &str) -> Result<(), VfsError> {
+        let (mount, file_path) = find_mount(path, &self.mounts)?;
+        Ok(mount.driver.remove(file_path)?)
+    }
+
+    /// Renames a file or directory. Both paths have to be in the same mount.
+    pub fn rename(&self, from: &str, to: &str) -> Result<(), VfsError> {
+        let (mount, from_path) = find_mount(from, &self.mounts)?;
+
+        if !Path::new(to).starts_with(&mount.target) {
+            return Err(VfsError::CrossMountRename {
+                from: from.into(),
+                to: to.into(),
+            });
+        }
+
+        let to_path = mount_relative_path(&mount.target, to);
+        Ok(mount.driver.rename(from_path, to_path)?)
+    }
+
+    /// Runs `write` on the mounts that overlay the parent of `path` until one isn't read-only
+    fn write_to_mount<F>(&self, path: &str, write: F) -> Result<(), VfsError>
+    where
+        F: Fn(&ArcDriver, &str) -> Result<(), InternalError>,
+    {
+        let dir = Path::new(path)
+            .parent()
+            .map_or(Cow::Borrowed(""), |p| p.to_string_lossy());
+        let candidates = get_mounts_for_dir(&dir, &self.mounts);
+
+        if candidates.is_empty() {
+            return Err(VfsError::NoMountFound { path: path.into() });
+        }
+
+        for index in candidates {
+            let mount = &self.mounts[index];
+
+            match write(&mount.driver, mount_relative_path(&mount.target, path)) {
+                Err(InternalError::ReadOnly { .. }) => (),
+                res => return Ok(res?),
+            }
+        }
+
+        Err(VfsError::ReadOnly { path: path.into() })
+    }
+
+    /// TODO: Error handling, etc, correct path, etc
+    pub fn load_file(&self, path: &str) -> Handle {
+        self.load_file_with_mode(path, LoadMode::Read)
+    }
+
+    /// Loads a file by memory mapping it when the driver supports it. This avoids
+    /// allocating and copying large files (such as video or audio banks) as the returned
+    /// buffer borrows the mapping directly. Drivers that can't map fall back to reading.
+    ///
+    /// The file must not be changed while the buffer (or a slice of it) is alive. If another
+    /// process truncates the file, reading the missing part raises SIGBUS and crashes the
+    /// program, and if it's modified the data in the buffer changes. Use `load_file` for files
+    /// that may be written to while they are in use.
+    pub fn load_file_mapped(&self, path: &str) -> Handle {
+        self.load_file_with_mode(path, LoadMode::Mmap)
+    }
+
+    /// Loads a file and delivers the data as `RecvMsg::Chunk` messages while it's being read
+    /// followed by `RecvMsg::StreamDone`. This allows things like audio playback or progressive
+    /// image decoding to start before the whole file has been loaded.
+    pub fn load_file_streaming(&self, path: &str) -> Handle {
+        self.load_file_with_mode(path, LoadMode::Stream)
+    }
+
+    fn load_file_with_mode(&self, path: &str, mode: LoadMode) -> Handle {
+        let mounts = self.mounts.clone();
+        let drivers = self.registry.drivers();
+        let (thread_send, main_recv) = unbounded::<RecvMsg>();
+
+        self.main_send
+            .send(SendMsg::LoadFile(
+                path.into(),
+                mode,
+                mounts,
+                drivers,
+                MsgSender::new(thread_send, self.progress_granularity),
+            ))
+            .unwrap();
+
+        Handle { recv: main_recv }
+    }
+
+    /// Starts loading a group of files and returns a handle that tracks all of them. This is
+    /// useful for loading screens where a single progress bar is wanted for many files.
+    pub fn load_batch<I, S>(&self, paths: I, mode: BatchMode) -> BatchHandle
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let loads = paths
+            .into_iter()
+            .map(|path| {
+                let path = path.as_ref();
+                (path.to_owned(), self.load_file(path))
+            })
+            .collect();
+
+        BatchHandle::new(loads, mode)
+    }
+
+    /// Returns a handle that only sees the files below `prefix`, with paths relative to it.
+    /// This is meant for plugins and mods that should only have access to their own folder.
+    pub fn view(&self, prefix: &str) -> EvfsView<'_> {
+        EvfsView::new(self, prefix)
+    }
+
+    /// Returns a recursive walk of the virtual directory `dir` over all mounts that overlay it.
+    /// Use the methods on `Walk` to filter it and `Walk::entries` to run it.
+    pub fn walk(&self, dir: &str) -> Walk<'_> {
+        Walk::new(&self.mounts, dir)
+    }
+
+    /// Returns the virtual paths of all files and directories that match a glob pattern such
+    /// as `/assets/**/*.png`. See `Walk` for how overlapping mounts are handled.
+    pub fn glob(&self, pattern: &str) -> Result<Vec<String>, VfsError> {
+        walk::glob(&self.mounts, pattern)
+    }
+
+    /// Watches for changes to files matching `pattern`, which is either a path (everything at
+    /// or below it is watched) or a glob such as `/assets/**/*.png`. Changes are sent with
+    /// their virtual paths. Directory mounts are watched natively when possible while archive
+    /// mounts are polled for being replaced on disk. Remote mounts can't be watched.
+    #[cfg(feature = "watch")]
+    pub fn watch(&self, pattern: &str) -> Result<WatchHandle, VfsError> {
+        let mounts: Vec<(String, String)> = self
+            .mounts
+            .iter()
+            .filter(|m| !m.driver.is_remote() && !m.source.is_empty())
+            .filter(|m| watch::mount_matches(pattern, &m.target))
+            .map(|m| {
+                // Directories inside archives are watched through the archive
+                let (source, _) = sub_dir_fs::split_sub_path(&m.source);
+                (m.target.clone(), source.to_owned())
+            })
+            .collect();
+
+        if mounts.is_empty() {
+            return Err(VfsError::NoMountFound {
+                path: pattern.to_owned(),
+            });
+        }
+
+        Ok(watch::watch(pattern, mounts)?)
+    }
+
+    /// Loads `len` bytes starting at `offset` of a file. This is useful for reading headers or
+    /// tables of contents without loading the whole file. If the range goes past the end of
+    /// the file only the data up to the end is returned.
+    pub fn load_range(&self, path: &str, offset: u64, len: u64) -> Handle {
+        let mounts = self.mounts.clone();
+        let drivers = self.registry.drivers();
+        let (thread_send, main_recv) = unbounded::<RecvMsg>();
+
+        self.main_send
+            .send(SendMsg::LoadRange(
+                path.into(),
+                offset,
+                len,
+                mounts,
+                drivers,
+                MsgSender::new(thread_send, self.progress_granularity),
+            ))
+            .unwrap();
+
+        Handle { recv: main_recv }
+    }
+
+    /// Opens a file for streaming. Instead of loading the whole file into memory the handle
+    /// receives a `Read + Seek` stream backed by the driver, which makes it possible to read
+    /// large files incrementally.
+    pub fn open(&self, path: &str) -> StreamHandle {
+        let mounts = self.mounts.clone();
+        let drivers = self.registry.drivers();
+        let (thread_send, main_recv) = unbounded();
+
+        self.main_send
+            .send(SendMsg::OpenFile(path.into(), mounts, drivers, thread_send))
+            .unwrap();
+
+        StreamHandle { recv: main_recv }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Buffer, Handle, RecvMsg, VfsError};
+
+    /// Waits for a load to finish and returns the data
+    #[allow(dead_code)]
+    fn wait_for_data(handle: &Handle) -> Buffer {
+        loop {
+            match handle
+                .recv
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .expect("timed out waiting for data")
+            {
+                RecvMsg::ReadDone(data) => return data,
+                RecvMsg::Error(e) => panic!("main: error {:#?}", e),
+                _ => (),
+            }
+        }
+    }
+
+    /// Waits for a load to fail, skipping progress updates
+    #[allow(dead_code)]
+    fn wait_for_error(handle: &Handle) -> VfsError {
+        loop {
+            match handle
+                .recv
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .expect("timed out waiting for error")
+            {
+                RecvMsg::Error(e) => return e,
+                RecvMsg::ReadDone(_) => panic!("main: expected an error"),
+                _ => (),
+            }
+        }
+    }
+
+    /// Creates an empty directory for a test in the system temp directory
+    #[allow(dead_code)]
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("evfs_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Checks that a driver behaves like the reference implementation `MemoryFs`. `files` are
+    /// all files in the driver with their content and `dirs` all directories.
+    #[allow(dead_code)]
+    fn check_driver_conformance(
+        driver: &dyn super::VfsDriver,
+        files: &[(&str, &[u8])],
+        dirs: &[&str],
+    ) {
+        use super::*;
+        use std::collections::BTreeMap;
+        use std::io::{Read, Seek, SeekFrom};
+
+        let (send, recv) = crossbeam_channel::unbounded();
+        let msg = MsgSender::new(send.clone(), 16);
+
+        let parent = |path: &str| path.rfind('/').map_or("", |end| &path[..end]).to_owned();
+        let name = |path: &str| path.rsplit('/').next().unwrap().to_owned();
+
+        assert_eq!(driver.has_entry(""), EntryType::Directory);
+        assert_eq!(driver.has_entry("not_found.txt"), EntryType::NotFound);
+        assert!(driver.metadata("not_found.txt").is_err());
+        assert!(driver.load_file("not_found.txt", &msg).is_err());
+
+        for (path, data) in files {
+            let len = data.len() as u64;
+            // A new sender for each file as progress is tracked per load
+            let msg = MsgSender::new(send.clone(), 16);
+
+            assert_eq!(driver.has_entry(path), EntryType::File, "{}", path);
+            assert_eq!(driver.metadata(path).unwrap().entry_type, EntryType::File);
+            assert_eq!(driver.metadata(path).unwrap().len, len);
+
+            assert_eq!(&driver.load_file(path, &msg).unwrap()[..], *data);
+            assert_eq!(&driver.map_file(path, &msg).unwrap()[..], *data);
+
+            // The last progress update is always for the whole file
+            let last_progress = recv.try_iter().fold(None, |last, msg| match msg {
+                RecvMsg::ReadProgress(p) => Some(p),
+                _ => last,
+            });
+            assert_eq!(last_progress.unwrap().bytes_done, len);
+
+            let mut stream = driver.open(path).unwrap();
+            assert_eq!(stream.seek(SeekFrom::End(0)).unwrap(), len);
+            stream.seek(SeekFrom::Start(len / 2)).unwrap();
+            let mut tail = Vec::new();
+            stream.read_to_end(&mut tail).unwrap();
+            assert_eq!(tail, &data[data.len() / 2..]);
+
+            let range = driver.load_range(path, 1, 4, &msg).unwrap();
+            assert_eq!(&range[..], &data[data.len().min(1)..data.len().min(5)]);
+            assert!(driver
+                .load_range(path, len + 10, 4, &msg)
+                .unwrap()
+                .is_empty());
+
+            driver.stream_file(path, &msg).unwrap();
+            let mut streamed = Vec::new();
+            for msg in recv.try_iter() {
+                if let RecvMsg::Chunk { offset, data } = msg {
+                    assert_eq!(offset, streamed.len() as u64);
+                    streamed.extend_from_slice(&data);
+                }
+            }
+            assert_eq!(streamed, *data);
+        }
+
+        for dir in dirs.iter().chain(&[""]) {
+            assert_eq!(driver.has_entry(dir), EntryType::Directory, "{}", dir);
+            assert_eq!(
+                driver.metadata(dir).unwrap().entry_type,
+                EntryType::Directory
+            );
+
+            let listed: BTreeMap<String, EntryType> = driver
+                .read_dir(dir)
+                .unwrap()
+                .into_iter()
+                .map(|e| (e.name, e.entry_type))
+                .collect();
+
+            let expected: BTreeMap<String, EntryType> = files
+                .iter()
+                .map(|(path, _)| (*path, EntryType::File))
+                .chain(dirs.iter().map(|path| (*path, EntryType::Directory)))
+                .filter(|(path, _)| parent(path) == *dir)
+                .map(|(path, entry_type)| (name(path), entry_type))
+                .collect();
+
+            assert_eq!(listed, expected, "listing of {:?}", dir);
+        }
+    }
+
+    #[test]
+    fn buffer_slice() {
+        use super::*;
+
+        let buffer = Buffer::from(b"hello world".to_vec());
+        let world = buffer.slice(6..);
+        let clone = world.clone();
+
+        assert_eq!(&world[..], b"world");
+        assert_eq!(clone.as_ptr(), world.as_ptr());
+        assert_eq!(world.slice(1..3), Buffer::from_static(b"or"));
+        assert!(buffer.slice(3..3).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "local-fs")]
+    fn load_local_file() {
+        use super::*;
+        use std::{thread, time};
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/test", "").unwrap();
+        let handle = vfs.load_file("/test/Cargo.toml");
+
+        for _ in 0..10 {
+            if let Ok(data) = handle.recv.try_recv() {
+                match data {
+                    RecvMsg::ReadProgress(p) => println!("ReadProgress {:?}", p),
+                    RecvMsg::ReadDone(_data) => {
+                        println!("File read done!");
+                    }
+
+                    RecvMsg::Error(e) => {
+                        panic!("main: error {:#?}", e);
+                    }
+
+                    _ => (),
+                }
+            }
+
+            thread::sleep(time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "zip-fs")]
+    fn load_zip_file() {
+        use super::*;
+        use hex_literal::hex;
+        use sha1::*;
+        use std::{thread, time};
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/data", "data/test_data.zip").unwrap();
+        let handle = vfs.load_file("/data/text.txt");
+        let mut file_done = false;
+
+        for _ in 0..10 {
+            if let Ok(data) = handle.recv.try_recv() {
+                match data {
+                    RecvMsg::ReadProgress(p) => println!("ReadProgress {:?}", p),
+                    RecvMsg::ReadDone(data) => {
+                        let mut hasher = Sha1::new();
+                        hasher.update(data);
+                        let hash = hasher.finalize();
+                        assert_eq!(hash[..], hex!("afa6fc4177cd134e532257de55ad71c9a454b640"));
+                        file_done = true;
+                    }
+
+                    RecvMsg::Error(e) => {
+                        panic!("main: error {:#?}", e);
+                    }
+
+                    _ => (),
+                }
+            }
+
+            thread::sleep(time::Duration::from_millis(10));
+        }
+
+        // Make sure we actually got the data
+        assert!(file_done);
+    }
+
+    #[test]
+    #[cfg(all(feature = "embedded-fs", feature = "local-fs"))]
+    fn embedded_files() {
+        use super::embedded_fs::{EmbedBuilder, EmbeddedDir, EmbeddedFile};
+        use super::*;
+        use std::io::{Read, Write};
+
+        let text = std::fs::read("data/text.txt").unwrap();
+        let len = text.len() as u64;
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&text).unwrap();
+        let packed: &'static [u8] = Box::leak(encoder.finish().unwrap().into_boxed_slice());
+        let unpacked: &'static [u8] = Box::leak(text.clone().into_boxed_slice());
+
+        // Same layout as the tables generated by EmbedBuilder
+        let files = vec![
+            EmbeddedFile {
+                path: "docs/text.txt",
+                data: packed,
+                compressed: true,
+                len,
+            },
+            EmbeddedFile {
+                path: "text.txt",
+                data: unpacked,
+                compressed: false,
+                len,
+            },
+        ];
+        let dir: &'static EmbeddedDir = Box::leak(Box::new(EmbeddedDir {
+            root: "data",
+            files: Box::leak(files.into_boxed_slice()),
+        }));
+
+        check_driver_conformance(
+            &EmbeddedFs::new(dir),
+            &[("docs/text.txt", &text), ("text.txt", &text)],
+            &["docs"],
+        );
+
+        let mut vfs = Evfs::new();
+        vfs.install_driver(Arc::new(Box::new(EmbeddedFs::new(dir).prefer_disk(false))));
+        vfs.install_driver(Arc::new(Box::new(
+            EmbeddedFs::with_name("disk", dir).prefer_disk(true),
+        )));
+        vfs.mount("/builtin", "embedded://").unwrap();
+        vfs.mount("/disk", "embedded://disk").unwrap();
+
+        // Uncompressed files point directly to the embedded data
+        let data = wait_for_data(&vfs.load_file("/builtin/text.txt"));
+        assert_eq!(data.as_ptr(), unpacked.as_ptr());
+        let data = wait_for_data(&vfs.load_file("/builtin/docs/text.txt"));
+        assert_eq!(&data[..], &text[..]);
+
+        // Preferring the disk mounts the directory the files came from with the same paths
+        let data = wait_for_data(&vfs.load_file("/disk/text.txt"));
+        assert_eq!(&data[..], &text[..]);
+        assert!(vfs.metadata("/disk/test_data.zip").is_ok());
+        assert!(vfs.metadata("/builtin/test_data.zip").is_err());
+
+        let out = temp_dir("embed");
+        EmbedBuilder::new("data")
+            .name("ASSETS")
+            .compress(true)
+            .write(out.join("assets.rs"))
+            .unwrap();
+
+        let source = std::fs::read_to_string(out.join("assets.rs")).unwrap();
+        let file_count = vfs.walk("/disk").files_only().entries().unwrap().len();
+        assert!(source.contains("pub static ASSETS: ::evfs::embedded_fs::EmbeddedDir"));
+        assert!(source.contains("path: \"text.txt\""));
+        assert_eq!(source.matches("include_bytes!").count(), file_count);
+
+        // text.txt compresses well so it's included from the compressed copy
+        let line = source.lines().find(|l| l.contains("\"text.txt\"")).unwrap();
+        assert!(line.contains("compressed: true"));
+        let packed_path = line.split("include_bytes!(\"").nth(1).unwrap();
+        let packed_path = &packed_path[..packed_path.find('"').unwrap()];
+        let mut unpacked = Vec::new();
+        flate2::read::DeflateDecoder::new(std::fs::File::open(packed_path).unwrap())
+            .read_to_end(&mut unpacked)
+            .unwrap();
+        assert_eq!(unpacked, text);
+    }
+
+    #[test]
+    #[cfg(feature = "memory-fs")]
+    fn memory_fs() {
+        use super::*;
+
+        let fs = MemoryFs::new();
+        fs.insert("text.txt", b"hello memory".to_vec()).unwrap();
+        fs.insert("gen/level/map.bin", vec![7u8; 1000]).unwrap();
+        fs.insert("gen/empty.bin", Buffer::new()).unwrap();
+        fs.create_dir("gen/nothing").unwrap();
+
+        check_driver_conformance(
+            &fs,
+            &[
+                ("text.txt", b"hello memory"),
+                ("gen/level/map.bin", &[7u8; 1000]),
+                ("gen/empty.bin", b""),
+            ],
+            &["gen", "gen/level", "gen/nothing"],
+        );
+
+        // Files can't be written over directories or inside files
+        assert!(fs.insert("gen", b"x".to_vec()).is_err());
+        assert!(fs.insert("text.txt/x", b"x".to_vec()).is_err());
+
+        // Clones share the files so changes show up in mounted copies
+        let mut vfs = Evfs::new();
+        vfs.mount_driver("/mem", Box::new(fs.clone()));
+
+        vfs.write_file("/mem/gen/new.txt", b"new".to_vec()).unwrap();
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/mem/gen/new.txt"))[..],
+            b"new"
+        );
+        assert_eq!(fs.has_entry("gen/new.txt"), EntryType::File);
+        assert_eq!(vfs.metadata("/mem/gen/new.txt").unwrap().len, 3);
+
+        vfs.remove("/mem/gen").unwrap();
+        assert_eq!(fs.has_entry("gen/level/map.bin"), EntryType::NotFound);
+        assert!(matches!(
+            vfs.metadata("/mem/gen"),
+            Err(VfsError::PathNotFound { .. })
+        ));
+
+        // Directories are renamed with everything in them
+        fs.insert("a/b.txt", b"b".to_vec()).unwrap();
+        fs.rename("a", "c").unwrap();
+        assert_eq!(fs.has_entry("c/b.txt"), EntryType::File);
+        assert_eq!(fs.has_entry("a"), EntryType::NotFound);
+
+        // but not into themselves, which would lose the files
+        assert!(fs.rename("c", "c").is_err());
+        assert!(fs.rename("c", "c/d").is_err());
+        assert!(fs.rename("c", "c/d/e").is_err());
+        assert_eq!(fs.has_entry("c/b.txt"), EntryType::File);
+
+        fs.insert("cd.txt", b"cd".to_vec()).unwrap();
+        fs.rename("c", "cd").unwrap();
+        assert_eq!(fs.has_entry("cd/b.txt"), EntryType::File);
+    }
+
+    #[test]
+    #[cfg(all(feature = "local-fs", feature = "zip-fs", feature = "tar-fs"))]
+    fn driver_conformance() {
+        use super::*;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+        let dir = temp_dir("conformance");
+        std::fs::create_dir_all(dir.join("sub/empty")).unwrap();
+        std::fs::write(dir.join("text.txt"), &text).unwrap();
+        std::fs::write(dir.join("sub/small.bin"), b"abc").unwrap();
+
+        let local = LocalFs::new().new_from_path(dir.to_str().unwrap()).unwrap();
+        check_driver_conformance(
+            local.as_ref(),
+            &[("text.txt", &text), ("sub/small.bin", b"abc")],
+            &["sub", "sub/empty"],
+        );
+
+        for zip in &["data/test_data.zip", "data/test_data_stored.zip"] {
+            let zip = ZipFs::new().new_from_path(zip).unwrap();
+            check_driver_conformance(zip.as_ref(), &[("text.txt", &text)], &[]);
+        }
+
+        for tar in &[
+            "data/test_data.tar",
+            "data/test_data.tar.gz",
+            "data/test_data.tar.zst",
+        ] {
+            let tar = TarFs::new().new_from_path(tar).unwrap();
+            check_driver_conformance(
+                tar.as_ref(),
+                &[("text.txt", &text), ("docs/text.txt", &text)],
+                &["docs"],
+            );
+        }
+
+        #[cfg(feature = "sevenz-fs")]
+        {
+            let sevenz = SevenZipFs::new()
+                .new_from_path("data/test_data.7z")
+                .unwrap();
+            check_driver_conformance(
+                sevenz.as_ref(),
+                &[("text.txt", &text), ("docs/text.txt", &text)],
+                &["docs"],
+            );
+        }
+
+        #[cfg(all(feature = "xz-fs", feature = "bzip2-fs"))]
+        for file in &["data/text.txt.xz", "data/text.txt.bz2"] {
+            let compressed = CompressedFs::new().new_from_path(file).unwrap();
+            check_driver_conformance(compressed.as_ref(), &[("text.txt", &text)], &[]);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "memory-fs", feature = "zip-fs"))]
+    fn write_to_overlays() {
+        use super::*;
+
+        let fs = MemoryFs::new();
+        let mut vfs = Evfs::new();
+        vfs.mount("/data", "data/test_data.zip").unwrap();
+        vfs.mount_driver("/data", Box::new(fs.clone()));
+
+        // The zip is read-only so the write goes to the memory mount below it
+        vfs.write_file("/data/save.txt", b"saved".to_vec()).unwrap();
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/data/save.txt"))[..],
+            b"saved"
+        );
+        assert_eq!(vfs.metadata("/data/text.txt").unwrap().len, 1369);
+
+        assert!(matches!(
+            vfs.remove("/data/text.txt"),
+            Err(VfsError::ReadOnly { .. })
+        ));
+        assert!(matches!(
+            vfs.write_file("/other/save.txt", b"x".to_vec()),
+            Err(VfsError::NoMountFound { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "zip-fs")]
+    fn write_zip_files() {
+        use super::*;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+        let dir = temp_dir("zip_writes");
+        let filename = dir.join("save.zip");
+        let filename = filename.to_str().unwrap();
+        std::fs::copy("data/test_data.zip", filename).unwrap();
+
+        let zip = ZipFs::writable(filename).unwrap();
+        let mut vfs = Evfs::new();
+        vfs.mount_driver("/save", Box::new(zip.clone()));
+
+        // Changes are visible before they are committed
+        vfs.write_file("/save/slot1/state.bin", b"state".to_vec())
+            .unwrap();
+        vfs.create_dir("/save/empty").unwrap();
+        vfs.rename("/save/text.txt", "/save/docs/renamed.txt")
+            .unwrap();
+        assert!(zip.has_changes());
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/save/slot1/state.bin"))[..],
+            b"state"
+        );
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/save/docs/renamed.txt"))[..],
+            &text[..]
+        );
+        assert!(matches!(
+            vfs.metadata("/save/text.txt"),
+            Err(VfsError::PathNotFound { .. })
+        ));
+
+        // The archive on disk isn't changed until the commit
+        let on_disk = ZipFs::new().new_from_path(filename).unwrap();
+        assert_eq!(on_disk.has_entry("text.txt"), EntryType::File);
+        assert_eq!(on_disk.has_entry("slot1/state.bin"), EntryType::NotFound);
+
+        zip.commit().unwrap();
+        assert!(!zip.has_changes());
+        assert!(!std::path::Path::new(&format!("{}.tmp", filename)).exists());
+
+        let committed = ZipFs::new().new_from_path(filename).unwrap();
+        check_driver_conformance(
+            committed.as_ref(),
+            &[("docs/renamed.txt", &text), ("slot1/state.bin", b"state")],
+            &["docs", "empty", "slot1"],
+        );
+
+        // Uncommitted changes can be thrown away
+        vfs.remove("/save/slot1").unwrap();
+        assert_eq!(zip.has_entry("slot1/state.bin"), EntryType::NotFound);
+        zip.discard();
+        assert_eq!(zip.has_entry("slot1/state.bin"), EntryType::File);
+
+        // New archives are created on the first commit
+        let new_filename = dir.join("new.zip");
+        let new_zip = ZipFs::writable(new_filename.to_str().unwrap()).unwrap();
+        assert_eq!(new_zip.read_dir("").unwrap(), vec![]);
+        new_zip
+            .write_file("a.txt", Buffer::from(b"a".to_vec()))
+            .unwrap();
+        new_zip.commit().unwrap();
+        let mut archive =
+            zip::ZipArchive::new(std::fs::File::open(&new_filename).unwrap()).unwrap();
+        assert_eq!(archive.by_name("a.txt").unwrap().size(), 1);
+
+        assert!(matches!(
+            vfs.rename("/save/docs/renamed.txt", "/other/text.txt"),
+            Err(VfsError::CrossMountRename { .. })
+        ));
+        assert!(matches!(
+            ZipFs::new()
+                .new_from_path("data/test_data.zip")
+                .unwrap()
+                .write_file("x", Buffer::new()),
+            Err(InternalError::ReadOnly { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "zip-fs", feature = "tar-fs"))]
+    fn encrypted_zip_files() {
+        use super::*;
+        use std::io::Read;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/crypto", "data/encrypted/test_data_zipcrypto.zip")
+            .unwrap();
+        vfs.mount_driver(
+            "/aes",
+            Box::new(
+                ZipFs::open("data/encrypted/test_data_aes.zip")
+                    .unwrap()
+                    .password("secret"),
+            ),
+        );
+
+        // The password of the AES archive was given at mount time
+        for path in &["/aes/text.txt", "/aes/docs/text.txt"] {
+            assert_eq!(&wait_for_data(&vfs.load_file(path))[..], &text[..]);
+            assert_eq!(&wait_for_data(&vfs.load_file_mapped(path))[..], &text[..]);
+        }
+
+        let mut stream = vfs.open("/aes/docs/text.txt").recv.recv().unwrap().unwrap();
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        assert_eq!(data, text);
+
+        match vfs.load_file("/crypto/text.txt").recv.recv().unwrap() {
+            RecvMsg::Error(VfsError::InvalidPassword { .. }) => (),
+            _ => panic!("expected InvalidPassword error"),
+        }
+
+        vfs.set_password_provider(Arc::new(|archive: &str, path: &str| {
+            assert!(archive.ends_with("test_data_zipcrypto.zip"));
+            match path {
+                "text.txt" => Some(b"secret".to_vec()),
+                _ => Some(b"wrong".to_vec()),
+            }
+        }));
+
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/crypto/text.txt"))[..],
+            &text[..]
+        );
+        match vfs.load_file("/crypto/docs/text.txt").recv.recv().unwrap() {
+            RecvMsg::Error(VfsError::InvalidPassword { .. }) => (),
+            _ => panic!("expected InvalidPassword error"),
+        }
+
+        // Archives inside other mounts ask the provider with their path in the mount
+        let nested_dir = temp_dir("nested_encryption");
+        let nested = nested_dir.join("nested.tar");
+        let mut builder = tar::Builder::new(std::fs::File::create(&nested).unwrap());
+        builder
+            .append_path_with_name(
+                "data/encrypted/test_data_zipcrypto.zip",
+                "test_data_zipcrypto.zip",
+            )
+            .unwrap();
+        builder.finish().unwrap();
+
+        vfs.mount("/nested", nested.to_str().unwrap()).unwrap();
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/nested/test_data_zipcrypto.zip/text.txt"))[..],
+            &text[..]
+        );
+
+        // PKWARE strong encryption is reported as unsupported instead of a wrong password
+        let mut strong = std::fs::read("data/encrypted/test_data_zipcrypto.zip").unwrap();
+        strong[6] |= 1 << 6;
+        let dir = temp_dir("strong_encryption");
+        let strong_filename = dir.join("strong.zip");
+        std::fs::write(&strong_filename, strong).unwrap();
+
+        let strong = ZipFs::open(strong_filename.to_str().unwrap())
+            .unwrap()
+            .password("secret");
+        let (send, _recv) = crossbeam_channel::unbounded();
+        assert!(matches!(
+            strong.load_file(
+                "text.txt",
+                &MsgSender::new(send, DEFAULT_PROGRESS_GRANULARITY)
+            ),
+            Err(InternalError::UnsupportedEncryption { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "encrypted-fs", feature = "memory-fs", feature = "tar-fs"))]
+    fn encrypted_files() {
+        use super::*;
+        use encrypted_fs::{encrypt, Key};
+
+        let text = std::fs::read("data/text.txt").unwrap();
+        let tar = std::fs::read("data/test_data.tar").unwrap();
+        let old_key: Key = [1; 32];
+        let new_key: Key = [2; 32];
+        let keys: Arc<dyn KeyProvider> = Arc::new(move |key_id: &str, _: &str| match key_id {
+            "old" => Some(old_key),
+            "new" => Some(new_key),
+            _ => None,
+        });
+
+        // Files encrypted by a packaging tool with different keys and ciphers
+        let dir = temp_dir("encrypted_files");
+        let write = |name: &str, data: Vec<u8>| std::fs::write(dir.join(name), data).unwrap();
+        write(
+            "text.txt",
+            encrypt(Cipher::Aes256Gcm, "old", &old_key, &text).unwrap(),
+        );
+        write(
+            "data.tar",
+            encrypt(Cipher::ChaCha20Poly1305, "new", &new_key, &tar).unwrap(),
+        );
+        write(
+            "unknown.txt",
+            encrypt(Cipher::Aes256Gcm, "lost", &[3; 32], &text).unwrap(),
+        );
+        write("plain.txt", text.clone());
+
+        let mut tampered = encrypt(Cipher::ChaCha20Poly1305, "new", &new_key, &text).unwrap();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        write("tampered.txt", tampered);
+
+        let mut vfs = Evfs::new();
+        vfs.mount_encrypted("/enc", dir.to_str().unwrap(), keys.clone())
+            .unwrap();
+
+        // Progress is in bytes of the encrypted file and never goes backwards
+        let handle = vfs.load_file("/enc/text.txt");
+        let mut updates = Vec::new();
+        let data = loop {
+            match handle.recv.recv().unwrap() {
+                RecvMsg::ReadProgress(p) => updates.push(p),
+                RecvMsg::ReadDone(data) => break data,
+                RecvMsg::Error(e) => panic!("main: error {:#?}", e),
+                _ => (),
+            }
+        };
+        assert_eq!(&data[..], &text[..]);
+        let encrypted_len = std::fs::metadata(dir.join("text.txt")).unwrap().len();
+        assert_eq!(updates.last().unwrap().bytes_done, encrypted_len);
+        assert!(updates
+            .windows(2)
+            .all(|w| w[0].bytes_done <= w[1].bytes_done));
+
+        assert_eq!(
+            vfs.metadata("/enc/text.txt").unwrap().len,
+            text.len() as u64
+        );
+
+        // Archives are decrypted before they are opened
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/enc/data.tar/docs/text.txt"))[..],
+            &text[..]
+        );
+
+        match wait_for_error(&vfs.load_file("/enc/unknown.txt")) {
+            VfsError::KeyNotFound { key_id, .. } => assert_eq!(key_id, "lost"),
+            e => panic!("expected KeyNotFound error, got {:?}", e),
+        }
+
+        for path in &["/enc/plain.txt", "/enc/tampered.txt"] {
+            match wait_for_error(&vfs.load_file(path)) {
+                VfsError::AuthenticationFailed { .. } => (),
+                e => panic!(
+                    "expected AuthenticationFailed error for {}, got {:?}",
+                    path, e
+                ),
+            }
+        }
+
+        // Writes are encrypted before they reach the wrapped driver
+        let memory = MemoryFs::new();
+        let encrypted =
+            EncryptedFs::new(Box::new(memory.clone()), keys).encrypt_with("new", Cipher::Aes256Gcm);
+        vfs.mount_driver("/mem", Box::new(encrypted));
+        vfs.write_file("/mem/text.txt", text.clone()).unwrap();
+
+        let (send, _recv) = crossbeam_channel::unbounded();
+        let stored = memory
+            .load_file(
+                "text.txt",
+                &MsgSender::new(send, DEFAULT_PROGRESS_GRANULARITY),
+            )
+            .unwrap();
+        assert!(!stored.windows(16).any(|w| w == &text[..16]));
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/mem/text.txt"))[..],
+            &text[..]
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[cfg(all(feature = "manifest", feature = "local-fs", feature = "tar-fs"))]
+    fn verified_files() {
+        use super::*;
+        use manifest::public_key;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+        let tar = std::fs::read("data/test_data.tar").unwrap();
+        let secret_key = [7; 32];
+
+        let dir = temp_dir("verified_files");
+        std::fs::write(dir.join("text.txt"), &text).unwrap();
+        std::fs::write(dir.join("data.tar"), &tar).unwrap();
+        std::fs::write(dir.join("unlisted.txt"), &text).unwrap();
+
+        for &algorithm in &[HashAlgorithm::Sha256, HashAlgorithm::Blake3] {
+            let mut manifest = Manifest::new(algorithm);
+            manifest.add("text.txt", &text);
+            manifest.add("/data.tar", &tar);
+            manifest.add("corrupted.txt", &text);
+
+            // The manifest is shipped with its signature
+            let data = manifest.to_bytes();
+            let signature = manifest.sign(&secret_key);
+            let parsed =
+                Manifest::parse_signed(&data, &signature, &public_key(&secret_key)).unwrap();
+            assert_eq!(parsed, manifest);
+
+            let mut tampered = data.clone();
+            tampered[30] ^= 1;
+            assert!(matches!(
+                Manifest::parse_signed(&tampered, &signature, &public_key(&secret_key)),
+                Err(VfsError::InvalidSignature {})
+            ));
+            assert!(matches!(
+                Manifest::parse_signed(&data, &signature, &public_key(&[8; 32])),
+                Err(VfsError::InvalidSignature {})
+            ));
+
+            // Hashes must have the length of the algorithm
+            let header_end = data.iter().position(|&b| b == b'\n').unwrap() + 1;
+            let mut short_hash = data[..header_end].to_vec();
+            short_hash.extend_from_slice(b"abcd 5 short.txt\n");
+            assert!(Manifest::parse(&short_hash).is_err());
+
+            let mut corrupted = text.clone();
+            corrupted[100] ^= 1;
+            std::fs::write(dir.join("corrupted.txt"), &corrupted).unwrap();
+
+            let mut vfs = Evfs::new();
+            vfs.mount_verified("/verified", dir.to_str().unwrap(), Arc::new(parsed))
+                .unwrap();
+
+            for path in &["/verified/text.txt", "/verified/data.tar/docs/text.txt"] {
+                assert_eq!(&wait_for_data(&vfs.load_file(path))[..], &text[..]);
+                assert_eq!(&wait_for_data(&vfs.load_file_mapped(path))[..], &text[..]);
+            }
+
+            for path in &["/verified/corrupted.txt", "/verified/unlisted.txt"] {
+                match wait_for_error(&vfs.load_file(path)) {
+                    VfsError::VerificationFailed { .. } => (),
+                    e => panic!(
+                        "expected VerificationFailed error for {}, got {:?}",
+                        path, e
+                    ),
+                }
+                assert!(matches!(
+                    vfs.open(path).recv.recv().unwrap(),
+                    Err(VfsError::VerificationFailed { .. })
+                ));
+            }
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[cfg(all(feature = "cas-fs", feature = "memory-fs"))]
+    fn cas_files() {
+        use super::*;
+        use manifest::HashAlgorithm;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+        let store = temp_dir("cas_files");
+
+        // Two versions that share most of their files
+        let v1 = MemoryFs::new();
+        v1.insert("text.txt", text.clone()).unwrap();
+        v1.insert("levels/1.bin", &b"level 1"[..]).unwrap();
+        v1.insert("levels/2.bin", &b"level 2"[..]).unwrap();
+        let v2 = MemoryFs::new();
+        v2.insert("text.txt", text.clone()).unwrap();
+        v2.insert("levels/1.bin", &b"level 1"[..]).unwrap();
+        v2.insert("levels/2.bin", &b"level 2 with fixes"[..])
+            .unwrap();
+        v2.insert("copy.txt", text.clone()).unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount_driver("/v1", Box::new(v1));
+        vfs.mount_driver("/v2", Box::new(v2));
+
+        for version in &["v1", "v2"] {
+            let manifest = cas_fs::import(
+                &vfs,
+                &format!("/{}", version),
+                &store,
+                HashAlgorithm::Blake3,
+            )
+            .unwrap();
+            let filename = store.join(format!("{}.manifest", version));
+            std::fs::write(filename, manifest.to_bytes()).unwrap();
+        }
+
+        // Identical files are only stored once
+        let objects: usize = std::fs::read_dir(store.join("objects"))
+            .unwrap()
+            .map(|dir| std::fs::read_dir(dir.unwrap().path()).unwrap().count())
+            .sum();
+        assert_eq!(objects, 4);
+
+        let mut vfs = Evfs::new();
+        for version in &["v1", "v2"] {
+            let filename = store.join(format!("{}.manifest", version));
+            vfs.mount(&format!("/cas/{}", version), filename.to_str().unwrap())
+                .unwrap();
+        }
+
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/cas/v2/copy.txt"))[..],
+            &text[..]
+        );
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/cas/v1/levels/2.bin"))[..],
+            b"level 2"
+        );
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/cas/v2/levels/2.bin"))[..],
+            b"level 2 with fixes"
+        );
+        assert_eq!(
+            vfs.metadata("/cas/v2/text.txt").unwrap().len,
+            text.len() as u64
+        );
+
+        let paths = vfs.glob("/cas/v2/**").unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                "/cas/v2/copy.txt",
+                "/cas/v2/levels",
+                "/cas/v2/levels/1.bin",
+                "/cas/v2/levels/2.bin",
+                "/cas/v2/text.txt"
+            ]
+        );
+
+        // Objects that don't match their hash are reported
+        let manifest =
+            manifest::Manifest::parse(&std::fs::read(store.join("v1.manifest")).unwrap()).unwrap();
+        let hash = manifest::to_hex(&manifest.get("levels/1.bin").unwrap().hash);
+        let object = store.join("objects").join(&hash[..2]).join(&hash[2..]);
+        std::fs::write(object, b"level 3").unwrap();
+
+        match wait_for_error(&vfs.load_file("/cas/v1/levels/1.bin")) {
+            VfsError::VerificationFailed { .. } => (),
+            e => panic!("expected VerificationFailed error, got {:?}", e),
+        }
+
+        // Manifests with hashes that don't fit the algorithm can't be mounted
+        let data = std::fs::read_to_string(store.join("v1.manifest")).unwrap();
+        let header = data.lines().next().unwrap();
+        let broken = store.join("broken.manifest");
+        std::fs::write(&broken, format!("{}\nabcd 7 levels/1.bin\n", header)).unwrap();
+        assert!(vfs.mount("/broken", broken.to_str().unwrap()).is_err());
+
+        // Sizes in the manifest are checked against the objects instead of being trusted
+        let hash = manifest::to_hex(&manifest.get("text.txt").unwrap().hash);
+        let lying = store.join("lying.manifest");
+        let line = format!("{}\n{} {} text.txt\n", header, hash, 1u64 << 60);
+        std::fs::write(&lying, line).unwrap();
+        vfs.mount("/lying", lying.to_str().unwrap()).unwrap();
+        match wait_for_error(&vfs.load_file("/lying/text.txt")) {
+            VfsError::VerificationFailed { .. } => (),
+            e => panic!("expected VerificationFailed error, got {:?}", e),
+        }
+
+        let _ = std::fs::remove_dir_all(&store);
+    }
+
+    #[test]
+    #[cfg(all(feature = "patch-fs", feature = "local-fs"))]
+    fn patched_files() {
+        use super::*;
+        use patch_fs::create_delta;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+
+        // Large file where a few parts change between versions
+        let base_data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut new_data = base_data.clone();
+        new_data[1000..1010].copy_from_slice(b"0123456789");
+        new_data.splice(50_000..50_000, b"inserted".iter().copied());
+        new_data.drain(120_000..121_000);
+        new_data.extend_from_slice(b"appended");
+
+        let dir = temp_dir("patched_files");
+        let base = dir.join("base");
+        let patch = dir.join("patch");
+        std::fs::create_dir_all(base.join("levels")).unwrap();
+        std::fs::create_dir_all(patch.join("levels")).unwrap();
+
+        std::fs::write(base.join("levels/big.bin"), &base_data).unwrap();
+        std::fs::write(base.join("levels/other.bin"), &base_data).unwrap();
+        std::fs::write(base.join("text.txt"), b"old text").unwrap();
+        std::fs::write(base.join("kept.txt"), &text).unwrap();
+
+        let delta = create_delta(&base_data, &new_data);
+        assert!(delta.len() < 1000, "delta is {} bytes", delta.len());
+        std::fs::write(patch.join("levels/big.bin.delta"), &delta).unwrap();
+        std::fs::write(patch.join("text.txt"), &text).unwrap();
+
+        // Delta made for another version of the file
+        let mut other_base = base_data.clone();
+        other_base[0] ^= 1;
+        std::fs::write(
+            patch.join("levels/other.bin.delta"),
+            create_delta(&other_base, &new_data),
+        )
+        .unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount_patch("/game", base.to_str().unwrap(), patch.to_str().unwrap())
+            .unwrap();
+
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/game/levels/big.bin"))[..],
+            &new_data[..]
+        );
+        assert_eq!(
+            vfs.metadata("/game/levels/big.bin").unwrap().len,
+            new_data.len() as u64
+        );
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/game/text.txt"))[..],
+            &text[..]
+        );
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/game/kept.txt"))[..],
+            &text[..]
+        );
+
+        match wait_for_error(&vfs.load_file("/game/levels/other.bin")) {
+            VfsError::PatchBaseMismatch { path } => assert_eq!(path, "levels/other.bin"),
+            e => panic!("expected PatchBaseMismatch error, got {:?}", e),
+        }
+
+        let paths = vfs.glob("/game/**").unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                "/game/kept.txt",
+                "/game/levels",
+                "/game/levels/big.bin",
+                "/game/levels/other.bin",
+                "/game/text.txt"
+            ]
+        );
+
+        // Deltas between unrelated data and to empty files work too
+        for (old, new) in &[
+            (&b""[..], &text[..]),
+            (&text[..], &b""[..]),
+            (&text[..100], &text[50..]),
+        ] {
+            let delta = create_delta(old, new);
+            assert_eq!(
+                &patch_fs::apply_delta("test", old, &delta).unwrap()[..],
+                *new
+            );
+        }
+
+        // Operations that go past the size in the header are rejected right away
+        let mut delta = create_delta(&text[..100], &text[..100]);
+        delta.extend_from_slice(&[0, 0, 100]);
+        assert!(matches!(
+            patch_fs::apply_delta("test", &text[..100], &delta),
+            Err(InternalError::FileError(_))
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[cfg(all(feature = "local-fs", feature = "zip-fs"))]
+    fn driver_registry() {
+        use super::*;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+        let dir = temp_dir("driver_registry");
+        let archive = dir.join("archive.bin");
+        std::fs::copy("data/test_data.zip", &archive).unwrap();
+        let archive = archive.to_str().unwrap();
+
+        // Every driver is listed with the reason it rejected the source
+        let mut vfs = Evfs::new();
+        match vfs.mount("/data", archive) {
+            Err(VfsError::NoDriverSupport { mount, rejected }) => {
+                assert_eq!(mount, archive);
+                let names: Vec<&str> = rejected.iter().map(|(name, _)| name.as_str()).collect();
+                assert_eq!(names[..2], ["local", "zip"]);
+            }
+            _ => panic!("expected NoDriverSupport error"),
+        }
+
+        vfs.mount_with("/forced", archive, "zip").unwrap();
+        vfs.mount("/scheme", &format!("zip://{}", archive)).unwrap();
+        vfs.register_extension("bin", "zip").unwrap();
+        vfs.mount("/ext", archive).unwrap();
+
+        for mount in &["/forced", "/scheme", "/ext"] {
+            assert_eq!(
+                &wait_for_data(&vfs.load_file(&format!("{}/text.txt", mount)))[..],
+                &text[..]
+            );
+        }
+
+        assert!(matches!(
+            vfs.register_scheme("floppy", "floppy"),
+            Err(VfsError::UnknownDriver { .. })
+        ));
+        assert!(matches!(
+            vfs.mount_with("/floppy", archive, "floppy"),
+            Err(VfsError::UnknownDriver { .. })
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[cfg(feature = "zip-fs")]
+    fn nested_zip_files() {
+        use super::*;
+        use std::io::Read;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/outer", "data/outer.zip").unwrap();
+        vfs.mount("/dir", "data/outer.zip!/inner.zip/dir").unwrap();
+
+        for path in &["/outer/inner.zip/dir/text.txt", "/dir/text.txt"] {
+            assert_eq!(
+                &wait_for_data(&vfs.load_file(path))[..],
+                &text[..],
+                "{}",
+                path
+            );
+        }
+
+        assert_eq!(
+            &wait_for_data(&vfs.load_file_mapped("/dir/sub/stored.txt"))[..],
+            b"stored"
+        );
+        assert_eq!(
+            vfs.glob("/dir/**").unwrap(),
+            vec!["/dir/sub", "/dir/sub/stored.txt", "/dir/text.txt"]
+        );
+
+        let mut stream = vfs.open("/dir/text.txt").recv.recv().unwrap().unwrap();
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).unwrap();
+        assert_eq!(data, text);
+    }
+
+    #[test]
+    #[cfg(all(feature = "local-fs", feature = "memory-fs", feature = "tar-fs"))]
+    fn sub_path_mounts_and_views() {
+        use super::*;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+        let dir = temp_dir("sub_path_mounts");
+        std::fs::create_dir_all(dir.join("mods/foo")).unwrap();
+        std::fs::write(dir.join("mods/foo/config.txt"), b"foo").unwrap();
+        std::fs::write(dir.join("secret.txt"), b"secret").unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/docs", "data/test_data.tar!/docs").unwrap();
+        vfs.mount("/nested", "data/test_nested.tar!/test_data.tar.zst/docs")
+            .unwrap();
+        vfs.mount("/nested_root", "data/test_nested.tar!/test_data.tar.zst")
+            .unwrap();
+        vfs.mount("/mods", &format!("{}!/mods", dir.to_str().unwrap()))
+            .unwrap();
+        vfs.mount("/", dir.to_str().unwrap()).unwrap();
+        vfs.mount_driver("/saves", Box::new(MemoryFs::new()));
+
+        for path in &[
+            "/docs/text.txt",
+            "/nested/text.txt",
+            "/nested_root/docs/text.txt",
+        ] {
+            assert_eq!(&wait_for_data(&vfs.load_file(path))[..], &text[..]);
+        }
+
+        assert_eq!(vfs.glob("/docs/*").unwrap(), vec!["/docs/text.txt"]);
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/mods/foo/config.txt"))[..],
+            b"foo"
+        );
+
+        // Directories on disk keep their real path so they can be watched
+        let mods = vfs.mounts.iter().find(|m| m.target() == "/mods").unwrap();
+        assert!(Path::new(mods.source()).ends_with("mods"));
+
+        assert!(vfs
+            .mount("/missing", "data/test_data.tar!/missing")
+            .is_err());
+
+        // Views only see their own folder
+        let view = vfs.view("/mods/foo");
+        assert_eq!(view.prefix(), "/mods/foo");
+        assert_eq!(&wait_for_data(&view.load_file("/config.txt"))[..], b"foo");
+        assert_eq!(view.glob("/**").unwrap(), vec!["/config.txt"]);
+
+        let saves = vfs.view("/saves/foo");
+        saves.write_file("/slot1.sav", &b"saved"[..]).unwrap();
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/saves/foo/slot1.sav"))[..],
+            b"saved"
+        );
+
+        for path in &["/../../secret.txt", "../x/../../secret.txt"] {
+            match wait_for_error(&view.load_file(path)) {
+                VfsError::OutsideView { .. } => (),
+                e => panic!("expected OutsideView error, got {:?}", e),
+            }
+            assert!(matches!(
+                view.metadata(path),
+                Err(VfsError::OutsideView { .. })
+            ));
+        }
+
+        assert!(view.view("..").is_err());
+        assert_eq!(vfs.view("/mods/foo/../../..").prefix(), "/");
+
+        // Sub-path mounts don't let paths go up out of their directory
+        let local = LocalFs::new().new_from_path(dir.to_str().unwrap()).unwrap();
+        let mods = SubDirFs::new(Arc::new(local), "mods");
+        assert_eq!(mods.has_entry("foo/config.txt"), EntryType::File);
+
+        for path in &["../secret.txt", "foo/../../secret.txt", "..\\secret.txt"] {
+            assert_eq!(mods.has_entry(path), EntryType::NotFound);
+            assert!(matches!(
+                mods.metadata(path),
+                Err(InternalError::PathNotFound { .. })
+            ));
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[cfg(all(feature = "config", feature = "tar-fs"))]
+    fn mount_config() {
+        use super::*;
+
+        let dir = temp_dir("mount_config");
+        std::fs::create_dir_all(dir.join("base/Docs")).unwrap();
+        std::fs::create_dir_all(dir.join("mods/Docs")).unwrap();
+        std::fs::write(dir.join("base/Docs/Text.txt"), b"base").unwrap();
+        std::fs::write(dir.join("base/Docs/Only.txt"), b"only in base").unwrap();
+        std::fs::write(dir.join("mods/Docs/Text.txt"), b"mod").unwrap();
+
+        let config = dir.join("mounts.toml");
+        std::fs::write(
+            &config,
+            r#"
+[[mount]]
+target = "/game"
+source = "base"
+read_only = true
+options = { case_insensitive = true }
+
+[[mount]]
+target = "/game"
+source = "mods"
+priority = 10
+
+[[mount]]
+target = "/never"
+source = "missing"
+platforms = ["not-an-os"]
+"#,
+        )
+        .unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount_from_config(config.to_str().unwrap()).unwrap();
+
+        // The mods are searched first as they have a higher priority
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/game/Docs/Text.txt"))[..],
+            b"mod"
+        );
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/game/docs/ONLY.txt"))[..],
+            b"only in base"
+        );
+        assert!(matches!(
+            vfs.remove("/game/Docs/Only.txt"),
+            Err(VfsError::ReadOnly { .. })
+        ));
+
+        let mut vfs = Evfs::new();
+        vfs.mount_from_config_str(
+            r#"{
+                "mounts": [
+                    { "target": "/tar", "source": "data/test_data.tar", "driver": "tar" },
+                    { "target": "/data", "source": "data" }
+                ]
+            }"#,
+            ConfigFormat::Json,
+        )
+        .unwrap();
+
+        assert!(!wait_for_data(&vfs.load_file("/tar/docs/text.txt")).is_empty());
+        assert!(!wait_for_data(&vfs.load_file("/data/text.txt")).is_empty());
+
+        // All errors are reported with their lines and nothing is mounted
+        let mut vfs = Evfs::new();
+        let result = vfs.mount_from_config_str(
+            r#"
+[[mount]]
+target = "data"
+source = "data"
+
+[[mount]]
+target = "/a"
+source = "data"
+driver = "floppy"
+
+[[mount]]
+target = "/b"
+sorce = "data"
+"#,
+            ConfigFormat::Toml,
+        );
+
+        match result {
+            Err(VfsError::InvalidConfig { errors }) => {
+                let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+                assert_eq!(lines, vec![3, 9, 13]);
+                assert!(errors[1].message.contains("floppy"));
+                assert_eq!(errors[2].context, "sorce = \"data\"");
+            }
+            _ => panic!("expected InvalidConfig error"),
+        }
+        assert!(vfs.glob("/**").unwrap().is_empty());
+
+        match vfs.mount_from_config_str(
+            "{\n  \"mounts\": [\n    { \"target\": }\n  ]\n}",
+            ConfigFormat::Json,
+        ) {
+            Err(VfsError::InvalidConfig { errors }) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].line, 3);
+            }
+            _ => panic!("expected InvalidConfig error"),
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Writes archives past 4 GB, which is slow without sparse files, so it's only run when
+    /// asked for with `--ignored`
+    #[test]
+    #[ignore]
+    #[cfg(feature = "zip-fs")]
+    fn zip64_archives() {
+        use super::*;
+        use std::io::{Read, Seek, SeekFrom, Write};
+        use zip::write::FileOptions;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+        let dir = temp_dir("zip64");
+        let stored = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+        // Entries that start after 4 GB need Zip64 offsets. The space before them is a hole
+        // in a sparse file on file systems that support it.
+        let offsets = dir.join("offsets.zip");
+        let mut file = std::fs::File::create(&offsets).unwrap();
+        file.seek(SeekFrom::Start(0x1_2000_0000)).unwrap();
+        let mut writer = zip::ZipWriter::new(file);
+        writer.start_file("text.txt", stored).unwrap();
+        writer.write_all(&text).unwrap();
+        writer
+            .start_file("deflated/text.txt", FileOptions::default())
+            .unwrap();
+        writer.write_all(&text).unwrap();
+        writer.finish().unwrap();
+
+        // More entries than fit in the end of central directory record
+        let many = dir.join("many.zip");
+        let count = 70_000;
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&many).unwrap());
+        for i in 0..count {
+            writer
+                .start_file(format!("many/{:05}.txt", i), stored)
+                .unwrap();
+            writer.write_all(format!("{}", i).as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/offsets", offsets.to_str().unwrap()).unwrap();
+        vfs.mount("/many", many.to_str().unwrap()).unwrap();
+
+        for path in &["/offsets/text.txt", "/offsets/deflated/text.txt"] {
+            assert_eq!(&wait_for_data(&vfs.load_file(path))[..], &text[..]);
+            assert_eq!(&wait_for_data(&vfs.load_file_mapped(path))[..], &text[..]);
+            assert_eq!(vfs.metadata(path).unwrap().len, text.len() as u64);
+
+            let mut stream = vfs.open(path).recv.recv().unwrap().unwrap();
+            let mut tail = Vec::new();
+            stream.seek(SeekFrom::Start(1000)).unwrap();
+            stream.read_to_end(&mut tail).unwrap();
+            assert_eq!(tail, &text[1000..]);
+        }
+
+        let entries = vfs.walk("/offsets").entries().unwrap();
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "/offsets/deflated",
+                "/offsets/deflated/text.txt",
+                "/offsets/text.txt"
+            ]
+        );
+
+        let zip = ZipFs::new().new_from_path(many.to_str().unwrap()).unwrap();
+        assert_eq!(zip.read_dir("many").unwrap().len(), count);
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/many/many/69999.txt"))[..],
+            b"69999"
+        );
+        assert_eq!(vfs.metadata("/many/many/00000.txt").unwrap().len, 1);
+        assert_eq!(
+            vfs.metadata("/many/many").unwrap().entry_type,
+            EntryType::Directory
+        );
+
+        // Offsets pointing outside of the archive are errors instead of reading garbage
+        let mut truncated = std::fs::read(&many).unwrap();
+        let central_directory = truncated.len() - 200_000;
+        truncated.drain(100..central_directory);
+        let truncated_path = dir.join("truncated.zip");
+        std::fs::write(&truncated_path, truncated).unwrap();
+        vfs.mount("/truncated", truncated_path.to_str().unwrap())
+            .unwrap();
+        assert!(matches!(
+            vfs.load_file("/truncated/many/69999.txt")
+                .recv
+                .recv()
+                .unwrap(),
+            RecvMsg::Error(_)
+        ));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Listing reuses the parsed central directory, so walking an archive with more entries
+    /// than fit in the end of central directory record stays fast
+    #[test]
+    #[cfg(feature = "zip-fs")]
+    fn large_zip_listing() {
+        use super::*;
+        use std::io::Write;
+        use std::time::{Duration, Instant};
+        use zip::write::FileOptions;
+
+        let dir = temp_dir("large_zip_listing");
+        let many = dir.join("many.zip");
+        let count = 70_000;
+        let stored = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&many).unwrap());
+        for i in 0..count {
+            writer
+                .start_file(format!("many/{:02}/{:05}.txt", i % 100, i), stored)
+                .unwrap();
+            writer.write_all(format!("{}", i).as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/many", many.to_str().unwrap()).unwrap();
+
+        // Parsing the central directory for every entry would take minutes
+        let start = Instant::now();
+        let entries = vfs.walk("/many").files_only().entries().unwrap();
+        assert_eq!(entries.len(), count);
+        assert_eq!(vfs.glob("/many/many/07/*.txt").unwrap().len(), count / 100);
+        for entry in entries.iter().step_by(100) {
+            assert_eq!(
+                vfs.metadata(&entry.path).unwrap().entry_type,
+                EntryType::File
+            );
+        }
+        assert!(
+            start.elapsed() < Duration::from_secs(10),
+            "{:?}",
+            start.elapsed()
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Writes and reads more than 4 GB so it's only run when asked for with `--ignored`
+    #[test]
+    #[ignore]
+    #[cfg(feature = "zip-fs")]
+    fn zip64_large_entries() {
+        use super::*;
+        use std::io::{Read, Seek, SeekFrom, Write};
+        use zip::write::FileOptions;
+
+        let len = 0x1_1000_0000u64;
+        let dir = temp_dir("zip64_large");
+        let filename = dir.join("large.zip");
+
+        let options = FileOptions::default()
+            .compression_level(Some(1))
+            .large_file(true);
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&filename).unwrap());
+        writer.start_file("zeros.bin", options).unwrap();
+        let chunk = vec![0; 1 << 20];
+        for _ in 0..len / chunk.len() as u64 {
+            writer.write_all(&chunk).unwrap();
+        }
+        writer
+            .start_file("after.txt", FileOptions::default())
+            .unwrap();
+        writer.write_all(b"after").unwrap();
+        writer.finish().unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/large", filename.to_str().unwrap()).unwrap();
+
+        assert_eq!(vfs.metadata("/large/zeros.bin").unwrap().len, len);
+        assert_eq!(
+            &wait_for_data(&vfs.load_file("/large/after.txt"))[..],
+            b"after"
+        );
+
+        let mut stream = vfs.open("/large/zeros.bin").recv.recv().unwrap().unwrap();
+        assert_eq!(stream.seek(SeekFrom::End(0)).unwrap(), len);
+        stream.seek(SeekFrom::Start(len - 10)).unwrap();
+        let mut tail = Vec::new();
+        stream.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, vec![0; 10]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[cfg(all(feature = "pak-fs", feature = "memory-fs", feature = "tar-fs"))]
+    fn pak_files() {
+        use super::*;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+        let mut builder = PakBuilder::new();
+        builder.add_file_with("text.txt", text.clone(), PakCompression::Zstd);
+        builder.add_file_with("docs/text.txt", text.clone(), PakCompression::Lz4);
+        builder.add_file_with("raw/text.txt", text.clone(), PakCompression::None);
+        // Doesn't get smaller when compressed so it's stored as it is
+        builder.add_file("raw/tiny.txt", b"a".to_vec());
+
+        let many: Vec<(String, Vec<u8>)> = (0..100)
+            .map(|i| {
+                (
+                    format!("many/{}.txt", i),
+                    format!("file {}", i).into_bytes(),
+                )
+            })
+            .collect();
+        for (path, data) in &many {
+            builder.add_file(path, data.clone());
+        }
+
+        let dir = temp_dir("pak");
+        let pak_path = dir.join("test.pak");
+        builder.write_to_file(&pak_path).unwrap();
+
+        let mut files: Vec<(&str, &[u8])> = vec![
+            ("text.txt", &text),
+            ("docs/text.txt", &text),
+            ("raw/text.txt", &text),
+            ("raw/tiny.txt", b"a"),
+        ];
+        files.extend(many.iter().map(|(path, data)| (path.as_str(), &data[..])));
+
+        let pak = PakFs::new()
+            .new_from_path(pak_path.to_str().unwrap())
+            .unwrap();
+        check_driver_conformance(pak.as_ref(), &files, &["docs", "raw", "many"]);
+
+        // Uncompressed entries are aligned so mapping them gives aligned data
+        let mut vfs = Evfs::new();
+        vfs.mount("/pak", pak_path.to_str().unwrap()).unwrap();
+        let data = wait_for_data(&vfs.load_file_mapped("/pak/raw/text.txt"));
+        assert_eq!(&data[..], &text[..]);
+        #[cfg(feature = "mmap")]
+        assert_eq!(data.as_ptr() as u64 % DEFAULT_PAK_ALIGNMENT, 0);
+
+        // Paks can be built from the virtual file system and used inside other mounts
+        vfs.mount("/src", "data/test_data.tar.gz").unwrap();
+        let mut builder = PakBuilder::new()
+            .alignment(16)
+            .compression(PakCompression::Lz4);
+        builder.add_vfs(&vfs, "/src").unwrap();
+        assert_eq!(builder.len(), 2);
+
+        let mut pak_data = std::io::Cursor::new(Vec::new());
+        builder.write(&mut pak_data).unwrap();
+        let pak_data = pak_data.into_inner();
+
+        // Entries pointing outside of the pak are rejected when it's opened
+        let mut corrupt = pak_data.clone();
+        let mut index_offset = [0; 8];
+        index_offset.copy_from_slice(&corrupt[16..24]);
+        let index_offset = u64::from_le_bytes(index_offset) as usize;
+        corrupt[index_offset + 16..index_offset + 24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(PakFs::new()
+            .new_from_data("corrupt.pak", Buffer::from(corrupt))
+            .is_err());
+
+        // Sizes that don't match the data fail instead of being trusted
+        let entry = |i: usize| index_offset + 8 + i * 40;
+        let mut lying = pak_data.clone();
+        for i in 0..2 {
+            assert_eq!(lying[entry(i) + 38], 2, "entries are compressed with lz4");
+            lying[entry(i) + 24..entry(i) + 32].copy_from_slice(&(1u64 << 60).to_le_bytes());
+        }
+        let (send, _recv) = crossbeam_channel::unbounded();
+        let send_msg = MsgSender::new(send, DEFAULT_PROGRESS_GRANULARITY);
+        let lying_pak = PakFs::new()
+            .new_from_data("lying.pak", Buffer::from(lying.clone()))
+            .unwrap();
+        for path in &["text.txt", "docs/text.txt"] {
+            assert!(lying_pak.load_file(path, &send_msg).is_err(), "{}", path);
+        }
+
+        lying[entry(0) + 38] = 0;
+        assert!(PakFs::new()
+            .new_from_data("lying.pak", Buffer::from(lying))
+            .is_err());
+
+        let fs = MemoryFs::new();
+        fs.insert("built.pak", pak_data).unwrap();
+        vfs.mount_driver("/mem", Box::new(fs));
+
+        let data = wait_for_data(&vfs.load_file("/mem/built.pak/docs/text.txt"));
+        assert_eq!(&data[..], &text[..]);
+    }
+
+    #[test]
+    #[cfg(all(feature = "quake-pak-fs", feature = "wad-fs", feature = "zip-fs"))]
+    fn legacy_archives() {
+        use super::*;
+        use std::io::Write;
+
+        let text = std::fs::read("data/text.txt").unwrap();
+
+        // Quake pak: header, file data and then the directory
+        let quake_files: [(&str, &[u8]); 2] =
+            [("maps/e1m1.bsp", b"bsp data"), ("progs.dat", &text)];
+        let mut pak = b"PACK".to_vec();
+        let mut dir = Vec::new();
+        pak.resize(12, 0);
+
+        for (name, data) in &quake_files {
+            let mut entry = name.as_bytes().to_vec();
+            entry.resize(56, 0);
+            entry.extend_from_slice(&(pak.len() as u32).to_le_bytes());
+            entry.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            dir.extend_from_slice(&entry);
+            pak.extend_from_slice(data);
+        }
+
+        let dir_offset = pak.len() as u32;
+        pak[4..8].copy_from_slice(&dir_offset.to_le_bytes());
+        pak[8..12].copy_from_slice(&(dir.len() as u32).to_le_bytes());
+        pak.extend_from_slice(&dir);
+
+        // Doom wad: two binary maps with the same lump names, an UDMF map and a replaced lump
+        let lumps: [(&str, &[u8]); 12] = [
+            ("PLAYPAL", b"old palette"),
+            ("E1M1", b""),
+            ("THINGS", b"things 1"),
+            ("LINEDEFS", b"linedefs 1"),
+            ("E1M2", b""),
+            ("THINGS", b"things 2"),
+            ("MAP01", b""),
+            ("TEXTMAP", b"namespace = \"zdoom\";"),
+            ("ZNODES", b"nodes"),
+            ("ENDMAP", b""),
+            ("PLAYPAL", b"new palette"),
+            ("DEMO1", &text),
+        ];
+        let mut wad = b"PWAD".to_vec();
+        let mut dir = Vec::new();
+        wad.resize(12, 0);
+
+        for (name, data) in &lumps {
+            dir.extend_from_slice(&(wad.len() as u32).to_le_bytes());
+            dir.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            let mut lump_name = name.as_bytes().to_vec();
+            lump_name.resize(8, 0);
+            dir.extend_from_slice(&lump_name);
+            wad.extend_from_slice(data);
+        }
+
+        let dir_offset = wad.len() as u32;
+        wad[4..8].copy_from_slice(&(lumps.len() as u32).to_le_bytes());
+        wad[8..12].copy_from_slice(&dir_offset.to_le_bytes());
+        wad.extend_from_slice(&dir);
+
+        let dir = temp_dir("legacy");
+        std::fs::write(dir.join("id1.pak"), &pak).unwrap();
+        std::fs::write(dir.join("doom.wad"), &wad).unwrap();
+
+        let quake = QuakePakFs::new()
+            .new_from_path(dir.join("id1.pak").to_str().unwrap())
+            .unwrap();
+        check_driver_conformance(quake.as_ref(), &quake_files, &["maps"]);
+
+        let doom = WadFs::new()
+            .new_from_path(dir.join("doom.wad").to_str().unwrap())
+            .unwrap();
+        check_driver_conformance(
+            doom.as_ref(),
+            &[
+                ("PLAYPAL", b"new palette"),
+                ("E1M1/THINGS", b"things 1"),
+                ("E1M1/LINEDEFS", b"linedefs 1"),
+                ("E1M2/THINGS", b"things 2"),
+                ("MAP01/TEXTMAP", b"namespace = \"zdoom\";"),
+                ("MAP01/ZNODES", b"nodes"),
+                ("MAP01/ENDMAP", b""),
+                ("DEMO1", &text),
+            ],
+            &["E1M1", "E1M2", "MAP01"],
+        );
+
+        // Both formats are detected inside zips as well
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(dir.join("mod.zip")).unwrap());
+        let options = zip::write::FileOptions::default();
+        zip.start_file("id1/pak0.pak", options).unwrap();
+        zip.write_all(&pak).unwrap();
+        zip.start_file("doom2.wad", options).unwrap();
+        zip.write_all(&wad).unwrap();
+        zip.finish().unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/mod", dir.join("mod.zip").to_str().unwrap())
+            .unwrap();
+        vfs.mount("/doom", dir.join("doom.wad").to_str().unwrap())
+            .unwrap();
+
+        let data = wait_for_data(&vfs.load_file("/mod/id1/pak0.pak/progs.dat"));
+        assert_eq!(&data[..], &text[..]);
+        let data = wait_for_data(&vfs.load_file("/mod/doom2.wad/E1M2/THINGS"));
+        assert_eq!(&data[..], b"things 2");
+        let data = wait_for_data(&vfs.load_file_mapped("/doom/DEMO1"));
+        assert_eq!(&data[..], &text[..]);
+
+        // Corrupt directories are reported instead of read out of bounds
+        pak[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(QuakePakFs::new()
+            .new_from_data("broken.pak", Buffer::from(pak))
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "local-fs", feature = "tar-fs"))]
+    fn load_tar_files() {
+        use super::*;
+        use std::io::{Read, Seek, SeekFrom};
+
+        let expected = std::fs::read("data/text.txt").unwrap();
+        let mut vfs = Evfs::new();
+        vfs.mount("/tar", "data/test_data.tar").unwrap();
+        vfs.mount("/gz", "data/test_data.tar.gz").unwrap();
+        vfs.mount("/zst", "data/test_data.tar.zst").unwrap();
+        vfs.mount("/nested", "data/test_nested.tar").unwrap();
+
+        for root in &["/tar", "/gz", "/zst", "/nested/test_data.tar.zst"] {
+            for name in &["text.txt", "docs/text.txt"] {
+                let path = format!("{}/{}", root, name);
+                assert_eq!(&wait_for_data(&vfs.load_file(&path))[..], &expected[..]);
+                assert_eq!(
+                    &wait_for_data(&vfs.load_file_mapped(&path))[..],
+                    &expected[..]
+                );
+            }
+
+            let mut stream = vfs
+                .open(&format!("{}/docs/text.txt", root))
+                .recv
+                .recv()
+                .unwrap()
+                .unwrap();
+            let mut tail = Vec::new();
+            stream.seek(SeekFrom::Start(1000)).unwrap();
+            stream.read_to_end(&mut tail).unwrap();
+            assert_eq!(tail, &expected[1000..]);
+        }
+
+        let entries = vfs.walk("/gz").entries().unwrap();
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["/gz/docs", "/gz/docs/text.txt", "/gz/text.txt"]);
+
+        match vfs.load_file("/zst/docs").recv.recv().unwrap() {
+            RecvMsg::Error(VfsError::NotFile { .. }) => (),
+            _ => panic!("expected NotFile error"),
+        }
+
+        // Extensions are matched in any case
+        let dir = temp_dir("tar_case");
+        let upper = dir.join("TEST_DATA.TAR.GZ");
+        std::fs::copy("data/test_data.tar.gz", &upper).unwrap();
+        assert!(TarFs::new()
+            .can_mount("/upper", upper.to_str().unwrap())
+            .is_ok());
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "local-fs",
+        feature = "memory-fs",
+        feature = "tar-fs",
+        feature = "sevenz-fs",
+        feature = "xz-fs",
+        feature = "bzip2-fs"
+    ))]
+    fn compressed_files() {
+        use super::*;
+        use std::io::{Read, Seek, SeekFrom, Write};
+
+        let expected = std::fs::read("data/text.txt").unwrap();
+
+        let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+        encoder
+            .write_all(&std::fs::read("data/test_data.tar").unwrap())
+            .unwrap();
+        let fs = MemoryFs::new();
+        fs.insert("level.tar.xz", encoder.finish().unwrap())
+            .unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/data", "data").unwrap();
+        vfs.mount("/7z", "data/test_data.7z").unwrap();
+        vfs.mount("/xz", "data/text.txt.xz").unwrap();
+        vfs.mount_driver("/mem", Box::new(fs));
+
+        for path in &[
+            "/7z/text.txt",
+            "/7z/docs/text.txt",
+            "/xz/text.txt",
+            "/data/test_data.7z/docs/text.txt",
+            "/data/text.txt.xz/text.txt",
+            "/data/text.txt.bz2/text.txt",
+            "/mem/level.tar.xz/level.tar/docs/text.txt",
+        ] {
+            let data = wait_for_data(&vfs.load_file(path));
+            assert_eq!(&data[..], &expected[..], "{}", path);
+        }
+
+        let entries = vfs.walk("/7z").entries().unwrap();
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["/7z/docs", "/7z/docs/text.txt", "/7z/text.txt"]);
+
+        let mut stream = vfs.open("/xz/text.txt").recv.recv().unwrap().unwrap();
+        let mut tail = Vec::new();
+        stream.seek(SeekFrom::Start(1000)).unwrap();
+        stream.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &expected[1000..]);
+
+        assert_eq!(
+            vfs.metadata("/7z/docs/text.txt").unwrap().len,
+            expected.len() as u64
+        );
+
+        // The extension of 7z archives is matched in any case
+        let dir = temp_dir("sevenz");
+        let upper = dir.join("TEST_DATA.7Z");
+        std::fs::copy("data/test_data.7z", &upper).unwrap();
+        assert!(SevenZipFs::new()
+            .can_mount("/upper", upper.to_str().unwrap())
+            .is_ok());
+    }
+
+    #[test]
+    #[cfg(all(feature = "local-fs", feature = "zip-fs", feature = "mmap"))]
+    fn load_mapped_files() {
+        use super::*;
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/test", "data").unwrap();
+        vfs.mount("/stored", "data/test_data_stored.zip").unwrap();
+        vfs.mount("/deflated", "data/test_data.zip").unwrap();
+
+        let expected = std::fs::read("data/text.txt").unwrap();
+
+        for path in &["/test/text.txt", "/stored/text.txt"] {
+            let data = wait_for_data(&vfs.load_file_mapped(path));
+            assert_eq!(&data[..], &expected[..], "{}", path);
+        }
+
+        // compressed entries can't be mapped and are unpacked instead
+        let data = wait_for_data(&vfs.load_file_mapped("/deflated/text.txt"));
+        assert_eq!(&data[..], &expected[..]);
+    }
+
+    #[test]
+    #[cfg(all(feature = "local-fs", feature = "zip-fs"))]
+    fn byte_progress() {
+        use super::*;
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/test", "").unwrap();
+        vfs.mount("/deflated", "data/test_data.zip").unwrap();
+        vfs.set_progress_granularity(256);
+
+        for path in &["/test/src/lib.rs", "/deflated/text.txt"] {
+            let handle = vfs.load_file(path);
+            let mut updates = Vec::new();
+
+            let data = loop {
+                match handle.recv.recv().unwrap() {
+                    RecvMsg::ReadProgress(p) => updates.push(p),
+                    RecvMsg::ReadDone(data) => break data,
+                    RecvMsg::Error(e) => panic!("main: error {:#?}", e),
+                    _ => (),
+                }
+            };
+
+            let len = data.len() as u64;
+            let last = updates.last().unwrap();
+
+            assert_eq!(last.bytes_done, len, "{}", path);
+            assert_eq!(last.bytes_total, Some(len), "{}", path);
+            assert_eq!(last.fraction(), Some(1.0), "{}", path);
+            assert!(updates.len() as u64 >= len / 256, "{}", path);
+            assert!(updates
+                .windows(2)
+                .all(|w| w[0].bytes_done <= w[1].bytes_done));
+        }
+
+        // Sizes claimed by archives and servers aren't allocated up front
+        let (send, _recv) = crossbeam_channel::unbounded();
+        let send_msg = MsgSender::new(send, u64::MAX);
+        let data = send_msg
+            .read_all(&mut &b"short"[..], Some(1 << 60))
+            .unwrap();
+        assert_eq!(&data[..], b"short");
+    }
+
+    #[test]
+    #[cfg(all(feature = "local-fs", feature = "zip-fs"))]
+    fn load_batches() {
+        use super::*;
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/test", "data").unwrap();
+        vfs.mount("/deflated", "data/test_data.zip").unwrap();
+
+        let paths = ["/test/text.txt", "/test/missing.txt", "/deflated/text.txt"];
+        let mut batch = vfs.load_batch(paths, BatchMode::CollectAll);
+        let mut failed = Vec::new();
+
+        for _ in 0..500 {
+            for event in batch.update() {
+                match event {
+                    BatchEvent::Loaded { path, data, .. } => {
+                        assert_eq!(data.len(), 1369, "{}", path);
                     }
-                },
+                    BatchEvent::Failed { index, .. } => failed.push(index),
+                }
+            }
 
-                _ => (),
+            if batch.is_done() {
+                break;
             }
 
-            thread::sleep(time::Duration::from_millis(10));
+            thread::sleep(std::time::Duration::from_millis(10));
         }
 
-        // Make sure we actually got the data
-        assert_eq!(file_done, true);
+        assert!(batch.is_done());
+        assert_eq!(batch.len(), 3);
+        assert_eq!(batch.loaded_count(), 2);
+        assert_eq!(failed, vec![1]);
+        assert_eq!(batch.progress().bytes_done, 2 * 1369);
+        assert_eq!(batch.progress().bytes_total, Some(2 * 1369));
+
+        let mut batch = vfs.load_batch(&paths[1..2], BatchMode::FailFast);
+
+        while !batch.is_done() {
+            batch.update();
+        }
+
+        assert_eq!(batch.failed_count(), 1);
+    }
+
+    #[test]
+    fn glob_patterns() {
+        use super::glob::*;
+
+        assert!(glob_match("/assets/*.png", "/assets/a.png"));
+        assert!(!glob_match("/assets/*.png", "/assets/sub/a.png"));
+        assert!(glob_match("/assets/**/*.png", "/assets/a.png"));
+        assert!(glob_match("/assets/**/*.png", "/assets/sub/deeper/a.png"));
+        assert!(glob_match("/assets/**", "/assets/sub/a.txt"));
+        assert!(glob_match("/a/file?.[ch]", "/a/file1.h"));
+        assert!(!glob_match("/a/file?.[!ch]", "/a/file1.h"));
+        assert!(glob_match("/a/[0-9][0-9].txt", "/a/42.txt"));
+        assert!(!glob_match("/a/?", "/a/bc"));
+
+        assert_eq!(literal_prefix("/assets/**/*.png"), "/assets");
+        assert_eq!(literal_prefix("/*.png"), "/");
+        assert_eq!(literal_prefix("/assets/a.png"), "/assets/a.png");
+    }
+
+    #[test]
+    #[cfg(all(feature = "local-fs", feature = "zip-fs"))]
+    fn walk_and_glob() {
+        use super::*;
+
+        let dir = temp_dir("walk");
+        std::fs::create_dir_all(dir.join("gfx/ui")).unwrap();
+        std::fs::write(dir.join("gfx/a.png"), b"a").unwrap();
+        std::fs::write(dir.join("gfx/ui/b.png"), b"b").unwrap();
+        std::fs::write(dir.join("text.txt"), b"overlay").unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/assets", dir.to_str().unwrap()).unwrap();
+        vfs.mount("/assets", "data/test_data.zip").unwrap();
+        vfs.mount("/assets/extra", "data").unwrap();
+
+        let entries = vfs.walk("/assets").entries().unwrap();
+        let paths: Vec<&str> = entries.iter().map(|e| e.path.as_str()).collect();
+
+        assert!(paths.contains(&"/assets/gfx/ui/b.png"));
+        assert!(paths.contains(&"/assets/extra/test_data.zip"));
+        // text.txt is in both the directory and the zip but is only listed once
+        assert_eq!(
+            paths.iter().filter(|p| **p == "/assets/text.txt").count(),
+            1
+        );
+
+        // the first mount has priority when loading as well
+        let data = wait_for_data(&vfs.load_file("/assets/text.txt"));
+        assert_eq!(&data[..], b"overlay");
+
+        let top = vfs
+            .walk("/assets")
+            .max_depth(1)
+            .files_only()
+            .entries()
+            .unwrap();
+        let top: Vec<&str> = top.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(top, vec!["/assets/text.txt"]);
+
+        assert_eq!(
+            vfs.glob("/assets/**/*.png").unwrap(),
+            vec!["/assets/gfx/a.png", "/assets/gfx/ui/b.png"]
+        );
+        assert_eq!(
+            vfs.glob("/assets/*/*.png").unwrap(),
+            vec!["/assets/gfx/a.png"]
+        );
+        assert_eq!(
+            vfs.glob("/assets/extra/*.zip").unwrap(),
+            vec![
+                "/assets/extra/outer.zip",
+                "/assets/extra/test_data.zip",
+                "/assets/extra/test_data_stored.zip"
+            ]
+        );
+        assert!(vfs.glob("/nothing/**").unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(all(feature = "watch", feature = "local-fs", feature = "zip-fs"))]
+    fn watch_changes() {
+        use super::*;
+        use std::time::Duration;
+
+        let dir = temp_dir("watch");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::copy("data/test_data.zip", dir.join("data.zip")).unwrap();
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/w", dir.to_str().unwrap()).unwrap();
+        vfs.mount("/z", dir.join("data.zip").to_str().unwrap())
+            .unwrap();
+
+        let watch = vfs.watch("/w/**/*.txt").unwrap();
+
+        std::fs::write(dir.join("sub/ignored.bin"), b"data").unwrap();
+        std::fs::write(dir.join("sub/file.txt"), b"data").unwrap();
+
+        let event = watch.recv.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event.path, "/w/sub/file.txt");
+        assert_eq!(event.kind, WatchEventKind::Created);
+
+        let event = watch.recv.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event.path, "/w/sub/file.txt");
+        assert_eq!(event.kind, WatchEventKind::Modified);
+
+        // replacing the archive is reported for the whole mount
+        let archive_watch = vfs.watch("/z").unwrap();
+        std::fs::copy("data/test_data_stored.zip", dir.join("data.zip")).unwrap();
+
+        let event = archive_watch
+            .recv
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(event.path, "/z");
+        assert_eq!(event.kind, WatchEventKind::Modified);
+
+        assert!(vfs.watch("/unmounted").is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "local-fs", feature = "zip-fs"))]
+    fn open_streams() {
+        use super::*;
+        use std::io::{Read, Seek, SeekFrom};
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/test", "data").unwrap();
+        vfs.mount("/stored", "data/test_data_stored.zip").unwrap();
+        vfs.mount("/deflated", "data/test_data.zip").unwrap();
+
+        let expected = std::fs::read("data/text.txt").unwrap();
+
+        for path in &["/test/text.txt", "/stored/text.txt", "/deflated/text.txt"] {
+            let mut stream = vfs.open(path).recv.recv().unwrap().unwrap();
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).unwrap();
+            assert_eq!(data, expected, "{}", path);
+
+            // seek backwards and read a part in the middle
+            let mut part = [0u8; 16];
+            stream.seek(SeekFrom::Start(100)).unwrap();
+            stream.read_exact(&mut part).unwrap();
+            assert_eq!(&part[..], &expected[100..116], "{}", path);
+
+            stream.seek(SeekFrom::End(-8)).unwrap();
+            let mut end = Vec::new();
+            stream.read_to_end(&mut end).unwrap();
+            assert_eq!(&end[..], &expected[expected.len() - 8..], "{}", path);
+        }
+
+        match vfs.open("/test/missing.txt").recv.recv().unwrap() {
+            Err(VfsError::PathNotFound { .. }) => (),
+            _ => panic!("expected PathNotFound"),
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "local-fs", feature = "zip-fs"))]
+    fn load_ranges() {
+        use super::*;
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/test", "data").unwrap();
+        vfs.mount("/stored", "data/test_data_stored.zip").unwrap();
+        vfs.mount("/deflated", "data/test_data.zip").unwrap();
+
+        let expected = std::fs::read("data/text.txt").unwrap();
+
+        for path in &["/test/text.txt", "/stored/text.txt", "/deflated/text.txt"] {
+            let data = wait_for_data(&vfs.load_range(path, 500, 64));
+            assert_eq!(&data[..], &expected[500..564], "{}", path);
+
+            // ranges past the end are cut off at the end of the file
+            let data = wait_for_data(&vfs.load_range(path, 1300, 1000));
+            assert_eq!(&data[..], &expected[1300..], "{}", path);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "local-fs", feature = "zip-fs"))]
+    fn load_streaming() {
+        use super::*;
+
+        let mut vfs = Evfs::new();
+        vfs.mount("/test", "").unwrap();
+        vfs.mount("/deflated", "data/test_data.zip").unwrap();
+
+        let expected_local = std::fs::read("src/lib.rs").unwrap();
+        let expected_zip = std::fs::read("data/text.txt").unwrap();
+
+        for (path, expected) in &[
+            ("/test/src/lib.rs", expected_local),
+            ("/deflated/text.txt", expected_zip),
+        ] {
+            let handle = vfs.load_file_streaming(path);
+            let mut data = Vec::new();
+
+            loop {
+                match handle.recv.recv().unwrap() {
+                    RecvMsg::Chunk {
+                        offset,
+                        data: chunk,
+                    } => {
+                        assert_eq!(offset as usize, data.len());
+                        data.extend_from_slice(&chunk);
+                    }
+                    RecvMsg::StreamDone => break,
+                    RecvMsg::Error(e) => panic!("main: error {:#?}", e),
+                    _ => (),
+                }
+            }
+
+            assert_eq!(&data, expected, "{}", path);
+        }
     }
 
     #[test]
@@ -512,9 +3568,9 @@ mod tests {
         let mut file_done = false;
 
         for _ in 0..10 {
-            match handle.recv.try_recv() {
-                Ok(data) => match data {
-                    RecvMsg::ReadProgress(p) => println!("ReadProgress {}", p),
+            if let Ok(data) = handle.recv.try_recv() {
+                match data {
+                    RecvMsg::ReadProgress(p) => println!("ReadProgress {:?}", p),
                     RecvMsg::ReadDone(data) => {
                         let mut hasher = Sha1::new();
                         hasher.update(data);
@@ -526,15 +3582,15 @@ mod tests {
                     RecvMsg::Error(e) => {
                         panic!("main: error {:#?}", e);
                     }
-                },
 
-                _ => (),
+                    _ => (),
+                }
             }
 
             thread::sleep(time::Duration::from_millis(200));
         }
 
         // Make sure we actually got the data
-        assert_eq!(file_done, true);
+        assert!(file_done);
     }
 }
@@ -1,7 +1,8 @@
-use crate::{EntryType, InternalError, RecvMsg, VfsDriver, VfsError};
+use crate::{
+    Buffer, DirEntry, EntryType, InternalError, Metadata, MsgSender, ReadSeek, VfsDriver, VfsError,
+};
 use log::*;
 use std::fs::File;
-use std::io::Read;
 use std::path::Path;
 
 #[derive(Clone)]
@@ -17,6 +18,12 @@ impl LocalFs {
     }
 }
 
+impl Default for LocalFs {
+    fn default() -> LocalFs {
+        LocalFs::new()
+    }
+}
+
 impl VfsDriver for LocalFs {
     fn is_remote(&self) -> bool {
         false
@@ -24,7 +31,7 @@ impl VfsDriver for LocalFs {
 
     fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
         // special case for source of current dir
-        if source == "" {
+        if source.is_empty() {
             return Ok(());
         }
 
@@ -45,44 +52,79 @@ impl VfsDriver for LocalFs {
 
     ///
     /// Read a file from the local filesystem.
-    /// TODO: Make the 5 meg size configurable
-    fn load_file(
-        &self,
-        path: &str,
-        send_msg: &crossbeam_channel::Sender<RecvMsg>,
-    ) -> Result<Box<[u8]>, InternalError> {
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
         let path = Path::new(&self.root).join(path);
-
-        let metadata = std::fs::metadata(&path)?;
-        let len = metadata.len() as usize;
         let mut file = File::open(&path)?;
-        let mut output_data = vec![0u8; len];
+        let len = file.metadata()?.len();
 
         trace!("vfs: reading from {:#?}", path);
 
-        // if file is small than 5 meg we just load it fully directly to memory
-        if len < 5 * 1024 * 1024 {
-            send_msg.send(RecvMsg::ReadProgress(0.0))?;
-            file.read_to_end(&mut output_data)?;
-        } else {
-            // above 5 meg we read in 10 chunks
-            let loop_count = 10;
-            let block_len = len / loop_count;
-            let mut percent = 0.0;
-            let percent_step = 1.0 / loop_count as f32;
-
-            for i in 0..loop_count {
-                let block_offset = i * block_len;
-                let read_amount = usize::min(len - block_offset, block_len);
-                file.read_exact(&mut output_data[block_offset..block_offset + read_amount])?;
-                send_msg.send(RecvMsg::ReadProgress(percent))?;
-                percent += percent_step;
-            }
+        send_msg.read_all(&mut file, Some(len))
+    }
+
+    ///
+    /// Map a file from the local filesystem into memory instead of reading it.
+    #[cfg(feature = "mmap")]
+    fn map_file(&self, path: &str, _send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let path = Path::new(&self.root).join(path);
+        let file = File::open(&path)?;
+
+        trace!("vfs: mapping {:#?}", path);
+
+        // mapping an empty file fails on some platforms
+        if file.metadata()?.len() == 0 {
+            return Ok(Buffer::new());
+        }
+
+        // SAFETY: The mapping is only read through the returned buffer. If the file is truncated
+        // while it's mapped, reading past the new end raises SIGBUS, and if it's modified the data
+        // in the buffer changes with it. `Evfs::load_file_mapped` documents this for callers.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+
+        Ok(Buffer::from_owner(map))
+    }
+
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        let path = Path::new(&self.root).join(path);
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        let path = Path::new(&self.root).join(path);
+        let mut entries = Vec::new();
+
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+
+            let entry_type = if entry.file_type()?.is_dir() {
+                EntryType::Directory
+            } else {
+                EntryType::File
+            };
+
+            entries.push(DirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                entry_type,
+            });
         }
 
-        //send_msg.send(RecvMsg::ReadDone(output_data.into_boxed_slice()))?;
+        Ok(entries)
+    }
+
+    fn metadata(&self, path: &str) -> Result<Metadata, InternalError> {
+        let metadata = std::fs::metadata(Path::new(&self.root).join(path))?;
 
-        Ok(output_data.into_boxed_slice())
+        let (entry_type, len) = if metadata.is_file() {
+            (EntryType::File, metadata.len())
+        } else {
+            (EntryType::Directory, 0)
+        };
+
+        Ok(Metadata {
+            entry_type,
+            len,
+            modified: metadata.modified().ok(),
+        })
     }
 
     fn has_entry(&self, path: &str) -> EntryType {
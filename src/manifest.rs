@@ -0,0 +1,292 @@
+use crate::{Buffer, DirEntry, EntryType, InternalError, Metadata, MsgSender, VfsDriver, VfsError};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::Arc;
+
+const HEADER: &str = "evfs-manifest 1";
+
+/// Hash used for the files of a manifest
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<HashAlgorithm> {
+        match name {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            _ => None,
+        }
+    }
+
+    /// Length of the hashes in bytes
+    pub(crate) fn len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Blake3 => blake3::OUT_LEN,
+        }
+    }
+
+    fn hash(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+            HashAlgorithm::Blake3 => blake3::hash(data).as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Expected size and hash of a file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub size: u64,
+    pub hash: Vec<u8>,
+}
+
+/// List of files with their sizes and hashes, used by `VerifiedFs` to detect tampered or
+/// corrupted files. Manifests are text with a header line followed by one line per file:
+///
+/// ```text
+/// evfs-manifest 1 sha256
+/// <hex hash> <size> <path>
+/// ```
+///
+/// The manifest itself is verified with an Ed25519 signature of its bytes when it's parsed with
+/// `parse_signed`. Tools creating manifests use `add`, `to_bytes` and `sign`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Manifest {
+    algorithm: HashAlgorithm,
+    entries: BTreeMap<String, ManifestEntry>,
+}
+
+fn invalid_data(msg: String) -> VfsError {
+    io::Error::new(io::ErrorKind::InvalidData, msg).into()
+}
+
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    data.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{:02x}", byte);
+        hex
+    })
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Paths are stored without leading or trailing slashes
+fn normalize(path: &str) -> &str {
+    path.trim_matches('/')
+}
+
+/// Returns the public key of an Ed25519 secret key. This is what `Manifest::parse_signed`
+/// needs to verify manifests signed with `Manifest::sign`.
+pub fn public_key(secret_key: &[u8; 32]) -> [u8; 32] {
+    SigningKey::from_bytes(secret_key)
+        .verifying_key()
+        .to_bytes()
+}
+
+impl Manifest {
+    pub fn new(algorithm: HashAlgorithm) -> Manifest {
+        Manifest {
+            algorithm,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a file with its data, replacing an earlier entry for the same path
+    pub fn add(&mut self, path: &str, data: &[u8]) {
+        let entry = ManifestEntry {
+            size: data.len() as u64,
+            hash: self.algorithm.hash(data),
+        };
+
+        self.entries.insert(normalize(path).to_owned(), entry);
+    }
+
+    /// Returns the entry of a file
+    pub fn get(&self, path: &str) -> Option<&ManifestEntry> {
+        self.entries.get(normalize(path))
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the files sorted by path
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ManifestEntry)> {
+        self.entries
+            .iter()
+            .map(|(path, entry)| (path.as_str(), entry))
+    }
+
+    /// Parses a manifest without verifying it. Use `parse_signed` for manifests that are
+    /// downloaded together with the files they list.
+    pub fn parse(data: &[u8]) -> Result<Manifest, VfsError> {
+        let text = std::str::from_utf8(data)
+            .map_err(|_| invalid_data("The manifest isn't valid UTF-8".into()))?;
+        let mut lines = text.lines();
+
+        let algorithm = lines
+            .next()
+            .and_then(|line| line.strip_prefix(HEADER))
+            .and_then(|algorithm| HashAlgorithm::from_name(algorithm.trim()))
+            .ok_or_else(|| invalid_data("Invalid manifest header".into()))?;
+
+        let mut manifest = Manifest::new(algorithm);
+
+        for line in lines.filter(|line| !line.is_empty()) {
+            let mut parts = line.splitn(3, ' ');
+            let hash = parts
+                .next()
+                .and_then(from_hex)
+                .filter(|hash| hash.len() == algorithm.len());
+            let size = parts.next().and_then(|size| size.parse().ok());
+
+            match (hash, size, parts.next()) {
+                (Some(hash), Some(size), Some(path)) => {
+                    let entry = ManifestEntry { size, hash };
+                    manifest.entries.insert(normalize(path).to_owned(), entry);
+                }
+                _ => return Err(invalid_data(format!("Invalid manifest line `{}`", line))),
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Parses a manifest after checking that `signature` is a valid Ed25519 signature of
+    /// `data` made with the secret key belonging to `public_key`.
+    pub fn parse_signed(
+        data: &[u8],
+        signature: &[u8],
+        public_key: &[u8; 32],
+    ) -> Result<Manifest, VfsError> {
+        let public_key =
+            VerifyingKey::from_bytes(public_key).map_err(|_| VfsError::InvalidSignature {})?;
+        let signature =
+            Signature::from_slice(signature).map_err(|_| VfsError::InvalidSignature {})?;
+
+        public_key
+            .verify_strict(data, &signature)
+            .map_err(|_| VfsError::InvalidSignature {})?;
+
+        Manifest::parse(data)
+    }
+
+    /// Returns the manifest in the format read by `parse`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut text = format!("{} {}\n", HEADER, self.algorithm.name());
+
+        for (path, entry) in &self.entries {
+            let _ = writeln!(text, "{} {} {}", to_hex(&entry.hash), entry.size, path);
+        }
+
+        text.into_bytes()
+    }
+
+    /// Signs the bytes returned by `to_bytes` with an Ed25519 secret key
+    pub fn sign(&self, secret_key: &[u8; 32]) -> [u8; 64] {
+        SigningKey::from_bytes(secret_key)
+            .sign(&self.to_bytes())
+            .to_bytes()
+    }
+
+    /// Checks that `data` matches the entry of `path`. Files that aren't in the manifest fail.
+    pub fn verify(&self, path: &str, data: &[u8]) -> Result<(), InternalError> {
+        match self.get(path) {
+            Some(entry)
+                if entry.size == data.len() as u64 && entry.hash == self.algorithm.hash(data) =>
+            {
+                Ok(())
+            }
+            _ => Err(InternalError::VerificationFailed { path: path.into() }),
+        }
+    }
+}
+
+/// Wraps another driver and checks every loaded file against a `Manifest` before it's
+/// returned. Files that are missing from the manifest or don't match it fail with
+/// `InternalError::VerificationFailed`. Archives are verified as files so the files inside
+/// them don't have to be listed.
+///
+/// Streams are opened by loading and verifying the whole file first so no unverified data is
+/// ever returned. The mount is read-only as changes would invalidate the manifest.
+pub struct VerifiedFs {
+    inner: Box<dyn VfsDriver>,
+    manifest: Arc<Manifest>,
+}
+
+impl VerifiedFs {
+    pub fn new(inner: Box<dyn VfsDriver>, manifest: Arc<Manifest>) -> VerifiedFs {
+        VerifiedFs { inner, manifest }
+    }
+}
+
+impl VfsDriver for VerifiedFs {
+    fn is_remote(&self) -> bool {
+        self.inner.is_remote()
+    }
+
+    /// Verified mounts are only created with `Evfs::mount_verified` or `Evfs::mount_driver`
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        Err(VfsError::UnsupportedMount {
+            mount: source.into(),
+        })
+    }
+
+    fn new_from_path(&self, path: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        Err(VfsError::UnsupportedMount { mount: path.into() })
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let data = self.inner.load_file(path, send_msg)?;
+        self.manifest.verify(path, &data)?;
+        Ok(data)
+    }
+
+    fn map_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let data = self.inner.map_file(path, send_msg)?;
+        self.manifest.verify(path, &data)?;
+        Ok(data)
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        self.inner.has_entry(path)
+    }
+
+    fn metadata(&self, path: &str) -> Result<Metadata, InternalError> {
+        self.inner.metadata(path)
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        self.inner.read_dir(path)
+    }
+
+    fn can_decompress(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    fn supports_file_ext(&self, _file_ext: &str) -> bool {
+        false
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{
+    Buffer, DirEntry, EntryType, InternalError, Metadata, MsgSender, ReadSeek, VfsDriver, VfsError,
+};
+use std::collections::BTreeMap;
+use std::io::{self, Cursor};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+#[derive(Clone)]
+enum MemoryEntry {
+    File { data: Buffer, modified: SystemTime },
+    Directory { modified: SystemTime },
+}
+
+type Entries = BTreeMap<String, MemoryEntry>;
+
+/// File system that keeps all files in memory. Files can be added from code with `insert` or
+/// through the write functions of `VfsDriver`. Clones share the same files so a clone can be
+/// mounted with `Evfs::mount_driver` while the original is used to change the content.
+///
+/// Parent directories are created when writing files so `insert("a/b/c.txt", ..)` works
+/// without creating `a` and `a/b` first.
+#[derive(Clone, Default)]
+pub struct MemoryFs {
+    entries: Arc<RwLock<Entries>>,
+}
+
+/// Paths are stored without leading or trailing slashes. The root is the empty path.
+fn normalize(path: &str) -> &str {
+    path.trim_matches('/')
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    /// Adds a file or replaces an existing one
+    pub fn insert(&self, path: &str, data: impl Into<Buffer>) -> Result<(), VfsError> {
+        Ok(self.write_file(path, data.into())?)
+    }
+
+    /// Creates the parents of `path` that don't exist yet
+    fn create_parents(entries: &mut Entries, path: &str) -> Result<(), InternalError> {
+        let now = SystemTime::now();
+        let mut end = 0;
+
+        while let Some(slash) = path[end..].find('/') {
+            end += slash;
+            let parent = &path[..end];
+
+            match entries.get(parent) {
+                Some(MemoryEntry::File { .. }) => {
+                    return Err(InternalError::NotFile {
+                        path: parent.to_owned(),
+                    })
+                }
+                Some(MemoryEntry::Directory { .. }) => (),
+                None => {
+                    entries.insert(parent.to_owned(), MemoryEntry::Directory { modified: now });
+                }
+            }
+
+            end += 1;
+        }
+
+        Ok(())
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Entries> {
+        // The map is never left in an invalid state so a poisoned lock can still be used
+        self.entries.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Entries> {
+        self.entries.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn get_file(&self, path: &str) -> Result<Buffer, InternalError> {
+        match self.read().get(normalize(path)) {
+            Some(MemoryEntry::File { data, .. }) => Ok(data.clone()),
+            Some(MemoryEntry::Directory { .. }) => {
+                Err(InternalError::NotFile { path: path.into() })
+            }
+            None if normalize(path).is_empty() => Err(InternalError::NotFile { path: path.into() }),
+            None => Err(InternalError::PathNotFound { path: path.into() }),
+        }
+    }
+}
+
+impl VfsDriver for MemoryFs {
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    /// Memory file systems are mounted with `Evfs::mount_driver`
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        Err(VfsError::UnsupportedMount {
+            mount: source.into(),
+        })
+    }
+
+    fn new_from_path(&self, path: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        Err(VfsError::UnsupportedMount { mount: path.into() })
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let data = self.get_file(path)?;
+        send_msg.finish(data.len() as u64)?;
+        Ok(data)
+    }
+
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        Ok(Box::new(Cursor::new(self.get_file(path)?)))
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        let path = normalize(path);
+
+        match self.read().get(path) {
+            _ if path.is_empty() => EntryType::Directory,
+            Some(MemoryEntry::File { .. }) => EntryType::File,
+            Some(MemoryEntry::Directory { .. }) => EntryType::Directory,
+            None => EntryType::NotFound,
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        let entries = self.read();
+        let prefix = match normalize(path) {
+            "" => String::new(),
+            dir => {
+                match entries.get(dir) {
+                    Some(MemoryEntry::Directory { .. }) => (),
+                    Some(MemoryEntry::File { .. }) => {
+                        return Err(io::Error::other(format!("{} is not a directory", dir)).into())
+                    }
+                    None => return Err(InternalError::PathNotFound { path: path.into() }),
+                }
+
+                format!("{}/", dir)
+            }
+        };
+
+        Ok(entries
+            .range(prefix.clone()..)
+            .take_while(|(name, _)| name.starts_with(&prefix))
+            .filter(|(name, _)| !name[prefix.len()..].contains('/'))
+            .map(|(name, entry)| DirEntry {
+                name: name[prefix.len()..].to_owned(),
+                entry_type: match entry {
+                    MemoryEntry::File { .. } => EntryType::File,
+                    MemoryEntry::Directory { .. } => EntryType::Directory,
+                },
+            })
+            .collect())
+    }
+
+    fn metadata(&self, path: &str) -> Result<Metadata, InternalError> {
+        match self.read().get(normalize(path)) {
+            Some(MemoryEntry::File { data, modified }) => Ok(Metadata {
+                entry_type: EntryType::File,
+                len: data.len() as u64,
+                modified: Some(*modified),
+            }),
+            Some(MemoryEntry::Directory { modified }) => Ok(Metadata {
+                entry_type: EntryType::Directory,
+                len: 0,
+                modified: Some(*modified),
+            }),
+            None if normalize(path).is_empty() => Ok(Metadata {
+                entry_type: EntryType::Directory,
+                len: 0,
+                modified: None,
+            }),
+            None => Err(InternalError::PathNotFound { path: path.into() }),
+        }
+    }
+
+    fn write_file(&self, path: &str, data: Buffer) -> Result<(), InternalError> {
+        let path = normalize(path);
+        let mut entries = self.write();
+
+        if path.is_empty() || matches!(entries.get(path), Some(MemoryEntry::Directory { .. })) {
+            return Err(InternalError::NotFile { path: path.into() });
+        }
+
+        Self::create_parents(&mut entries, path)?;
+
+        let modified = SystemTime::now();
+        entries.insert(path.to_owned(), MemoryEntry::File { data, modified });
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), InternalError> {
+        let path = normalize(path);
+        let mut entries = self.write();
+
+        match entries.get(path) {
+            _ if path.is_empty() => Ok(()),
+            Some(MemoryEntry::Directory { .. }) => Ok(()),
+            Some(MemoryEntry::File { .. }) => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists as a file", path),
+            )
+            .into()),
+            None => {
+                Self::create_parents(&mut entries, path)?;
+
+                let modified = SystemTime::now();
+                entries.insert(path.to_owned(), MemoryEntry::Directory { modified });
+                Ok(())
+            }
+        }
+    }
+
+    ///
+    /// Directories are removed together with everything in them.
+    fn remove(&self, path: &str) -> Result<(), InternalError> {
+        let path = normalize(path);
+        let mut entries = self.write();
+
+        if path.is_empty() || entries.remove(path).is_none() {
+            return Err(InternalError::PathNotFound { path: path.into() });
+        }
+
+        let prefix = format!("{}/", path);
+        entries.retain(|name, _| !name.starts_with(&prefix));
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), InternalError> {
+        let (from, to) = (normalize(from), normalize(to));
+        let mut entries = self.write();
+
+        if from.is_empty() || !entries.contains_key(from) {
+            return Err(InternalError::PathNotFound { path: from.into() });
+        }
+
+        if to.is_empty() || matches!(entries.get(to), Some(MemoryEntry::Directory { .. })) {
+            return Err(InternalError::NotFile { path: to.into() });
+        }
+
+        // Everything below a directory moves with it, so it can't be moved into itself
+        let prefix = format!("{}/", from);
+
+        if to == from || to.starts_with(&prefix) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unable to rename {} to {} inside of it", from, to),
+            )
+            .into());
+        }
+
+        Self::create_parents(&mut entries, to)?;
+
+        let moved: Vec<String> = entries
+            .range(from.to_owned()..)
+            .map(|(name, _)| name)
+            .take_while(|name| name.starts_with(from))
+            .filter(|name| *name == from || name.starts_with(&prefix))
+            .cloned()
+            .collect();
+
+        for name in moved {
+            if let Some(entry) = entries.remove(&name) {
+                entries.insert(format!("{}{}", to, &name[from.len()..]), entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn can_decompress(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    fn supports_file_ext(&self, _file_ext: &str) -> bool {
+        false
+    }
+}
@@ -0,0 +1,709 @@
+//! The evfs pak format.
+//!
+//! Paks are made for loading speed rather than compatibility:
+//!
+//! * The data of each entry starts at a multiple of the pak alignment so uncompressed entries
+//!   can be memory mapped and used directly.
+//! * Each entry is stored uncompressed or compressed with zstd or lz4.
+//! * The index at the end of the file contains a hash table so finding a file doesn't require
+//!   searching through all entries.
+//!
+//! All numbers are little endian. The layout is:
+//!
+//! ```text
+//! header   magic "EVFSPAK\0", version: u32, entry_count: u32, index_offset: u64, index_size: u64
+//! data     the data of the entries, each aligned
+//! index    slot_count: u32, reserved: u32
+//!          entries: [hash: u64, offset: u64, stored_size: u64, size: u64,
+//!                    name_offset: u32, name_len: u16, compression: u8, reserved: u8]
+//!          slots: [u32; slot_count] with entry index + 1 or 0 for an empty slot
+//!          names: utf-8 paths of the entries relative to the pak root using `/`
+//! ```
+//!
+//! Paks are created with `PakBuilder`.
+
+use crate::stream::{DecoderFactory, DecoderStream, SubReader};
+use crate::{
+    Buffer, DirEntry, EntryType, Evfs, InternalError, MsgSender, ReadSeek, RecvMsg, VfsDriver,
+    VfsError,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const PAK_MAGIC: &[u8; 8] = b"EVFSPAK\0";
+const PAK_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 32;
+const INDEX_ENTRY_SIZE: usize = 40;
+/// Default alignment of entry data, the page size on most platforms
+pub const DEFAULT_PAK_ALIGNMENT: u64 = 4096;
+
+/// How an entry in a pak is compressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PakCompression {
+    None,
+    /// Good compression ratio with fast decompression
+    Zstd,
+    /// Lower compression ratio but faster decompression than zstd
+    Lz4,
+}
+
+impl PakCompression {
+    fn from_u8(value: u8) -> io::Result<PakCompression> {
+        match value {
+            0 => Ok(PakCompression::None),
+            1 => Ok(PakCompression::Zstd),
+            2 => Ok(PakCompression::Lz4),
+            _ => Err(invalid_data("unknown compression in pak")),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            PakCompression::None => 0,
+            PakCompression::Zstd => 1,
+            PakCompression::Lz4 => 2,
+        }
+    }
+
+    fn decoder<'a, R: Read + Send + 'a>(self, reader: R) -> io::Result<Box<dyn Read + Send + 'a>> {
+        Ok(match self {
+            PakCompression::None => Box::new(reader),
+            PakCompression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            PakCompression::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(reader)),
+        })
+    }
+
+    fn compress(self, data: &[u8], zstd_level: i32) -> io::Result<Vec<u8>> {
+        match self {
+            PakCompression::None => Ok(data.to_vec()),
+            PakCompression::Zstd => zstd::stream::encode_all(data, zstd_level),
+            PakCompression::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(data)?;
+                encoder.finish().map_err(io::Error::other)
+            }
+        }
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// FNV-1a hash of an entry path
+fn hash_path(path: &str) -> u64 {
+    path.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[derive(Clone, Debug)]
+struct PakEntry {
+    hash: u64,
+    offset: u64,
+    stored_size: u64,
+    size: u64,
+    name: String,
+    compression: PakCompression,
+}
+
+/// Index of a pak as it's stored in the file
+struct PakIndex {
+    entries: Vec<PakEntry>,
+    slots: Vec<u32>,
+    /// Directories aren't stored in paks so they are collected when the index is read
+    dirs: HashSet<String>,
+}
+
+impl PakIndex {
+    fn empty() -> PakIndex {
+        PakIndex {
+            entries: Vec::new(),
+            slots: Vec::new(),
+            dirs: HashSet::new(),
+        }
+    }
+
+    /// Reads the header and index from the start of a pak
+    fn read(reader: &mut (impl Read + Seek)) -> io::Result<PakIndex> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        let mut header = [0; HEADER_SIZE];
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_exact(&mut header)?;
+
+        if &header[..8] != PAK_MAGIC {
+            return Err(invalid_data("not an evfs pak"));
+        }
+
+        if read_u32(&header, 8) != PAK_VERSION {
+            return Err(invalid_data("unsupported pak version"));
+        }
+
+        let entry_count = read_u32(&header, 12) as usize;
+        let index_offset = read_u64(&header, 16);
+        let index_size = read_u64(&header, 24) as usize;
+
+        let mut index = Vec::new();
+        reader.seek(SeekFrom::Start(index_offset))?;
+        reader.take(index_size as u64).read_to_end(&mut index)?;
+
+        if index.len() != index_size || index_size < 8 {
+            return Err(invalid_data("truncated pak index"));
+        }
+
+        let slot_count = read_u32(&index, 0) as usize;
+        let slots_start = entry_count
+            .checked_mul(INDEX_ENTRY_SIZE)
+            .and_then(|size| size.checked_add(8));
+        let names_start =
+            slots_start.and_then(|start| slot_count.checked_mul(4)?.checked_add(start));
+
+        let (slots_start, names_start) = match (slots_start, names_start) {
+            (Some(slots_start), Some(names_start))
+                if names_start <= index.len() && slot_count.is_power_of_two() =>
+            {
+                (slots_start, names_start)
+            }
+            _ => return Err(invalid_data("invalid pak index")),
+        };
+
+        let names = &index[names_start..];
+        let mut entries = Vec::with_capacity(entry_count);
+        let mut dirs = HashSet::new();
+
+        for i in 0..entry_count {
+            let e = &index[8 + i * INDEX_ENTRY_SIZE..];
+            let name_offset = read_u32(e, 32) as usize;
+            let name_len = read_u16(e, 36) as usize;
+
+            let name = name_offset
+                .checked_add(name_len)
+                .and_then(|name_end| names.get(name_offset..name_end))
+                .and_then(|name| std::str::from_utf8(name).ok())
+                .ok_or_else(|| invalid_data("invalid name in pak index"))?;
+
+            let mut parent = name;
+            while let Some(end) = parent.rfind('/') {
+                parent = &parent[..end];
+                dirs.insert(parent.to_owned());
+            }
+
+            let offset = read_u64(e, 8);
+            let stored_size = read_u64(e, 16);
+            let size = read_u64(e, 24);
+            let compression = PakCompression::from_u8(e[38])?;
+
+            if offset.checked_add(stored_size).is_none_or(|end| end > len) {
+                return Err(invalid_data("file outside of the pak"));
+            }
+
+            // The size of compressed files is checked when they are unpacked
+            if compression == PakCompression::None && size != stored_size {
+                return Err(invalid_data(
+                    "size of an uncompressed file doesn't match its data",
+                ));
+            }
+
+            entries.push(PakEntry {
+                hash: read_u64(e, 0),
+                offset,
+                stored_size,
+                size,
+                name: name.to_owned(),
+                compression,
+            });
+        }
+
+        let slots = (0..slot_count)
+            .map(|i| read_u32(&index, slots_start + i * 4))
+            .collect();
+
+        Ok(PakIndex {
+            entries,
+            slots,
+            dirs,
+        })
+    }
+
+    fn find(&self, path: &str) -> Option<&PakEntry> {
+        if self.slots.is_empty() {
+            return None;
+        }
+
+        let hash = hash_path(path);
+        let mask = self.slots.len() - 1;
+        let mut slot = hash as usize & mask;
+
+        // The table is never full so probing always ends at an empty slot
+        for _ in 0..self.slots.len() {
+            let entry = match self.slots[slot] {
+                0 => return None,
+                index => self.entries.get(index as usize - 1)?,
+            };
+
+            if entry.hash == hash && entry.name == path {
+                return Some(entry);
+            }
+
+            slot = (slot + 1) & mask;
+        }
+
+        None
+    }
+}
+
+/// Where the pak data comes from
+enum Source {
+    File(String),
+    /// Used for paks inside other mounts
+    Memory(Buffer),
+}
+
+/// Driver for reading paks created with `PakBuilder`
+pub struct PakFs {
+    source: Source,
+    index: PakIndex,
+}
+
+impl PakFs {
+    pub fn new() -> PakFs {
+        PakFs {
+            source: Source::Memory(Buffer::new()),
+            index: PakIndex::empty(),
+        }
+    }
+
+    fn find_file(&self, path: &str) -> Result<&PakEntry, InternalError> {
+        match self.index.find(path) {
+            Some(entry) => Ok(entry),
+            None if self.has_entry(path) == EntryType::Directory => {
+                Err(InternalError::NotFile { path: path.into() })
+            }
+            None => Err(InternalError::PathNotFound { path: path.into() }),
+        }
+    }
+
+    /// Returns a reader for the data of an entry as it's stored in the pak
+    fn raw_reader(&self, entry: &PakEntry) -> io::Result<Box<dyn Read + Send>> {
+        match &self.source {
+            Source::File(filename) => Ok(Box::new(SubReader::new(
+                File::open(filename)?,
+                entry.offset,
+                entry.stored_size,
+            )?)),
+            Source::Memory(data) => Ok(Box::new(Cursor::new(memory_slice(data, entry)?))),
+        }
+    }
+}
+
+impl Default for PakFs {
+    fn default() -> PakFs {
+        PakFs::new()
+    }
+}
+
+fn memory_slice(data: &Buffer, entry: &PakEntry) -> io::Result<Buffer> {
+    match entry.offset.checked_add(entry.stored_size) {
+        Some(end) if end <= data.len() as u64 => {
+            Ok(data.slice(entry.offset as usize..end as usize))
+        }
+        _ => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+    }
+}
+
+impl VfsDriver for PakFs {
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        let mut magic = [0; 8];
+
+        // Other formats use the .pak extension as well so only the magic is checked
+        if std::fs::metadata(source)?.is_file()
+            && File::open(source)?.read_exact(&mut magic).is_ok()
+            && &magic == PAK_MAGIC
+        {
+            Ok(())
+        } else {
+            Err(VfsError::UnsupportedMount {
+                mount: source.into(),
+            })
+        }
+    }
+
+    fn new_from_path(&self, filename: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        let index = PakIndex::read(&mut File::open(filename)?)?;
+
+        Ok(Box::new(PakFs {
+            source: Source::File(filename.into()),
+            index,
+        }))
+    }
+
+    fn new_from_data(&self, path: &str, data: Buffer) -> Result<Box<dyn VfsDriver>, InternalError> {
+        if !self.can_decompress(&data) {
+            return Err(InternalError::DecompressorNotFound { path: path.into() });
+        }
+
+        Ok(Box::new(PakFs {
+            index: PakIndex::read(&mut Cursor::new(data.clone()))?,
+            source: Source::Memory(data),
+        }))
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let entry = self.find_file(path)?;
+
+        if let (Source::Memory(data), PakCompression::None) = (&self.source, entry.compression) {
+            send_msg.finish(entry.size)?;
+            return Ok(memory_slice(data, entry)?);
+        }
+
+        // Compressed data isn't unpacked further than one byte past the size in the index, which
+        // is enough to tell that the size is wrong
+        let reader = entry.compression.decoder(self.raw_reader(entry)?)?;
+        let mut reader = reader.take(entry.size.saturating_add(1));
+        let data = send_msg.read_all(&mut reader, Some(entry.size))?;
+
+        if data.len() as u64 != entry.size {
+            return Err(invalid_data("size of a compressed file doesn't match its data").into());
+        }
+
+        Ok(data)
+    }
+
+    ///
+    /// Uncompressed entries are returned as a slice of the mapped pak.
+    #[cfg(feature = "mmap")]
+    fn map_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let entry = self.find_file(path)?;
+
+        let filename = match (&self.source, entry.compression) {
+            (Source::File(filename), PakCompression::None) => filename,
+            _ => return self.load_file(path, send_msg),
+        };
+
+        // SAFETY: The pak is only read through the returned slice. If it's truncated while
+        // mapped, reads raise SIGBUS, and changes to it show up in the data. This is documented
+        // on `Evfs::load_file_mapped`.
+        let map = unsafe { memmap2::Mmap::map(&File::open(filename)?)? };
+        let data = memory_slice(&Buffer::from_owner(map), entry)?;
+
+        send_msg.finish(entry.size)?;
+        Ok(data)
+    }
+
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        let entry = self.find_file(path)?;
+
+        match (&self.source, entry.compression) {
+            (Source::File(filename), PakCompression::None) => Ok(Box::new(SubReader::new(
+                File::open(filename)?,
+                entry.offset,
+                entry.size,
+            )?)),
+            (Source::Memory(data), PakCompression::None) => {
+                Ok(Box::new(Cursor::new(memory_slice(data, entry)?)))
+            }
+            (Source::File(filename), compression) => {
+                let (filename, offset, stored_size) =
+                    (filename.clone(), entry.offset, entry.stored_size);
+
+                let factory: DecoderFactory = Box::new(move || {
+                    let raw = SubReader::new(File::open(&filename)?, offset, stored_size)?;
+                    compression.decoder(raw)
+                });
+
+                Ok(Box::new(DecoderStream::new(factory, entry.size)?))
+            }
+            (Source::Memory(data), compression) => {
+                let raw = memory_slice(data, entry)?;
+                let factory: DecoderFactory =
+                    Box::new(move || compression.decoder(Cursor::new(raw.clone())));
+
+                Ok(Box::new(DecoderStream::new(factory, entry.size)?))
+            }
+        }
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        if path.is_empty() || self.index.dirs.contains(path.trim_end_matches('/')) {
+            EntryType::Directory
+        } else if self.index.find(path).is_some() {
+            EntryType::File
+        } else {
+            EntryType::NotFound
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        let prefix = match path.trim_end_matches('/') {
+            "" => String::new(),
+            dir => format!("{}/", dir),
+        };
+
+        let mut entries = BTreeMap::new();
+
+        for entry in &self.index.entries {
+            let rest = match entry.name.strip_prefix(&prefix) {
+                Some(rest) => rest,
+                None => continue,
+            };
+
+            match rest.find('/') {
+                Some(end) => entries.insert(&rest[..end], EntryType::Directory),
+                None => entries.insert(rest, EntryType::File),
+            };
+        }
+
+        Ok(entries
+            .into_iter()
+            .map(|(name, entry_type)| DirEntry {
+                name: name.to_owned(),
+                entry_type,
+            })
+            .collect())
+    }
+
+    fn can_decompress(&self, data: &[u8]) -> bool {
+        data.starts_with(PAK_MAGIC)
+    }
+
+    fn supports_file_ext(&self, _file_ext: &str) -> bool {
+        false
+    }
+}
+
+/// Creates evfs paks from files on disk, files in a virtual file system or data in memory
+pub struct PakBuilder {
+    alignment: u64,
+    compression: PakCompression,
+    zstd_level: i32,
+    entries: BTreeMap<String, (Buffer, PakCompression)>,
+}
+
+impl PakBuilder {
+    pub fn new() -> PakBuilder {
+        PakBuilder {
+            alignment: DEFAULT_PAK_ALIGNMENT,
+            compression: PakCompression::Zstd,
+            zstd_level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Alignment of the entry data. Defaults to `DEFAULT_PAK_ALIGNMENT`.
+    pub fn alignment(mut self, alignment: u64) -> PakBuilder {
+        self.alignment = alignment.max(1);
+        self
+    }
+
+    /// Compression used for entries added without one. Defaults to zstd.
+    pub fn compression(mut self, compression: PakCompression) -> PakBuilder {
+        self.compression = compression;
+        self
+    }
+
+    /// Compression level used for zstd, from 1 to 22. Higher levels make smaller paks but
+    /// take longer to build. Decompression speed is about the same for all levels.
+    pub fn zstd_level(mut self, level: i32) -> PakBuilder {
+        self.zstd_level = level;
+        self
+    }
+
+    /// Adds a file with the default compression, replacing any earlier file with the same path
+    pub fn add_file(&mut self, path: &str, data: impl Into<Buffer>) {
+        let compression = self.compression;
+        self.add_file_with(path, data, compression);
+    }
+
+    /// Adds a file with a specific compression. Entries that don't get smaller when compressed
+    /// are stored uncompressed.
+    pub fn add_file_with(
+        &mut self,
+        path: &str,
+        data: impl Into<Buffer>,
+        compression: PakCompression,
+    ) {
+        let path = path.trim_matches('/').replace('\\', "/");
+        self.entries.insert(path, (data.into(), compression));
+    }
+
+    /// Adds all files in a directory on disk and its sub directories
+    pub fn add_dir(&mut self, dir: impl AsRef<Path>) -> io::Result<()> {
+        self.add_dir_with_prefix(dir.as_ref(), "")
+    }
+
+    fn add_dir_with_prefix(&mut self, dir: &Path, prefix: &str) -> io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+
+            if entry.file_type()?.is_dir() {
+                self.add_dir_with_prefix(&entry.path(), &format!("{}/", path))?;
+            } else {
+                self.add_file(&path, std::fs::read(entry.path())?);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds all files below `dir` in a virtual file system. Paths in the pak are relative to
+    /// `dir`. This blocks until all files have been loaded.
+    pub fn add_vfs(&mut self, vfs: &Evfs, dir: &str) -> Result<(), VfsError> {
+        let prefix = format!("{}/", dir.trim_end_matches('/'));
+
+        for entry in vfs.walk(dir).files_only().entries()? {
+            let handle = vfs.load_file(&entry.path);
+
+            let data = loop {
+                match handle.recv.recv() {
+                    Ok(RecvMsg::ReadDone(data)) => break data,
+                    Ok(RecvMsg::Error(e)) => return Err(e),
+                    Ok(_) => (),
+                    Err(_) => return Err(io::Error::from(io::ErrorKind::BrokenPipe).into()),
+                }
+            };
+
+            let path = entry.path.strip_prefix(&prefix).unwrap_or(&entry.path);
+            self.add_file(path, data);
+        }
+
+        Ok(())
+    }
+
+    /// Number of files added to the builder
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if no files have been added
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes the pak to a file
+    pub fn write_to_file(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = io::BufWriter::new(File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()
+    }
+
+    /// Writes the pak
+    pub fn write<W: Write + Seek>(&self, writer: &mut W) -> io::Result<()> {
+        let start = writer.stream_position()?;
+        let mut entries = Vec::with_capacity(self.entries.len());
+        let mut names = Vec::new();
+        let mut pos = HEADER_SIZE as u64;
+
+        writer.write_all(&[0; HEADER_SIZE])?;
+
+        for (name, (data, compression)) in &self.entries {
+            let packed = match compression {
+                PakCompression::None => None,
+                compression => Some(compression.compress(data, self.zstd_level)?),
+            };
+
+            let (stored, compression) = match &packed {
+                Some(packed) if packed.len() < data.len() => (&packed[..], *compression),
+                _ => (&data[..], PakCompression::None),
+            };
+
+            let padding = (self.alignment - pos % self.alignment) % self.alignment;
+            writer.write_all(&vec![0; padding as usize])?;
+            pos += padding;
+
+            if name.len() > u16::MAX as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "path is too long",
+                ));
+            }
+
+            entries.push(PakEntry {
+                hash: hash_path(name),
+                offset: pos,
+                stored_size: stored.len() as u64,
+                size: data.len() as u64,
+                name: name.clone(),
+                compression,
+            });
+
+            writer.write_all(stored)?;
+            pos += stored.len() as u64;
+        }
+
+        // Keep the table at most half full so probing is short
+        let slot_count = (entries.len() * 2).next_power_of_two().max(1);
+        let mut slots = vec![0u32; slot_count];
+
+        for (i, entry) in entries.iter().enumerate() {
+            let mut slot = entry.hash as usize & (slot_count - 1);
+
+            while slots[slot] != 0 {
+                slot = (slot + 1) & (slot_count - 1);
+            }
+
+            slots[slot] = i as u32 + 1;
+        }
+
+        let mut index = Vec::new();
+        index.extend_from_slice(&(slot_count as u32).to_le_bytes());
+        index.extend_from_slice(&0u32.to_le_bytes());
+
+        for entry in &entries {
+            index.extend_from_slice(&entry.hash.to_le_bytes());
+            index.extend_from_slice(&entry.offset.to_le_bytes());
+            index.extend_from_slice(&entry.stored_size.to_le_bytes());
+            index.extend_from_slice(&entry.size.to_le_bytes());
+            index.extend_from_slice(&(names.len() as u32).to_le_bytes());
+            index.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            index.push(entry.compression.to_u8());
+            index.push(0);
+            names.extend_from_slice(entry.name.as_bytes());
+        }
+
+        for slot in slots {
+            index.extend_from_slice(&slot.to_le_bytes());
+        }
+
+        index.extend_from_slice(&names);
+        writer.write_all(&index)?;
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(PAK_MAGIC);
+        header.extend_from_slice(&PAK_VERSION.to_le_bytes());
+        header.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        header.extend_from_slice(&pos.to_le_bytes());
+        header.extend_from_slice(&(index.len() as u64).to_le_bytes());
+
+        writer.seek(SeekFrom::Start(start))?;
+        writer.write_all(&header)?;
+        writer.seek(SeekFrom::Start(start + pos + index.len() as u64))?;
+
+        Ok(())
+    }
+}
+
+impl Default for PakBuilder {
+    fn default() -> PakBuilder {
+        PakBuilder::new()
+    }
+}
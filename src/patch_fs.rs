@@ -0,0 +1,384 @@
+use crate::{
+    Buffer, DirEntry, EntryType, InternalError, Metadata, MsgSender, RecvMsg, VfsDriver, VfsError,
+};
+use crossbeam_channel::unbounded;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+use std::io;
+
+/// Extension of delta files in a patch. `level.bin.delta` patches `level.bin` of the base.
+pub const DELTA_EXT: &str = "delta";
+
+const MAGIC: &[u8] = b"EVFD";
+const VERSION: u8 = 1;
+/// Magic, version, size and SHA-256 of the base followed by size and SHA-256 of the result
+const HEADER_LEN: usize = MAGIC.len() + 1 + 2 * (8 + 32);
+
+/// Size of the blocks of the base that are searched for in the new file
+const BLOCK_LEN: usize = 16;
+const HASH_FACTOR: u64 = 0x100_0000_01b3;
+
+const OP_COPY: u8 = 0;
+const OP_INSERT: u8 = 1;
+
+/// Header of a delta file
+struct DeltaHeader {
+    base_len: u64,
+    base_hash: [u8; 32],
+    len: u64,
+    hash: [u8; 32],
+}
+
+impl DeltaHeader {
+    fn parse(path: &str, data: &[u8]) -> Result<DeltaHeader, InternalError> {
+        if data.len() < HEADER_LEN || !data.starts_with(MAGIC) || data[MAGIC.len()] != VERSION {
+            return Err(invalid_delta(path).into());
+        }
+
+        let field = |start: usize, len: usize| &data[MAGIC.len() + 1 + start..][..len];
+
+        Ok(DeltaHeader {
+            base_len: u64::from_le_bytes(field(0, 8).try_into().unwrap()),
+            base_hash: field(8, 32).try_into().unwrap(),
+            len: u64::from_le_bytes(field(40, 8).try_into().unwrap()),
+            hash: field(48, 32).try_into().unwrap(),
+        })
+    }
+}
+
+fn invalid_delta(path: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid delta for `{}`", path),
+    )
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    output.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+/// Rolling hash of a block so the hash at the next position can be found from the previous one
+fn block_hash(block: &[u8]) -> u64 {
+    block.iter().fold(0, |hash, &byte| {
+        hash.wrapping_mul(HASH_FACTOR).wrapping_add(u64::from(byte))
+    })
+}
+
+/// Adds the copy and insert operations for `target` to `output`
+fn write_ops(output: &mut Vec<u8>, base: &[u8], target: &[u8]) {
+    // Start of every block in the base by hash. Only the first block with a hash is kept.
+    let mut blocks = HashMap::new();
+    for (index, block) in base.chunks_exact(BLOCK_LEN).enumerate() {
+        blocks.entry(block_hash(block)).or_insert(index * BLOCK_LEN);
+    }
+
+    // Factor of the byte that leaves the block when rolling the hash
+    let out_factor = (1..BLOCK_LEN).fold(1u64, |factor, _| factor.wrapping_mul(HASH_FACTOR));
+
+    let mut insert_start = 0;
+    let mut pos = 0;
+    let mut hash = None;
+
+    while pos + BLOCK_LEN <= target.len() {
+        let current = *hash.get_or_insert_with(|| block_hash(&target[pos..pos + BLOCK_LEN]));
+
+        let found = blocks
+            .get(&current)
+            .copied()
+            .filter(|&start| base[start..start + BLOCK_LEN] == target[pos..pos + BLOCK_LEN]);
+
+        let mut base_start = match found {
+            Some(base_start) => base_start,
+            None => {
+                if pos + BLOCK_LEN < target.len() {
+                    let rolled = current
+                        .wrapping_sub(u64::from(target[pos]).wrapping_mul(out_factor))
+                        .wrapping_mul(HASH_FACTOR)
+                        .wrapping_add(u64::from(target[pos + BLOCK_LEN]));
+                    hash = Some(rolled);
+                }
+
+                pos += 1;
+                continue;
+            }
+        };
+
+        // Grow the match in both directions
+        let mut start = pos;
+        while start > insert_start && base_start > 0 && base[base_start - 1] == target[start - 1] {
+            start -= 1;
+            base_start -= 1;
+        }
+
+        let mut end = pos + BLOCK_LEN;
+        let mut base_end = base_start + (end - start);
+        while end < target.len() && base_end < base.len() && base[base_end] == target[end] {
+            end += 1;
+            base_end += 1;
+        }
+
+        if start > insert_start {
+            output.push(OP_INSERT);
+            write_varint(output, (start - insert_start) as u64);
+            output.extend_from_slice(&target[insert_start..start]);
+        }
+
+        output.push(OP_COPY);
+        write_varint(output, base_start as u64);
+        write_varint(output, (end - start) as u64);
+
+        insert_start = end;
+        pos = end;
+        hash = None;
+    }
+
+    if insert_start < target.len() {
+        output.push(OP_INSERT);
+        write_varint(output, (target.len() - insert_start) as u64);
+        output.extend_from_slice(&target[insert_start..]);
+    }
+}
+
+/// Creates a delta that turns `base` into `target`. The delta stores the size and hash of both
+/// so it's only applied to the version of the file it was made for. Save it as
+/// `<path>.delta` in a patch to use it with `PatchFs`.
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    output.extend_from_slice(MAGIC);
+    output.push(VERSION);
+    output.extend_from_slice(&(base.len() as u64).to_le_bytes());
+    output.extend_from_slice(&sha256(base));
+    output.extend_from_slice(&(target.len() as u64).to_le_bytes());
+    output.extend_from_slice(&sha256(target));
+
+    write_ops(&mut output, base, target);
+    output
+}
+
+/// Applies a delta made by `create_delta` to `base`. `path` is only used for errors.
+pub fn apply_delta(path: &str, base: &[u8], delta: &[u8]) -> Result<Vec<u8>, InternalError> {
+    let header = DeltaHeader::parse(path, delta)?;
+
+    if header.base_len != base.len() as u64 || header.base_hash != sha256(base) {
+        return Err(InternalError::PatchBaseMismatch { path: path.into() });
+    }
+
+    // The size in the header isn't trusted until the result has been checked
+    let mut output = Vec::with_capacity(header.len.min((base.len() + delta.len()) as u64) as usize);
+    let mut pos = HEADER_LEN;
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+
+        let data = match op {
+            OP_COPY => {
+                let start = read_varint(delta, &mut pos);
+                let len = read_varint(delta, &mut pos);
+
+                start
+                    .zip(len)
+                    .and_then(|(start, len)| base.get(start as usize..)?.get(..len as usize))
+            }
+            OP_INSERT => read_varint(delta, &mut pos).and_then(|len| {
+                let data = delta.get(pos..)?.get(..len as usize)?;
+                pos += data.len();
+                Some(data)
+            }),
+            _ => None,
+        };
+
+        let data = data.ok_or_else(|| invalid_delta(path))?;
+
+        // Repeated copies could otherwise grow the output far past the size in the header
+        if output.len() as u64 + data.len() as u64 > header.len {
+            return Err(invalid_delta(path).into());
+        }
+
+        output.extend_from_slice(data);
+    }
+
+    if output.len() as u64 != header.len || sha256(&output) != header.hash {
+        return Err(InternalError::VerificationFailed { path: path.into() });
+    }
+
+    Ok(output)
+}
+
+/// Layers a patch over a base mount. Files in the patch replace the files of the base, and
+/// delta files (`level.bin.delta`) are applied to the file of the base with the same name
+/// without the extension when it's loaded. This keeps patches for large data files small as
+/// only the changed parts are stored.
+///
+/// A delta is only applied to the version of the base file it was created from. Other versions
+/// fail with `InternalError::PatchBaseMismatch`. Deltas are applied in memory so the base file
+/// and the result have to fit in memory.
+pub struct PatchFs {
+    base: Box<dyn VfsDriver>,
+    patch: Box<dyn VfsDriver>,
+}
+
+impl PatchFs {
+    pub fn new(base: Box<dyn VfsDriver>, patch: Box<dyn VfsDriver>) -> PatchFs {
+        PatchFs { base, patch }
+    }
+
+    /// Returns the path of the delta for `path` if the patch has one
+    fn delta_path(&self, path: &str) -> Option<String> {
+        let delta_path = format!("{}.{}", path.trim_end_matches('/'), DELTA_EXT);
+
+        if self.patch.has_entry(&delta_path) == EntryType::File {
+            Some(delta_path)
+        } else {
+            None
+        }
+    }
+
+    fn load_delta(&self, path: &str, delta_path: &str) -> Result<Vec<u8>, InternalError> {
+        let (send_msg, _recv_msg) = unbounded::<RecvMsg>();
+        let msg = MsgSender::new(send_msg, u64::MAX).without_progress();
+
+        let delta = self.patch.load_file(delta_path, &msg)?;
+        let base = self.base.load_file(path, &msg).map_err(|e| match e {
+            // The base has to have the file the delta is for
+            InternalError::PathNotFound { .. } | InternalError::NotFile { .. } => {
+                InternalError::PatchBaseMismatch { path: path.into() }
+            }
+            e => e,
+        })?;
+
+        apply_delta(path, &base, &delta)
+    }
+}
+
+impl VfsDriver for PatchFs {
+    fn is_remote(&self) -> bool {
+        self.base.is_remote() || self.patch.is_remote()
+    }
+
+    /// Patch mounts are only created with `Evfs::mount_patch` or `Evfs::mount_driver`
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        Err(VfsError::UnsupportedMount {
+            mount: source.into(),
+        })
+    }
+
+    fn new_from_path(&self, path: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        Err(VfsError::UnsupportedMount { mount: path.into() })
+    }
+
+    ///
+    /// Progress is only reported when the result is done for patched files.
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        if self.patch.has_entry(path) == EntryType::File {
+            return self.patch.load_file(path, send_msg);
+        }
+
+        match self.delta_path(path) {
+            Some(delta_path) => {
+                let data = self.load_delta(path, &delta_path)?;
+                send_msg.finish(data.len() as u64)?;
+                Ok(data.into())
+            }
+            None => self.base.load_file(path, send_msg),
+        }
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        match self.patch.has_entry(path) {
+            EntryType::NotFound if self.delta_path(path).is_some() => EntryType::File,
+            EntryType::NotFound => self.base.has_entry(path),
+            entry_type => entry_type,
+        }
+    }
+
+    ///
+    /// The size of patched files is read from the header of the delta.
+    fn metadata(&self, path: &str) -> Result<Metadata, InternalError> {
+        if self.patch.has_entry(path) != EntryType::NotFound {
+            return self.patch.metadata(path);
+        }
+
+        let delta_path = match self.delta_path(path) {
+            Some(delta_path) => delta_path,
+            None => return self.base.metadata(path),
+        };
+
+        let (send_msg, _recv_msg) = unbounded::<RecvMsg>();
+        let msg = MsgSender::new(send_msg, u64::MAX).without_progress();
+        let header = self
+            .patch
+            .load_range(&delta_path, 0, HEADER_LEN as u64, &msg)?;
+        let header = DeltaHeader::parse(path, &header)?;
+
+        Ok(Metadata {
+            entry_type: EntryType::File,
+            len: header.len,
+            modified: self.patch.metadata(&delta_path)?.modified,
+        })
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        let mut entries = BTreeMap::new();
+
+        if self.base.has_entry(path) == EntryType::Directory {
+            for entry in self.base.read_dir(path)? {
+                entries.insert(entry.name, entry.entry_type);
+            }
+        }
+
+        if self.patch.has_entry(path) == EntryType::Directory {
+            for entry in self.patch.read_dir(path)? {
+                let delta_name = entry
+                    .name
+                    .strip_suffix(DELTA_EXT)
+                    .and_then(|name| name.strip_suffix('.'))
+                    .filter(|_| entry.entry_type == EntryType::File);
+
+                match delta_name {
+                    Some(name) => entries.insert(name.to_owned(), EntryType::File),
+                    None => entries.insert(entry.name, entry.entry_type),
+                };
+            }
+        }
+
+        Ok(entries
+            .into_iter()
+            .map(|(name, entry_type)| DirEntry { name, entry_type })
+            .collect())
+    }
+
+    fn can_decompress(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    fn supports_file_ext(&self, _file_ext: &str) -> bool {
+        false
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{Buffer, InternalError, RecvMsg};
+use std::cell::Cell;
+use std::io::Read;
+
+/// Default number of bytes between progress updates
+pub const DEFAULT_PROGRESS_GRANULARITY: u64 = 256 * 1024;
+
+/// Most memory `read_all` reserves before any data has been read
+const MAX_PREALLOCATION: u64 = 16 * 1024 * 1024;
+
+/// Progress of a load in bytes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// Number of bytes loaded so far
+    pub bytes_done: u64,
+    /// Total size of the file if it's known
+    pub bytes_total: Option<u64>,
+}
+
+impl Progress {
+    /// Progress between 0.0 and 1.0 if the total size is known
+    pub fn fraction(&self) -> Option<f32> {
+        match self.bytes_total {
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.bytes_done as f64 / total as f64) as f32),
+            None => None,
+        }
+    }
+}
+
+/// Used by drivers to send progress and data back to the requester of a load
+pub struct MsgSender {
+    sender: crossbeam_channel::Sender<RecvMsg>,
+    granularity: u64,
+    report_progress: bool,
+    last_progress: Cell<Option<Progress>>,
+}
+
+impl MsgSender {
+    /// `granularity` is the number of bytes that should be read between progress updates
+    pub fn new(sender: crossbeam_channel::Sender<RecvMsg>, granularity: u64) -> MsgSender {
+        MsgSender {
+            sender,
+            granularity: granularity.max(1),
+            report_progress: true,
+            last_progress: Cell::new(None),
+        }
+    }
+
+    /// Returns a sender to the same receiver that doesn't report any progress. This is used
+    /// when loading files on the way to the requested one (such as archives) so the reported
+    /// progress is always for the requested file.
+    pub fn without_progress(&self) -> MsgSender {
+        MsgSender {
+            report_progress: false,
+            ..MsgSender::new(self.sender.clone(), self.granularity)
+        }
+    }
+
+    /// Number of bytes that should be read between progress updates
+    pub fn granularity(&self) -> u64 {
+        self.granularity
+    }
+
+    pub fn send(&self, msg: RecvMsg) -> Result<(), InternalError> {
+        self.sender.send(msg)?;
+        Ok(())
+    }
+
+    /// Reports that `bytes_done` of `bytes_total` bytes have been loaded
+    pub fn progress(&self, bytes_done: u64, bytes_total: Option<u64>) -> Result<(), InternalError> {
+        if !self.report_progress {
+            return Ok(());
+        }
+
+        let progress = Progress {
+            bytes_done,
+            bytes_total,
+        };
+
+        self.last_progress.set(Some(progress));
+        self.send(RecvMsg::ReadProgress(progress))
+    }
+
+    /// Sends the final 100% update for a file of `len` bytes unless the driver already did
+    pub fn finish(&self, len: u64) -> Result<(), InternalError> {
+        let done = Progress {
+            bytes_done: len,
+            bytes_total: Some(len),
+        };
+
+        if self.last_progress.get() == Some(done) {
+            Ok(())
+        } else {
+            self.progress(len, Some(len))
+        }
+    }
+
+    /// Reads all data from `reader` while sending progress every `granularity` bytes.
+    /// `len` is the expected size of the data if known. It comes from archive headers or
+    /// servers so it's only used for progress and a limited pre-allocation, and the data can
+    /// be shorter or longer than it.
+    pub fn read_all(
+        &self,
+        reader: &mut dyn Read,
+        len: Option<u64>,
+    ) -> Result<Buffer, InternalError> {
+        let capacity = len
+            .unwrap_or(0)
+            .min(self.granularity)
+            .min(MAX_PREALLOCATION);
+        let mut output_data = Vec::with_capacity(capacity as usize);
+
+        self.progress(0, len)?;
+
+        loop {
+            let read = (&mut *reader)
+                .take(self.granularity)
+                .read_to_end(&mut output_data)?;
+
+            if read == 0 {
+                break;
+            }
+
+            self.progress(output_data.len() as u64, len)?;
+        }
+
+        self.finish(output_data.len() as u64)?;
+
+        Ok(output_data.into())
+    }
+}
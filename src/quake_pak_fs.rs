@@ -0,0 +1,123 @@
+use crate::archive::{invalid_data, read_at, read_name, read_u32, ArchiveSource, FlatArchive};
+use crate::{Buffer, DirEntry, EntryType, InternalError, MsgSender, ReadSeek, VfsDriver, VfsError};
+use std::fs::File;
+use std::io::{self, Read};
+
+const PACK_MAGIC: &[u8] = b"PACK";
+const HEADER_SIZE: usize = 12;
+/// 56 bytes of name followed by the offset and size
+const DIR_ENTRY_SIZE: usize = 64;
+const NAME_SIZE: usize = 56;
+
+/// Driver for the `.pak` archives used by Quake and Quake 2. Paths in the archive are used
+/// as they are, such as `maps/e1m1.bsp`.
+pub struct QuakePakFs {
+    archive: FlatArchive,
+}
+
+impl QuakePakFs {
+    pub fn new() -> QuakePakFs {
+        QuakePakFs {
+            archive: FlatArchive::empty(),
+        }
+    }
+
+    fn open_archive(source: ArchiveSource) -> io::Result<QuakePakFs> {
+        Ok(QuakePakFs {
+            archive: FlatArchive::new(source, read_directory)?,
+        })
+    }
+}
+
+impl Default for QuakePakFs {
+    fn default() -> QuakePakFs {
+        QuakePakFs::new()
+    }
+}
+
+fn read_directory(reader: &mut dyn ReadSeek) -> io::Result<Vec<(String, u64, u64)>> {
+    let header = read_at(reader, 0, HEADER_SIZE)?;
+
+    if !header.starts_with(PACK_MAGIC) {
+        return Err(invalid_data("not a Quake pak"));
+    }
+
+    let dir_offset = read_u32(&header, 4) as u64;
+    let dir_len = read_u32(&header, 8) as usize;
+    let dir = read_at(reader, dir_offset, dir_len)?;
+
+    Ok(dir
+        .chunks_exact(DIR_ENTRY_SIZE)
+        .map(|entry| {
+            (
+                read_name(&entry[..NAME_SIZE]).replace('\\', "/"),
+                read_u32(entry, NAME_SIZE) as u64,
+                read_u32(entry, NAME_SIZE + 4) as u64,
+            )
+        })
+        .collect())
+}
+
+impl VfsDriver for QuakePakFs {
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        let mut magic = [0; 4];
+
+        // The evfs pak format uses the same extension so the magic has to be checked
+        if std::fs::metadata(source)?.is_file()
+            && File::open(source)?.read_exact(&mut magic).is_ok()
+            && self.can_decompress(&magic)
+        {
+            Ok(())
+        } else {
+            Err(VfsError::UnsupportedMount {
+                mount: source.into(),
+            })
+        }
+    }
+
+    fn new_from_path(&self, filename: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        let source = ArchiveSource::File(filename.into());
+        Ok(Box::new(Self::open_archive(source)?))
+    }
+
+    fn new_from_data(&self, path: &str, data: Buffer) -> Result<Box<dyn VfsDriver>, InternalError> {
+        if !self.can_decompress(&data) {
+            return Err(InternalError::DecompressorNotFound { path: path.into() });
+        }
+
+        Ok(Box::new(Self::open_archive(ArchiveSource::Memory(data))?))
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        self.archive.load_file(path, send_msg)
+    }
+
+    #[cfg(feature = "mmap")]
+    fn map_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        self.archive.map_file(path, send_msg)
+    }
+
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        self.archive.open(path)
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        self.archive.has_entry(path)
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        Ok(self.archive.read_dir(path))
+    }
+
+    fn can_decompress(&self, data: &[u8]) -> bool {
+        data.starts_with(PACK_MAGIC)
+    }
+
+    fn supports_file_ext(&self, file_ext: &str) -> bool {
+        file_ext.eq_ignore_ascii_case("pak")
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{ArcDriver, VfsError};
+use std::collections::BTreeMap;
+
+/// Name of drivers installed with `Evfs::install_driver`
+const UNNAMED: &str = "custom";
+
+/// Names of the built-in drivers with their schemes and extensions
+const BUILTIN: &[(&str, &[&str], &[&str])] = &[
+    ("local", &["file"], &[]),
+    ("zip", &["zip"], &["zip"]),
+    (
+        "tar",
+        &["tar"],
+        &["tar", "tar.gz", "tgz", "tar.zst", "tzst"],
+    ),
+    // Both pak formats use the same extension so they are told apart by `can_mount`
+    ("pak", &["pak"], &[]),
+    ("quake-pak", &["quake-pak"], &[]),
+    ("wad", &["wad"], &["wad"]),
+    ("7z", &["7z"], &["7z"]),
+    ("cas", &["cas"], &["manifest"]),
+    ("compressed", &[], &["xz", "bz2"]),
+    ("http", &["http", "https"], &[]),
+];
+
+/// Drivers that mounts are created with. A source is matched to a driver by its scheme (such
+/// as `zip://data.bin`) or, for files, by its extension. Other sources are offered to all
+/// drivers in the order they were installed and the first one accepting it in
+/// `VfsDriver::can_mount` is used.
+#[derive(Default)]
+pub(crate) struct Registry {
+    /// Drivers with their names in the order they were installed
+    drivers: Vec<(String, ArcDriver)>,
+    /// Driver names by scheme
+    schemes: BTreeMap<String, String>,
+    /// Driver names by extension, without the leading dot
+    extensions: BTreeMap<String, String>,
+}
+
+/// Returns why a driver rejected a source
+pub(crate) fn rejection_reason(e: VfsError) -> String {
+    match e {
+        VfsError::FileError(e) => e.to_string(),
+        VfsError::UnsupportedMount { .. } => "The source isn't supported by this driver".into(),
+        e => e.to_string(),
+    }
+}
+
+pub(crate) fn format_rejections(rejected: &[(String, String)]) -> String {
+    rejected
+        .iter()
+        .map(|(driver, reason)| format!("\n    {}: {}", driver, reason))
+        .collect()
+}
+
+impl Registry {
+    /// Installs `driver` as `name`, replacing the driver that had the name before
+    pub fn install(&mut self, name: &str, driver: ArcDriver) {
+        match self.drivers.iter_mut().find(|(n, _)| n == name) {
+            Some(installed) => installed.1 = driver,
+            None => self.drivers.push((name.into(), driver)),
+        }
+    }
+
+    /// Sets up the schemes and extensions of the built-in drivers that are installed
+    pub fn add_builtin_schemes(&mut self) {
+        for (name, schemes, extensions) in BUILTIN {
+            if self.get(name).is_err() {
+                continue;
+            }
+
+            for scheme in *schemes {
+                self.schemes.insert((*scheme).into(), (*name).into());
+            }
+
+            for ext in *extensions {
+                self.extensions.insert((*ext).into(), (*name).into());
+            }
+        }
+    }
+
+    /// Installs a driver that is only used when no scheme matches
+    pub fn install_unnamed(&mut self, driver: ArcDriver) {
+        self.drivers.push((UNNAMED.into(), driver));
+    }
+
+    pub fn get(&self, name: &str) -> Result<&ArcDriver, VfsError> {
+        self.drivers
+            .iter()
+            .find(|(n, _)| n == name && n != UNNAMED)
+            .map(|(_, driver)| driver)
+            .ok_or_else(|| VfsError::UnknownDriver { name: name.into() })
+    }
+
+    /// All drivers in the order they were installed, used for archives inside mounts
+    pub fn drivers(&self) -> Vec<ArcDriver> {
+        self.drivers
+            .iter()
+            .map(|(_, driver)| driver.clone())
+            .collect()
+    }
+
+    pub fn set_scheme(&mut self, scheme: &str, name: &str) -> Result<(), VfsError> {
+        self.get(name)?;
+        self.schemes
+            .insert(scheme.to_ascii_lowercase(), name.into());
+        Ok(())
+    }
+
+    pub fn set_extension(&mut self, ext: &str, name: &str) -> Result<(), VfsError> {
+        self.get(name)?;
+        self.extensions.insert(
+            ext.trim_start_matches('.').to_ascii_lowercase(),
+            name.into(),
+        );
+        Ok(())
+    }
+
+    /// Returns the driver for the scheme of `source` together with what should be passed to
+    /// it. Remote drivers get the whole URL and others only the path after the scheme.
+    pub fn for_scheme<'a>(&self, source: &'a str) -> Option<(&ArcDriver, &'a str)> {
+        let (scheme, path) = source.split_once("://")?;
+        let driver = self
+            .get(self.schemes.get(&scheme.to_ascii_lowercase())?)
+            .ok()?;
+
+        if driver.is_remote() {
+            Some((driver, source))
+        } else {
+            Some((driver, path))
+        }
+    }
+
+    /// Returns the driver of the longest registered extension `source` has
+    pub fn for_extension(&self, source: &str) -> Option<&ArcDriver> {
+        let source = source.to_ascii_lowercase();
+        let name = self
+            .extensions
+            .iter()
+            .filter(|(ext, _)| {
+                source
+                    .strip_suffix(ext.as_str())
+                    .is_some_and(|rest| rest.ends_with('.'))
+            })
+            .max_by_key(|(ext, _)| ext.len())
+            .map(|(_, name)| name)?;
+
+        self.get(name).ok()
+    }
+
+    /// Returns the drivers with their names in the order they were installed
+    pub fn iter(&self) -> impl Iterator<Item = &(String, ArcDriver)> {
+        self.drivers.iter()
+    }
+}
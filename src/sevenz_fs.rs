@@ -0,0 +1,243 @@
+use crate::{Buffer, DirEntry, EntryType, InternalError, Metadata, MsgSender, VfsDriver, VfsError};
+use sevenz_rust::{Password, SevenZReader};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+const SEVENZ_MAGIC: &[u8] = &[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c];
+
+/// Where the archive data comes from
+enum Source {
+    File(String),
+    /// Used for archives inside other mounts
+    Memory(Buffer),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum IndexEntry {
+    File { size: u64 },
+    Directory,
+}
+
+/// Driver for 7z archives. The archive is indexed when it's mounted, but as 7z archives are
+/// usually solid a file is decompressed by decoding its block from the start, so loading many
+/// small files from a large block is slow. Encrypted archives are not supported.
+pub struct SevenZipFs {
+    source: Source,
+    index: BTreeMap<String, IndexEntry>,
+}
+
+impl SevenZipFs {
+    pub fn new() -> SevenZipFs {
+        SevenZipFs {
+            source: Source::Memory(Buffer::default()),
+            index: BTreeMap::new(),
+        }
+    }
+
+    fn open_archive(source: Source) -> io::Result<SevenZipFs> {
+        let index = match &source {
+            Source::File(filename) => build_index(open_reader(File::open(filename)?)?),
+            Source::Memory(data) => build_index(open_reader(Cursor::new(data.clone()))?),
+        };
+
+        Ok(SevenZipFs { source, index })
+    }
+
+    fn find_file(&self, path: &str) -> Result<u64, InternalError> {
+        match self.index.get(path) {
+            Some(IndexEntry::File { size }) => Ok(*size),
+            Some(IndexEntry::Directory) => Err(InternalError::NotFile { path: path.into() }),
+            None => Err(InternalError::PathNotFound { path: path.into() }),
+        }
+    }
+
+    /// Decodes the archive up to `path` and reads it
+    fn read_file<R: Read + io::Seek>(
+        mut reader: SevenZReader<R>,
+        path: &str,
+        size: u64,
+        send_msg: &MsgSender,
+    ) -> Result<Buffer, InternalError> {
+        let mut result = None;
+
+        reader
+            .for_each_entries(|entry, data| {
+                if entry.name().replace('\\', "/").trim_matches('/') != path {
+                    return Ok(true);
+                }
+
+                result = Some(send_msg.read_all(data, Some(size)));
+                Ok(false)
+            })
+            .map_err(archive_error)?;
+
+        result.unwrap_or_else(|| Err(InternalError::PathNotFound { path: path.into() }))
+    }
+}
+
+impl Default for SevenZipFs {
+    fn default() -> SevenZipFs {
+        SevenZipFs::new()
+    }
+}
+
+fn archive_error(e: sevenz_rust::Error) -> io::Error {
+    match e {
+        sevenz_rust::Error::Io(e, _) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
+
+fn open_reader<R: Read + io::Seek>(mut reader: R) -> io::Result<SevenZReader<R>> {
+    let len = reader.seek(io::SeekFrom::End(0))?;
+    reader.seek(io::SeekFrom::Start(0))?;
+    SevenZReader::new(reader, len, Password::empty()).map_err(archive_error)
+}
+
+/// Collects the files and directories of the archive. Parent directories are added even if the
+/// archive doesn't have entries for them.
+fn build_index<R: Read + io::Seek>(reader: SevenZReader<R>) -> BTreeMap<String, IndexEntry> {
+    let mut index = BTreeMap::new();
+
+    for entry in &reader.archive().files {
+        let path = entry.name().replace('\\', "/");
+        let path = path.trim_matches('/');
+
+        if path.is_empty() || entry.is_anti_item {
+            continue;
+        }
+
+        let mut parent = path;
+        while let Some(end) = parent.rfind('/') {
+            parent = &parent[..end];
+            index
+                .entry(parent.to_owned())
+                .or_insert(IndexEntry::Directory);
+        }
+
+        let index_entry = if entry.is_directory() {
+            IndexEntry::Directory
+        } else {
+            IndexEntry::File { size: entry.size() }
+        };
+
+        index.insert(path.to_owned(), index_entry);
+    }
+
+    index
+}
+
+impl VfsDriver for SevenZipFs {
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        let metadata = std::fs::metadata(source)?;
+        let is_7z = Path::new(source)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("7z"));
+
+        if metadata.is_file() && is_7z {
+            Ok(())
+        } else {
+            Err(VfsError::UnsupportedMount {
+                mount: source.into(),
+            })
+        }
+    }
+
+    fn new_from_path(&self, filename: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        let source = Source::File(filename.into());
+        Ok(Box::new(Self::open_archive(source)?))
+    }
+
+    fn new_from_data(&self, path: &str, data: Buffer) -> Result<Box<dyn VfsDriver>, InternalError> {
+        if !self.can_decompress(&data) {
+            return Err(InternalError::DecompressorNotFound { path: path.into() });
+        }
+
+        Ok(Box::new(Self::open_archive(Source::Memory(data))?))
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let size = self.find_file(path)?;
+
+        match &self.source {
+            Source::File(filename) => {
+                let reader = open_reader(File::open(filename)?)?;
+                Self::read_file(reader, path, size, send_msg)
+            }
+            Source::Memory(data) => {
+                let reader = open_reader(Cursor::new(data.clone()))?;
+                Self::read_file(reader, path, size, send_msg)
+            }
+        }
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        match self.index.get(path) {
+            _ if path.is_empty() => EntryType::Directory,
+            Some(IndexEntry::File { .. }) => EntryType::File,
+            Some(IndexEntry::Directory) => EntryType::Directory,
+            None => EntryType::NotFound,
+        }
+    }
+
+    ///
+    /// Sizes come from the index so the file doesn't have to be decompressed.
+    fn metadata(&self, path: &str) -> Result<Metadata, InternalError> {
+        let (entry_type, len) = match self.index.get(path) {
+            Some(IndexEntry::File { size }) => (EntryType::File, *size),
+            Some(IndexEntry::Directory) => (EntryType::Directory, 0),
+            None if path.is_empty() => (EntryType::Directory, 0),
+            None => return Err(InternalError::PathNotFound { path: path.into() }),
+        };
+
+        Ok(Metadata {
+            entry_type,
+            len,
+            modified: None,
+        })
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        let prefix = match path.trim_end_matches('/') {
+            "" => String::new(),
+            dir => format!("{}/", dir),
+        };
+
+        Ok(self
+            .index
+            .range(prefix.clone()..)
+            .take_while(|(name, _)| name.starts_with(&prefix))
+            .filter_map(|(name, entry)| {
+                let name = &name[prefix.len()..];
+
+                if name.contains('/') {
+                    return None;
+                }
+
+                let entry_type = match entry {
+                    IndexEntry::File { .. } => EntryType::File,
+                    IndexEntry::Directory => EntryType::Directory,
+                };
+
+                Some(DirEntry {
+                    name: name.to_owned(),
+                    entry_type,
+                })
+            })
+            .collect())
+    }
+
+    fn can_decompress(&self, data: &[u8]) -> bool {
+        data.starts_with(SEVENZ_MAGIC)
+    }
+
+    fn supports_file_ext(&self, file_ext: &str) -> bool {
+        file_ext.eq_ignore_ascii_case("7z")
+    }
+}
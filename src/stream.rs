@@ -0,0 +1,223 @@
+use std::io::{self, SeekFrom};
+#[cfg(any(
+    feature = "zip-fs",
+    feature = "tar-fs",
+    feature = "embedded-fs",
+    feature = "pak-fs",
+    feature = "quake-pak-fs",
+    feature = "wad-fs",
+    feature = "xz-fs",
+    feature = "bzip2-fs"
+))]
+use std::io::{Read, Seek};
+
+/// Resolves a `SeekFrom` against the current position and length of a stream
+pub fn seek_position(pos: SeekFrom, current: u64, len: u64) -> io::Result<u64> {
+    let new_pos = match pos {
+        SeekFrom::Start(p) => Some(p),
+        SeekFrom::Current(p) => offset_by(current, p),
+        SeekFrom::End(p) => offset_by(len, p),
+    };
+
+    new_pos.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid seek to a negative or overflowing position",
+        )
+    })
+}
+
+fn offset_by(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.unsigned_abs())
+    }
+}
+
+/// Exposes the range `offset..offset + len` of a seekable reader as its own stream. This is
+/// used for archive entries that are stored without compression.
+#[cfg(any(
+    feature = "zip-fs",
+    feature = "tar-fs",
+    feature = "pak-fs",
+    feature = "quake-pak-fs",
+    feature = "wad-fs"
+))]
+pub struct SubReader<R> {
+    inner: R,
+    offset: u64,
+    len: u64,
+    pos: u64,
+}
+
+#[cfg(any(
+    feature = "zip-fs",
+    feature = "tar-fs",
+    feature = "pak-fs",
+    feature = "quake-pak-fs",
+    feature = "wad-fs"
+))]
+impl<R: Read + Seek> SubReader<R> {
+    pub fn new(mut inner: R, offset: u64, len: u64) -> io::Result<SubReader<R>> {
+        inner.seek(SeekFrom::Start(offset))?;
+
+        Ok(SubReader {
+            inner,
+            offset,
+            len,
+            pos: 0,
+        })
+    }
+}
+
+#[cfg(any(
+    feature = "zip-fs",
+    feature = "tar-fs",
+    feature = "pak-fs",
+    feature = "quake-pak-fs",
+    feature = "wad-fs"
+))]
+impl<R: Read + Seek> Read for SubReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = (self.len - self.pos.min(self.len)) as usize;
+        let max = buf.len().min(left);
+
+        if max == 0 {
+            return Ok(0);
+        }
+
+        let read = self.inner.read(&mut buf[..max])?;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(any(
+    feature = "zip-fs",
+    feature = "tar-fs",
+    feature = "pak-fs",
+    feature = "quake-pak-fs",
+    feature = "wad-fs"
+))]
+impl<R: Read + Seek> Seek for SubReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_position(pos, self.pos, self.len)?;
+        self.inner.seek(SeekFrom::Start(self.offset + self.pos))?;
+        Ok(self.pos)
+    }
+}
+
+/// Function used by `DecoderStream` to (re)create the decoder from the start of the data
+#[cfg(any(
+    feature = "zip-fs",
+    feature = "tar-fs",
+    feature = "embedded-fs",
+    feature = "pak-fs",
+    feature = "xz-fs",
+    feature = "bzip2-fs"
+))]
+pub type DecoderFactory = Box<dyn Fn() -> io::Result<Box<dyn Read + Send>> + Send>;
+
+/// Makes a decompressing reader seekable. Seeking forward reads and discards data and seeking
+/// backwards restarts decoding from the beginning, so this is only fast for mostly linear
+/// access, but it allows compressed entries to be used with the same API as uncompressed ones.
+#[cfg(any(
+    feature = "zip-fs",
+    feature = "tar-fs",
+    feature = "embedded-fs",
+    feature = "pak-fs",
+    feature = "xz-fs",
+    feature = "bzip2-fs"
+))]
+pub struct DecoderStream {
+    factory: DecoderFactory,
+    decoder: Box<dyn Read + Send>,
+    /// Position of the decoder in the uncompressed data
+    decoder_pos: u64,
+    /// Position requested by the user
+    pos: u64,
+    len: u64,
+}
+
+#[cfg(any(
+    feature = "zip-fs",
+    feature = "tar-fs",
+    feature = "embedded-fs",
+    feature = "pak-fs",
+    feature = "xz-fs",
+    feature = "bzip2-fs"
+))]
+impl DecoderStream {
+    /// `len` is the uncompressed size of the data
+    pub fn new(factory: DecoderFactory, len: u64) -> io::Result<DecoderStream> {
+        let decoder = factory()?;
+
+        Ok(DecoderStream {
+            factory,
+            decoder,
+            decoder_pos: 0,
+            pos: 0,
+            len,
+        })
+    }
+
+    fn sync_decoder(&mut self) -> io::Result<()> {
+        if self.pos < self.decoder_pos {
+            self.decoder = (self.factory)()?;
+            self.decoder_pos = 0;
+        }
+
+        let skip = self.pos - self.decoder_pos;
+
+        if skip > 0 {
+            let skipped = io::copy(&mut (&mut self.decoder).take(skip), &mut io::sink())?;
+            self.decoder_pos += skipped;
+
+            if skipped != skip {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(any(
+    feature = "zip-fs",
+    feature = "tar-fs",
+    feature = "embedded-fs",
+    feature = "pak-fs",
+    feature = "xz-fs",
+    feature = "bzip2-fs"
+))]
+impl Read for DecoderStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len {
+            return Ok(0);
+        }
+
+        self.sync_decoder()?;
+
+        let read = self.decoder.read(buf)?;
+        self.decoder_pos += read as u64;
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(any(
+    feature = "zip-fs",
+    feature = "tar-fs",
+    feature = "embedded-fs",
+    feature = "pak-fs",
+    feature = "xz-fs",
+    feature = "bzip2-fs"
+))]
+impl Seek for DecoderStream {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // The decoder is only moved on the next read so seeking around is cheap
+        self.pos = seek_position(pos, self.pos, self.len)?;
+        Ok(self.pos)
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{
+    ArcDriver, Buffer, DirEntry, EntryType, InternalError, Metadata, MsgSender, ReadSeek,
+    VfsDriver, VfsError,
+};
+use std::borrow::Cow;
+
+/// Separates the source of a mount from the directory inside it to mount, as in
+/// `data/base.zip!/gfx/textures`
+pub(crate) const SUB_PATH_SEPARATOR: &str = "!/";
+
+/// Splits a source such as `data/base.zip!/gfx` into the source and the directory inside it
+pub(crate) fn split_sub_path(source: &str) -> (&str, Option<&str>) {
+    match source.find(SUB_PATH_SEPARATOR) {
+        Some(index) => (
+            &source[..index],
+            Some(&source[index + SUB_PATH_SEPARATOR.len()..]),
+        ),
+        None => (source, None),
+    }
+}
+
+/// Wraps another driver so only the directory `prefix` in it is visible, with paths relative
+/// to that directory. This is what mounts of sources with a sub-path use.
+pub(crate) struct SubDirFs {
+    inner: ArcDriver,
+    prefix: String,
+}
+
+impl SubDirFs {
+    pub fn new(inner: ArcDriver, prefix: &str) -> SubDirFs {
+        SubDirFs {
+            inner,
+            prefix: prefix.trim_matches('/').into(),
+        }
+    }
+
+    /// Returns the path in the wrapped driver. Paths that go up with `..` could leave the
+    /// directory so they aren't found.
+    fn path<'a>(&self, path: &'a str) -> Result<Cow<'a, str>, InternalError> {
+        let path = path.trim_start_matches('/');
+
+        if path.split(['/', '\\']).any(|name| name == "..") {
+            return Err(InternalError::PathNotFound { path: path.into() });
+        }
+
+        Ok(if self.prefix.is_empty() {
+            Cow::Borrowed(path)
+        } else if path.is_empty() {
+            Cow::Owned(self.prefix.clone())
+        } else {
+            Cow::Owned(format!("{}/{}", self.prefix, path))
+        })
+    }
+}
+
+impl VfsDriver for SubDirFs {
+    fn is_remote(&self) -> bool {
+        self.inner.is_remote()
+    }
+
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        Err(VfsError::UnsupportedMount {
+            mount: source.into(),
+        })
+    }
+
+    fn new_from_path(&self, path: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        Err(VfsError::UnsupportedMount { mount: path.into() })
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        self.inner.load_file(&self.path(path)?, send_msg)
+    }
+
+    fn map_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        self.inner.map_file(&self.path(path)?, send_msg)
+    }
+
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        self.inner.open(&self.path(path)?)
+    }
+
+    fn stream_file(&self, path: &str, send_msg: &MsgSender) -> Result<(), InternalError> {
+        self.inner.stream_file(&self.path(path)?, send_msg)
+    }
+
+    fn load_range(
+        &self,
+        path: &str,
+        offset: u64,
+        len: u64,
+        send_msg: &MsgSender,
+    ) -> Result<Buffer, InternalError> {
+        self.inner
+            .load_range(&self.path(path)?, offset, len, send_msg)
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        match self.path(path) {
+            Ok(path) => self.inner.has_entry(&path),
+            Err(_) => EntryType::NotFound,
+        }
+    }
+
+    fn metadata(&self, path: &str) -> Result<Metadata, InternalError> {
+        self.inner.metadata(&self.path(path)?)
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        self.inner.read_dir(&self.path(path)?)
+    }
+
+    fn can_decompress(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    fn supports_file_ext(&self, _file_ext: &str) -> bool {
+        false
+    }
+
+    fn write_file(&self, path: &str, data: Buffer) -> Result<(), InternalError> {
+        self.inner.write_file(&self.path(path)?, data)
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), InternalError> {
+        self.inner.create_dir(&self.path(path)?)
+    }
+
+    fn remove(&self, path: &str) -> Result<(), InternalError> {
+        self.inner.remove(&self.path(path)?)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), InternalError> {
+        self.inner.rename(&self.path(from)?, &self.path(to)?)
+    }
+}
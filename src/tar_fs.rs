@@ -0,0 +1,334 @@
+use crate::stream::{DecoderFactory, DecoderStream, SubReader};
+use crate::{Buffer, DirEntry, EntryType, InternalError, MsgSender, ReadSeek, VfsDriver, VfsError};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+
+/// File extensions that are mounted with `TarFs`
+const TAR_EXTENSIONS: [&str; 5] = [".tar", ".tar.gz", ".tgz", ".tar.zst", ".tzst"];
+/// Position of the `ustar` magic in the header of the first entry
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn detect(header: &[u8]) -> Compression {
+        if header.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if header.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    fn decoder<'a, R: Read + Send + 'a>(self, reader: R) -> io::Result<Box<dyn Read + Send + 'a>> {
+        Ok(match self {
+            Compression::None => Box::new(reader),
+            Compression::Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        })
+    }
+}
+
+/// Where the archive data comes from
+enum Source {
+    /// Archive on disk. Compressed archives are decompressed from the start for each load.
+    File {
+        filename: String,
+        compression: Compression,
+    },
+    /// Uncompressed archive in memory. Used for archives inside other mounts.
+    Memory(Buffer),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum IndexEntry {
+    File { offset: u64, size: u64 },
+    Directory,
+}
+
+/// Driver for tar archives that are uncompressed or compressed with gzip or zstd. The archive
+/// is indexed when it's mounted so finding files doesn't require reading through it.
+pub struct TarFs {
+    source: Source,
+    index: BTreeMap<String, IndexEntry>,
+}
+
+impl TarFs {
+    pub fn new() -> TarFs {
+        TarFs {
+            source: Source::Memory(Buffer::default()),
+            index: BTreeMap::new(),
+        }
+    }
+
+    fn find_file(&self, path: &str) -> Result<(u64, u64), InternalError> {
+        match self.index.get(path) {
+            Some(IndexEntry::File { offset, size }) => Ok((*offset, *size)),
+            Some(IndexEntry::Directory) => Err(InternalError::NotFile { path: path.into() }),
+            None => Err(InternalError::PathNotFound { path: path.into() }),
+        }
+    }
+
+    /// Returns the data of a file in an archive that is kept in memory
+    fn memory_slice(data: &Buffer, offset: u64, size: u64) -> Result<Buffer, InternalError> {
+        match offset.checked_add(size) {
+            Some(end) if end <= data.len() as u64 => Ok(data.slice(offset as usize..end as usize)),
+            _ => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+}
+
+impl Default for TarFs {
+    fn default() -> TarFs {
+        TarFs::new()
+    }
+}
+
+/// Reads the headers of all entries in an uncompressed tar stream. Offsets are positions in the
+/// uncompressed data. Links and special files are skipped.
+fn build_index(reader: impl Read) -> io::Result<BTreeMap<String, IndexEntry>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut index = BTreeMap::new();
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.path()?.to_string_lossy().replace('\\', "/");
+        let path = path.trim_start_matches("./").trim_matches('/');
+
+        if path.is_empty() {
+            continue;
+        }
+
+        let index_entry = match entry.header().entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => IndexEntry::File {
+                offset: entry.raw_file_position(),
+                size: entry.size(),
+            },
+            tar::EntryType::Directory => IndexEntry::Directory,
+            _ => continue,
+        };
+
+        // Directories don't need to have their own entries in the archive
+        let mut parent = path;
+        while let Some(end) = parent.rfind('/') {
+            parent = &parent[..end];
+            index
+                .entry(parent.to_owned())
+                .or_insert(IndexEntry::Directory);
+        }
+
+        index.insert(path.to_owned(), index_entry);
+    }
+
+    Ok(index)
+}
+
+/// Returns true if the uncompressed data starts with a tar header
+fn is_tar_header(data: &[u8]) -> bool {
+    data.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC)
+}
+
+impl VfsDriver for TarFs {
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        let metadata = std::fs::metadata(source)?;
+        let source_lower = source.to_ascii_lowercase();
+
+        if metadata.is_file() && TAR_EXTENSIONS.iter().any(|ext| source_lower.ends_with(ext)) {
+            Ok(())
+        } else {
+            Err(VfsError::UnsupportedMount {
+                mount: source.into(),
+            })
+        }
+    }
+
+    fn new_from_path(&self, filename: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        let mut file = File::open(filename)?;
+        let mut header = [0; 4];
+        let read = file.read(&mut header)?;
+        let compression = Compression::detect(&header[..read]);
+
+        let file = BufReader::new(File::open(filename)?);
+        let index = build_index(compression.decoder(file)?)?;
+
+        Ok(Box::new(TarFs {
+            source: Source::File {
+                filename: filename.into(),
+                compression,
+            },
+            index,
+        }))
+    }
+
+    ///
+    /// Compressed archives are unpacked into memory as nested archives are already loaded.
+    fn new_from_data(
+        &self,
+        _path: &str,
+        data: Buffer,
+    ) -> Result<Box<dyn VfsDriver>, InternalError> {
+        let data = match Compression::detect(&data) {
+            Compression::None => data,
+            compression => {
+                let mut output_data = Vec::new();
+                compression
+                    .decoder(Cursor::new(data))?
+                    .read_to_end(&mut output_data)?;
+                Buffer::from(output_data)
+            }
+        };
+
+        Ok(Box::new(TarFs {
+            index: build_index(Cursor::new(data.clone()))?,
+            source: Source::Memory(data),
+        }))
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let (offset, size) = self.find_file(path)?;
+
+        if let Source::Memory(data) = &self.source {
+            let data = Self::memory_slice(data, offset, size)?;
+            send_msg.finish(size)?;
+            return Ok(data);
+        }
+
+        let mut stream = self.open(path)?;
+        send_msg.read_all(&mut stream, Some(size))
+    }
+
+    ///
+    /// Uncompressed archives on disk are mapped and the file is returned as a slice of the
+    /// mapping. Compressed archives are unpacked the same way as `load_file` does it.
+    #[cfg(feature = "mmap")]
+    fn map_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let filename = match &self.source {
+            Source::File {
+                filename,
+                compression: Compression::None,
+            } => filename,
+            _ => return self.load_file(path, send_msg),
+        };
+
+        let (offset, size) = self.find_file(path)?;
+        // SAFETY: Only the slice of the file is read from the mapping. A truncated archive
+        // raises SIGBUS on access and a modified one changes the returned data, see
+        // `Evfs::load_file_mapped`.
+        let map = unsafe { memmap2::Mmap::map(&File::open(filename)?)? };
+        let data = Self::memory_slice(&Buffer::from_owner(map), offset, size)?;
+
+        send_msg.finish(size)?;
+        Ok(data)
+    }
+
+    ///
+    /// Compressed archives are decompressed from the start of the archive up to the file
+    /// so seeking backwards in the stream is slow.
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        let (offset, size) = self.find_file(path)?;
+
+        match &self.source {
+            Source::Memory(data) => Ok(Box::new(Cursor::new(Self::memory_slice(
+                data, offset, size,
+            )?))),
+            Source::File {
+                filename,
+                compression: Compression::None,
+            } => Ok(Box::new(SubReader::new(
+                File::open(filename)?,
+                offset,
+                size,
+            )?)),
+            Source::File {
+                filename,
+                compression,
+            } => {
+                let filename = filename.clone();
+                let compression = *compression;
+
+                let factory: DecoderFactory =
+                    Box::new(move || compression.decoder(BufReader::new(File::open(&filename)?)));
+
+                // The archive only has to be decoded up to the end of the file
+                let archive = DecoderStream::new(factory, offset + size)?;
+                Ok(Box::new(SubReader::new(archive, offset, size)?))
+            }
+        }
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        match self.index.get(path) {
+            _ if path.is_empty() => EntryType::Directory,
+            Some(IndexEntry::File { .. }) => EntryType::File,
+            Some(IndexEntry::Directory) => EntryType::Directory,
+            None => EntryType::NotFound,
+        }
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        let prefix = match path.trim_end_matches('/') {
+            "" => String::new(),
+            dir => format!("{}/", dir),
+        };
+
+        // Sub directories are in the index as well so only direct children are listed
+        Ok(self
+            .index
+            .range(prefix.clone()..)
+            .take_while(|(name, _)| name.starts_with(&prefix))
+            .filter_map(|(name, entry)| {
+                let name = &name[prefix.len()..];
+
+                if name.contains('/') {
+                    return None;
+                }
+
+                let entry_type = match entry {
+                    IndexEntry::File { .. } => EntryType::File,
+                    IndexEntry::Directory => EntryType::Directory,
+                };
+
+                Some(DirEntry {
+                    name: name.to_owned(),
+                    entry_type,
+                })
+            })
+            .collect())
+    }
+
+    /// Detects tar data directly or inside gzip or zstd compression
+    fn can_decompress(&self, data: &[u8]) -> bool {
+        match Compression::detect(data) {
+            Compression::None => is_tar_header(data),
+            compression => {
+                let mut header = Vec::new();
+                compression
+                    .decoder(data)
+                    .and_then(|d| d.take(TAR_MAGIC_OFFSET as u64 + 8).read_to_end(&mut header))
+                    .is_ok()
+                    && is_tar_header(&header)
+            }
+        }
+    }
+
+    fn supports_file_ext(&self, file_ext: &str) -> bool {
+        matches!(
+            file_ext.to_ascii_lowercase().as_str(),
+            "tar" | "tgz" | "tzst"
+        )
+    }
+}
@@ -1,13 +1,43 @@
-use crate::RecvMsg;
+use crate::{Buffer, MsgSender, RecvMsg};
 use crate::{InternalError, VfsError};
+use crossbeam_channel::unbounded;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::time::SystemTime;
 
-#[derive(Eq, PartialEq)]
+/// Size of the chunks sent when streaming a file with `stream_file`
+pub const STREAM_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Stream returned when opening a file. Files can be read incrementally and seeked in
+/// without having to load the whole file into memory first.
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum EntryType {
     File,
     Directory,
     NotFound,
 }
 
+/// Entry in a directory listing
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Name of the entry without the directory
+    pub name: String,
+    pub entry_type: EntryType,
+}
+
+/// Information about a file or directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub entry_type: EntryType,
+    /// Size of the file in bytes. Always 0 for directories.
+    pub len: u64,
+    /// Time of the last modification if the driver knows it
+    pub modified: Option<SystemTime>,
+}
+
 /// File system implementations must implement this trait
 pub trait VfsDriver: Sync + Send {
     /// This indicates that the file system is remote (such as ftp, https) and has no local path
@@ -22,11 +52,115 @@ pub trait VfsDriver: Sync + Send {
     fn can_mount(&self, target: &str, source: &str) -> Result<(), VfsError>;
     /// Used when creating an instance of the driver with a path to load from
     fn new_from_path(&self, path: &str) -> Result<Box<dyn VfsDriver>, VfsError>;
+    /// Used when creating an instance of the driver for a file inside another mount (such as an
+    /// archive inside an archive). `data` is the loaded file and `path` is only for errors.
+    /// Drivers that can't read from memory return `InternalError::DecompressorNotFound`.
+    fn new_from_data(
+        &self,
+        path: &str,
+        _data: Buffer,
+    ) -> Result<Box<dyn VfsDriver>, InternalError> {
+        Err(InternalError::DecompressorNotFound { path: path.into() })
+    }
     /// Returns a handle which updates the progress and returns the loaded data. This will try to
-    /// decompress the data as well if an appropriate decompresser can be found.
-    fn load_file(
+    /// decompress the data as well if an appropriate decompresser can be found. Progress should
+    /// be sent every `msg.granularity()` bytes, `MsgSender::read_all` takes care of this.
+    fn load_file(&self, path: &str, msg: &MsgSender) -> Result<Buffer, InternalError>;
+    /// Same as `load_file` but allowed to return a buffer that borrows a memory mapping of the
+    /// file instead of a copy. Drivers that can't map their data fall back to `load_file`.
+    fn map_file(&self, path: &str, msg: &MsgSender) -> Result<Buffer, InternalError> {
+        self.load_file(path, msg)
+    }
+    /// Lists the entries of a directory. `path` is empty for the root of the mount.
+    /// Drivers that can't list directories (such as `HttpFs`) return no entries.
+    fn read_dir(&self, _path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        Ok(Vec::new())
+    }
+    /// Opens a file for streaming. The default implementation loads the whole file and
+    /// streams from memory so drivers should override it when they can do better.
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        // Keep the receiver alive so messages sent during loading don't fail
+        let (send_msg, _recv_msg) = unbounded::<RecvMsg>();
+        let msg = MsgSender::new(send_msg, u64::MAX).without_progress();
+        let data = self.load_file(path, &msg)?;
+        Ok(Box::new(Cursor::new(data)))
+    }
+    /// Reads a file and sends the data as `RecvMsg::Chunk` messages while reading, so the
+    /// receiver can start using the data before the whole file has been loaded.
+    fn stream_file(&self, path: &str, msg: &MsgSender) -> Result<(), InternalError> {
+        let mut stream = self.open(path)?;
+        let len = stream.seek(SeekFrom::End(0))?;
+        let mut offset = 0;
+
+        stream.seek(SeekFrom::Start(0))?;
+        msg.progress(0, Some(len))?;
+
+        loop {
+            let mut chunk = Vec::with_capacity(STREAM_CHUNK_SIZE as usize);
+            let read = (&mut stream)
+                .take(STREAM_CHUNK_SIZE)
+                .read_to_end(&mut chunk)?;
+
+            if read == 0 {
+                return msg.finish(offset);
+            }
+
+            msg.send(RecvMsg::Chunk {
+                offset,
+                data: chunk.into(),
+            })?;
+
+            offset += read as u64;
+            msg.progress(offset, Some(len))?;
+        }
+    }
+    /// Loads `len` bytes starting at `offset` of a file. If the range goes past the end of the
+    /// file the data up to the end is returned. The default implementation seeks in the stream
+    /// returned by `open` which means compressed data is read and discarded up to `offset`.
+    fn load_range(
         &self,
         path: &str,
-        msg: &crossbeam_channel::Sender<RecvMsg>,
-    ) -> Result<Box<[u8]>, InternalError>;
+        offset: u64,
+        len: u64,
+        msg: &MsgSender,
+    ) -> Result<Buffer, InternalError> {
+        let mut stream = self.open(path)?;
+        let file_len = stream.seek(SeekFrom::End(0))?;
+        let range_len = u64::min(len, file_len.saturating_sub(offset));
+
+        stream.seek(SeekFrom::Start(offset))?;
+        msg.read_all(&mut stream.take(len), Some(range_len))
+    }
+    /// Returns the type and size of an entry. The default implementation gets the size by
+    /// seeking to the end of the stream returned by `open`.
+    fn metadata(&self, path: &str) -> Result<Metadata, InternalError> {
+        let (entry_type, len) = match self.has_entry(path) {
+            EntryType::File => (EntryType::File, self.open(path)?.seek(SeekFrom::End(0))?),
+            EntryType::Directory => (EntryType::Directory, 0),
+            EntryType::NotFound => return Err(InternalError::PathNotFound { path: path.into() }),
+        };
+
+        Ok(Metadata {
+            entry_type,
+            len,
+            modified: None,
+        })
+    }
+    /// Writes a file, replacing it if it already exists. Drivers are read-only by default
+    /// and return `InternalError::ReadOnly`.
+    fn write_file(&self, path: &str, _data: Buffer) -> Result<(), InternalError> {
+        Err(InternalError::ReadOnly { path: path.into() })
+    }
+    /// Creates a directory and any missing parents
+    fn create_dir(&self, path: &str) -> Result<(), InternalError> {
+        Err(InternalError::ReadOnly { path: path.into() })
+    }
+    /// Removes a file or directory
+    fn remove(&self, path: &str) -> Result<(), InternalError> {
+        Err(InternalError::ReadOnly { path: path.into() })
+    }
+    /// Moves a file or directory to `to` inside the same driver, replacing `to` if it's a file
+    fn rename(&self, _from: &str, to: &str) -> Result<(), InternalError> {
+        Err(InternalError::ReadOnly { path: to.into() })
+    }
 }
@@ -0,0 +1,172 @@
+use crate::{
+    BatchHandle, BatchMode, Buffer, Evfs, Handle, Metadata, RecvMsg, StreamHandle, VfsError,
+};
+use crossbeam_channel::unbounded;
+
+/// Restricted handle to an `Evfs` returned by `Evfs::view`. Everything outside of the prefix
+/// of the view is hidden: paths are relative to the prefix (so `/config.toml` in a view of
+/// `/mods/foo` is `/mods/foo/config.toml`) and paths with `..` that would lead outside of it
+/// fail with `VfsError::OutsideView`.
+pub struct EvfsView<'a> {
+    vfs: &'a Evfs,
+    /// Prefix without a trailing slash, empty for the root
+    prefix: String,
+}
+
+/// Returns a handle that only receives `error`
+fn error_handle(error: VfsError) -> Handle {
+    let (send, recv) = unbounded();
+    let _ = send.send(RecvMsg::Error(error));
+    Handle { recv }
+}
+
+impl<'a> EvfsView<'a> {
+    pub(crate) fn new(vfs: &'a Evfs, prefix: &str) -> EvfsView<'a> {
+        let mut names: Vec<&str> = Vec::new();
+
+        // `..` in the prefix is resolved here so the view can't be escaped with it later
+        for name in prefix.split('/') {
+            match name {
+                "" | "." => (),
+                ".." => {
+                    names.pop();
+                }
+                name => names.push(name),
+            }
+        }
+
+        EvfsView {
+            vfs,
+            prefix: names.iter().map(|name| format!("/{}", name)).collect(),
+        }
+    }
+
+    /// The path in the `Evfs` the view is of
+    pub fn prefix(&self) -> &str {
+        if self.prefix.is_empty() {
+            "/"
+        } else {
+            &self.prefix
+        }
+    }
+
+    /// Returns the path in the `Evfs` of a path in the view
+    fn full_path(&self, path: &str) -> Result<String, VfsError> {
+        let mut full_path = self.prefix.clone();
+
+        for name in path.split('/') {
+            match name {
+                "" | "." => (),
+                ".." => return Err(VfsError::OutsideView { path: path.into() }),
+                name => {
+                    full_path.push('/');
+                    full_path.push_str(name);
+                }
+            }
+        }
+
+        if full_path.is_empty() {
+            full_path.push('/');
+        }
+
+        Ok(full_path)
+    }
+
+    /// Returns the path in the view of a path in the `Evfs`
+    fn view_path(&self, path: &str) -> String {
+        match path.strip_prefix(&self.prefix) {
+            Some("") => "/".into(),
+            Some(path) => path.into(),
+            None => path.into(),
+        }
+    }
+
+    /// Returns a view of a directory in this view
+    pub fn view(&self, prefix: &str) -> Result<EvfsView<'a>, VfsError> {
+        Ok(EvfsView::new(self.vfs, &self.full_path(prefix)?))
+    }
+
+    pub fn load_file(&self, path: &str) -> Handle {
+        match self.full_path(path) {
+            Ok(path) => self.vfs.load_file(&path),
+            Err(e) => error_handle(e),
+        }
+    }
+
+    pub fn load_file_mapped(&self, path: &str) -> Handle {
+        match self.full_path(path) {
+            Ok(path) => self.vfs.load_file_mapped(&path),
+            Err(e) => error_handle(e),
+        }
+    }
+
+    pub fn load_file_streaming(&self, path: &str) -> Handle {
+        match self.full_path(path) {
+            Ok(path) => self.vfs.load_file_streaming(&path),
+            Err(e) => error_handle(e),
+        }
+    }
+
+    pub fn load_range(&self, path: &str, offset: u64, len: u64) -> Handle {
+        match self.full_path(path) {
+            Ok(path) => self.vfs.load_range(&path, offset, len),
+            Err(e) => error_handle(e),
+        }
+    }
+
+    /// Starts loading a group of files, see `Evfs::load_batch`
+    pub fn load_batch<I, S>(&self, paths: I, mode: BatchMode) -> BatchHandle
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let loads = paths
+            .into_iter()
+            .map(|path| {
+                let path = path.as_ref();
+                (path.to_owned(), self.load_file(path))
+            })
+            .collect();
+
+        BatchHandle::new(loads, mode)
+    }
+
+    pub fn open(&self, path: &str) -> StreamHandle {
+        match self.full_path(path) {
+            Ok(path) => self.vfs.open(&path),
+            Err(e) => {
+                let (send, recv) = unbounded();
+                let _ = send.send(Err(e));
+                StreamHandle { recv }
+            }
+        }
+    }
+
+    pub fn metadata(&self, path: &str) -> Result<Metadata, VfsError> {
+        self.vfs.metadata(&self.full_path(path)?)
+    }
+
+    pub fn write_file(&self, path: &str, data: impl Into<Buffer>) -> Result<(), VfsError> {
+        self.vfs.write_file(&self.full_path(path)?, data)
+    }
+
+    pub fn create_dir(&self, path: &str) -> Result<(), VfsError> {
+        self.vfs.create_dir(&self.full_path(path)?)
+    }
+
+    pub fn remove(&self, path: &str) -> Result<(), VfsError> {
+        self.vfs.remove(&self.full_path(path)?)
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> Result<(), VfsError> {
+        self.vfs
+            .rename(&self.full_path(from)?, &self.full_path(to)?)
+    }
+
+    /// Returns the paths in the view of all files and directories matching a glob pattern,
+    /// see `Evfs::glob`
+    pub fn glob(&self, pattern: &str) -> Result<Vec<String>, VfsError> {
+        let paths = self.vfs.glob(&self.full_path(pattern)?)?;
+        Ok(paths.iter().map(|path| self.view_path(path)).collect())
+    }
+}
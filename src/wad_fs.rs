@@ -0,0 +1,170 @@
+use crate::archive::{invalid_data, read_at, read_name, read_u32, ArchiveSource, FlatArchive};
+use crate::{Buffer, DirEntry, EntryType, InternalError, MsgSender, ReadSeek, VfsDriver, VfsError};
+use std::fs::File;
+use std::io::{self, Read};
+
+const HEADER_SIZE: usize = 12;
+/// Offset and size followed by 8 bytes of name
+const DIR_ENTRY_SIZE: usize = 16;
+
+/// Lumps that belong to the map marker before them in maps using the binary format
+const MAP_LUMPS: [&str; 12] = [
+    "THINGS", "LINEDEFS", "SIDEDEFS", "VERTEXES", "SEGS", "SSECTORS", "NODES", "SECTORS", "REJECT",
+    "BLOCKMAP", "BEHAVIOR", "SCRIPTS",
+];
+
+/// Driver for Doom `.wad` files (both IWAD and PWAD).
+///
+/// Lumps are files named after the lump. Wads don't have directories but the lumps of a map
+/// (`THINGS`, `LINEDEFS`, ...) have the same names in every map so they are put in a directory
+/// named after the map marker, such as `E1M1/THINGS`. For UDMF maps everything from `TEXTMAP`
+/// to `ENDMAP` is in the map directory. When several lumps have the same name the last one is
+/// used, the same way the game does it.
+pub struct WadFs {
+    archive: FlatArchive,
+}
+
+impl WadFs {
+    pub fn new() -> WadFs {
+        WadFs {
+            archive: FlatArchive::empty(),
+        }
+    }
+
+    fn open_archive(source: ArchiveSource) -> io::Result<WadFs> {
+        Ok(WadFs {
+            archive: FlatArchive::new(source, read_directory)?,
+        })
+    }
+}
+
+impl Default for WadFs {
+    fn default() -> WadFs {
+        WadFs::new()
+    }
+}
+
+fn is_wad(data: &[u8]) -> bool {
+    data.starts_with(b"IWAD") || data.starts_with(b"PWAD")
+}
+
+fn read_directory(reader: &mut dyn ReadSeek) -> io::Result<Vec<(String, u64, u64)>> {
+    let header = read_at(reader, 0, HEADER_SIZE)?;
+
+    if !is_wad(&header) {
+        return Err(invalid_data("not a wad"));
+    }
+
+    let lump_count = read_u32(&header, 4) as usize;
+    let dir_offset = read_u32(&header, 8) as u64;
+    let dir_len = lump_count
+        .checked_mul(DIR_ENTRY_SIZE)
+        .ok_or_else(|| invalid_data("invalid lump count"))?;
+    let dir = read_at(reader, dir_offset, dir_len)?;
+
+    let lumps: Vec<(String, u64, u64)> = dir
+        .chunks_exact(DIR_ENTRY_SIZE)
+        .map(|entry| {
+            (
+                read_name(&entry[8..]).replace('/', "_"),
+                read_u32(entry, 0) as u64,
+                read_u32(entry, 4) as u64,
+            )
+        })
+        .collect();
+
+    let mut files = Vec::with_capacity(lumps.len());
+    let mut map: Option<&str> = None;
+    let mut in_udmf = false;
+
+    for (i, (name, offset, size)) in lumps.iter().enumerate() {
+        if let Some(map_name) = map {
+            if in_udmf || MAP_LUMPS.contains(&name.as_str()) {
+                files.push((format!("{}/{}", map_name, name), *offset, *size));
+
+                if name == "ENDMAP" {
+                    map = None;
+                }
+
+                continue;
+            }
+        }
+
+        // Map markers are usually empty and come right before the map lumps
+        match lumps.get(i + 1).map(|(next, _, _)| next.as_str()) {
+            Some(next @ "THINGS") | Some(next @ "TEXTMAP") => {
+                map = Some(name);
+                in_udmf = next == "TEXTMAP";
+            }
+            _ => {
+                map = None;
+                files.push((name.clone(), *offset, *size));
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+impl VfsDriver for WadFs {
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn can_mount(&self, _target: &str, source: &str) -> Result<(), VfsError> {
+        let mut magic = [0; 4];
+
+        if std::fs::metadata(source)?.is_file()
+            && File::open(source)?.read_exact(&mut magic).is_ok()
+            && is_wad(&magic)
+        {
+            Ok(())
+        } else {
+            Err(VfsError::UnsupportedMount {
+                mount: source.into(),
+            })
+        }
+    }
+
+    fn new_from_path(&self, filename: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
+        let source = ArchiveSource::File(filename.into());
+        Ok(Box::new(Self::open_archive(source)?))
+    }
+
+    fn new_from_data(&self, path: &str, data: Buffer) -> Result<Box<dyn VfsDriver>, InternalError> {
+        if !is_wad(&data) {
+            return Err(InternalError::DecompressorNotFound { path: path.into() });
+        }
+
+        Ok(Box::new(Self::open_archive(ArchiveSource::Memory(data))?))
+    }
+
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        self.archive.load_file(path, send_msg)
+    }
+
+    #[cfg(feature = "mmap")]
+    fn map_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        self.archive.map_file(path, send_msg)
+    }
+
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        self.archive.open(path)
+    }
+
+    fn has_entry(&self, path: &str) -> EntryType {
+        self.archive.has_entry(path)
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        Ok(self.archive.read_dir(path))
+    }
+
+    fn can_decompress(&self, data: &[u8]) -> bool {
+        is_wad(data)
+    }
+
+    fn supports_file_ext(&self, file_ext: &str) -> bool {
+        file_ext.eq_ignore_ascii_case("wad")
+    }
+}
@@ -0,0 +1,187 @@
+use crate::glob::{glob_match, has_wildcards, literal_prefix};
+use crate::{get_mounts_for_dir, mount_relative_path, EntryType, Mounts, VfsError};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Entry found when walking the virtual file system
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalkEntry {
+    /// Full virtual path of the entry
+    pub path: String,
+    pub entry_type: EntryType,
+    /// Number of directories below the root of the walk. Entries in the root have depth 1.
+    pub depth: usize,
+}
+
+/// Filter used to skip entries in a walk
+type WalkFilter<'a> = Box<dyn Fn(&WalkEntry) -> bool + 'a>;
+
+/// Recursive walk over the virtual file system, created with `Evfs::walk`.
+///
+/// The walk covers all mounts that overlay a directory. When several mounts have an entry
+/// with the same name only the one that would be loaded is returned.
+pub struct Walk<'a> {
+    mounts: &'a Mounts,
+    root: String,
+    max_depth: Option<usize>,
+    files_only: bool,
+    filter: Option<WalkFilter<'a>>,
+}
+
+impl<'a> Walk<'a> {
+    pub(crate) fn new(mounts: &'a Mounts, root: &str) -> Walk<'a> {
+        Walk {
+            mounts,
+            root: root.to_owned(),
+            max_depth: None,
+            files_only: false,
+            filter: None,
+        }
+    }
+
+    /// Only walk `depth` levels of directories. A depth of 1 only lists the root.
+    pub fn max_depth(mut self, depth: usize) -> Walk<'a> {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Only return files and not directories
+    pub fn files_only(mut self) -> Walk<'a> {
+        self.files_only = true;
+        self
+    }
+
+    /// Only return entries the filter accepts. Directories that are filtered out are still
+    /// walked.
+    pub fn filter<F>(mut self, filter: F) -> Walk<'a>
+    where
+        F: Fn(&WalkEntry) -> bool + 'a,
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Runs the walk and returns the entries sorted by path within each directory
+    pub fn entries(self) -> Result<Vec<WalkEntry>, VfsError> {
+        let root = match self.root.trim_end_matches('/') {
+            "" => "/",
+            root => root,
+        };
+
+        let entries = self.list_dir(root)?.ok_or_else(|| VfsError::NoMountFound {
+            path: self.root.clone(),
+        })?;
+
+        let mut output = Vec::new();
+        self.walk_entries(root, entries, 1, &mut output)?;
+        Ok(output)
+    }
+
+    fn walk_entries(
+        &self,
+        dir: &str,
+        entries: BTreeMap<String, EntryType>,
+        depth: usize,
+        output: &mut Vec<WalkEntry>,
+    ) -> Result<(), VfsError> {
+        for (name, entry_type) in entries {
+            let entry = WalkEntry {
+                path: join_path(dir, &name),
+                entry_type,
+                depth,
+            };
+
+            let is_dir = entry_type == EntryType::Directory;
+            let accepted = !(self.files_only && is_dir)
+                && self.filter.as_ref().is_none_or(|filter| filter(&entry));
+
+            let path = entry.path.clone();
+
+            if accepted {
+                output.push(entry);
+            }
+
+            if is_dir && self.max_depth.is_none_or(|max| depth < max) {
+                if let Some(sub_entries) = self.list_dir(&path)? {
+                    self.walk_entries(&path, sub_entries, depth + 1, output)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists a virtual directory by merging the listings of all mounts that overlay it.
+    /// Returns `None` if no mount has the directory.
+    fn list_dir(&self, dir: &str) -> Result<Option<BTreeMap<String, EntryType>>, VfsError> {
+        let mut entries = BTreeMap::new();
+        let mut found = false;
+
+        // Mounts are in priority order so the first entry with a name wins
+        for index in get_mounts_for_dir(dir, self.mounts) {
+            let mount = &self.mounts[index];
+            let path = mount_relative_path(&mount.target, dir);
+
+            if !path.is_empty() && mount.driver.has_entry(path) != EntryType::Directory {
+                continue;
+            }
+
+            found = true;
+
+            for entry in mount.driver.read_dir(path)? {
+                entries.entry(entry.name).or_insert(entry.entry_type);
+            }
+        }
+
+        // Mount points below the directory show up as directories
+        for mount in self.mounts {
+            if let Some(name) = child_name(dir, &mount.target) {
+                entries.insert(name.to_owned(), EntryType::Directory);
+                found = true;
+            }
+        }
+
+        Ok(if found { Some(entries) } else { None })
+    }
+}
+
+/// Returns the virtual paths matching a glob pattern
+pub fn glob(mounts: &Mounts, pattern: &str) -> Result<Vec<String>, VfsError> {
+    let root = if has_wildcards(pattern) {
+        literal_prefix(pattern)
+    } else {
+        Path::new(pattern)
+            .parent()
+            .and_then(|p| p.to_str())
+            .unwrap_or("/")
+    };
+
+    let mut walk = Walk::new(mounts, root);
+
+    // Without `**` the pattern can't match anything deeper than its number of components
+    if !pattern.contains("**") {
+        let root_depth = root.trim_end_matches('/').matches('/').count();
+        walk = walk.max_depth(pattern.matches('/').count() - root_depth);
+    }
+
+    let entries = match walk.filter(|e| glob_match(pattern, &e.path)).entries() {
+        Ok(entries) => entries,
+        // Nothing is mounted where the pattern points so nothing matches
+        Err(VfsError::NoMountFound { .. }) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+
+    Ok(entries.into_iter().map(|e| e.path).collect())
+}
+
+fn join_path(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+/// If `path` is below `dir` returns the name of the first component after `dir`
+fn child_name<'a>(dir: &str, path: &'a str) -> Option<&'a str> {
+    let dir = dir.trim_end_matches('/');
+    let rest = path.strip_prefix(dir)?.strip_prefix('/')?;
+
+    rest.split('/').next().filter(|name| !name.is_empty())
+}
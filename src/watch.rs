@@ -0,0 +1,316 @@
+use crate::glob::{glob_match, has_wildcards, literal_prefix};
+use log::*;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How often the watch thread checks for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What happened to a watched file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchEventKind {
+    /// The file was created (or moved into place, which is how many editors save)
+    Created,
+    /// The file was written to
+    Modified,
+    /// The file was deleted or moved away
+    Removed,
+}
+
+/// Sent when a watched file changes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// Path of the file in the virtual file system. When an archive mount is replaced on disk
+    /// this is the path of the mount itself as any file in it may have changed.
+    pub path: String,
+    pub kind: WatchEventKind,
+}
+
+/// Returned by `Evfs::watch`. Watching stops when the handle is dropped.
+pub struct WatchHandle {
+    pub recv: crossbeam_channel::Receiver<WatchEvent>,
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Returns true if any file inside the mount at `target` can match `pattern`
+pub fn mount_matches(pattern: &str, target: &str) -> bool {
+    let prefix = literal_prefix(pattern);
+    is_inside(target, prefix) || is_inside(prefix, target)
+}
+
+/// Returns true if the virtual path matches the pattern. Patterns without wildcards match the
+/// path itself and everything below it.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    if has_wildcards(pattern) {
+        glob_match(pattern, path)
+    } else {
+        is_inside(path, pattern)
+    }
+}
+
+fn is_inside(path: &str, dir: &str) -> bool {
+    let dir = dir.trim_end_matches('/');
+    path == dir || path.starts_with(dir) && path[dir.len()..].starts_with('/') || dir.is_empty()
+}
+
+/// Translates a path on disk back to the virtual file system
+fn virtual_path(target: &str, source: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(source).ok()?;
+    let mut virtual_path = target.trim_end_matches('/').to_owned();
+
+    for component in relative.components() {
+        virtual_path.push('/');
+        virtual_path.push_str(&component.as_os_str().to_string_lossy());
+    }
+
+    Some(virtual_path)
+}
+
+/// Watches a mount source on disk
+trait SourceWatcher: Send {
+    /// Adds the changes since the last call to `events`
+    fn poll(&mut self, events: &mut Vec<(PathBuf, WatchEventKind)>) -> io::Result<()>;
+}
+
+/// State of a file used for detecting changes by polling
+type FileState = (Option<SystemTime>, u64);
+
+/// Detects changes by comparing the modification time and size of files between polls. Used
+/// for archives and on platforms without native support for watching directories.
+struct PollWatcher {
+    root: PathBuf,
+    files: HashMap<PathBuf, FileState>,
+}
+
+impl PollWatcher {
+    fn new(root: &Path) -> PollWatcher {
+        let mut files = HashMap::new();
+        Self::scan(root, &mut files);
+
+        PollWatcher {
+            root: root.to_owned(),
+            files,
+        }
+    }
+
+    fn scan(path: &Path, files: &mut HashMap<PathBuf, FileState>) {
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return,
+        };
+
+        if metadata.is_file() {
+            files.insert(path.to_owned(), (metadata.modified().ok(), metadata.len()));
+        } else if let Ok(entries) = std::fs::read_dir(path) {
+            for entry in entries.flatten() {
+                Self::scan(&entry.path(), files);
+            }
+        }
+    }
+}
+
+impl SourceWatcher for PollWatcher {
+    fn poll(&mut self, events: &mut Vec<(PathBuf, WatchEventKind)>) -> io::Result<()> {
+        let mut files = HashMap::new();
+        Self::scan(&self.root, &mut files);
+
+        for (path, state) in &files {
+            match self.files.get(path) {
+                None => events.push((path.clone(), WatchEventKind::Created)),
+                Some(old_state) if old_state != state => {
+                    events.push((path.clone(), WatchEventKind::Modified))
+                }
+                _ => (),
+            }
+        }
+
+        for path in self.files.keys() {
+            if !files.contains_key(path) {
+                events.push((path.clone(), WatchEventKind::Removed));
+            }
+        }
+
+        self.files = files;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify_watcher {
+    use super::{SourceWatcher, WatchEventKind};
+    use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+    use std::collections::HashMap;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    /// Watches a directory tree using inotify
+    pub struct InotifyWatcher {
+        inotify: Inotify,
+        dirs: HashMap<WatchDescriptor, PathBuf>,
+        buffer: Vec<u8>,
+    }
+
+    impl InotifyWatcher {
+        pub fn new(root: &Path) -> io::Result<InotifyWatcher> {
+            let mut watcher = InotifyWatcher {
+                inotify: Inotify::init()?,
+                dirs: HashMap::new(),
+                buffer: vec![0; 64 * 1024],
+            };
+
+            watcher.add_dir(root)?;
+            Ok(watcher)
+        }
+
+        /// inotify doesn't watch sub directories so each one needs its own watch
+        fn add_dir(&mut self, dir: &Path) -> io::Result<()> {
+            let mask = WatchMask::CREATE
+                | WatchMask::CLOSE_WRITE
+                | WatchMask::DELETE
+                | WatchMask::MOVED_FROM
+                | WatchMask::MOVED_TO;
+
+            let wd = self.inotify.add_watch(dir, mask)?;
+            self.dirs.insert(wd, dir.to_owned());
+
+            for entry in std::fs::read_dir(dir)?.flatten() {
+                if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                    self.add_dir(&entry.path())?;
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    impl SourceWatcher for InotifyWatcher {
+        fn poll(&mut self, events: &mut Vec<(PathBuf, WatchEventKind)>) -> io::Result<()> {
+            loop {
+                let mut changes = Vec::new();
+
+                for event in self.inotify.read_events(&mut self.buffer)? {
+                    if let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) {
+                        changes.push((dir.join(name), event.mask));
+                    }
+                }
+
+                if changes.is_empty() {
+                    return Ok(());
+                }
+
+                for (path, mask) in changes {
+                    if mask.contains(EventMask::ISDIR) {
+                        if mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+                            self.add_dir(&path)?;
+                        }
+                        continue;
+                    }
+
+                    let kind = if mask.intersects(EventMask::CREATE | EventMask::MOVED_TO) {
+                        WatchEventKind::Created
+                    } else if mask.contains(EventMask::CLOSE_WRITE) {
+                        WatchEventKind::Modified
+                    } else {
+                        WatchEventKind::Removed
+                    };
+
+                    events.push((path, kind));
+                }
+            }
+        }
+    }
+}
+
+/// A mount that is being watched
+struct WatchedMount {
+    target: String,
+    source: PathBuf,
+    /// Archives report changes for the whole mount
+    is_archive: bool,
+    watcher: Box<dyn SourceWatcher>,
+}
+
+fn new_watcher(source: &Path) -> io::Result<Box<dyn SourceWatcher>> {
+    #[cfg(target_os = "linux")]
+    {
+        if source.is_dir() {
+            return Ok(Box::new(inotify_watcher::InotifyWatcher::new(source)?));
+        }
+    }
+
+    Ok(Box::new(PollWatcher::new(source)))
+}
+
+/// Starts watching the given (target, source) mounts for changes matching `pattern`
+pub fn watch(pattern: &str, mounts: Vec<(String, String)>) -> io::Result<WatchHandle> {
+    let mut watched = Vec::with_capacity(mounts.len());
+
+    for (target, source) in mounts {
+        let source = PathBuf::from(source);
+
+        watched.push(WatchedMount {
+            target,
+            is_archive: source.is_file(),
+            watcher: new_watcher(&source)?,
+            source,
+        });
+    }
+
+    let (send, recv) = crossbeam_channel::unbounded();
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop.clone();
+    let pattern = pattern.to_owned();
+
+    let thread = thread::Builder::new()
+        .name("evfs_watch_thread".to_string())
+        .spawn(move || {
+            let mut changes = Vec::new();
+
+            while !thread_stop.load(Ordering::Relaxed) {
+                for mount in &mut watched {
+                    if let Err(e) = mount.watcher.poll(&mut changes) {
+                        error!("evfs: Unable to watch {:#?}: {:#?}", mount.source, e);
+                    }
+
+                    for (path, kind) in changes.drain(..) {
+                        let path = if mount.is_archive {
+                            Some(mount.target.clone())
+                        } else {
+                            virtual_path(&mount.target, &mount.source, &path)
+                                .filter(|path| path_matches(&pattern, path))
+                        };
+
+                        if let Some(path) = path {
+                            if send.send(WatchEvent { path, kind }).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+
+                thread::sleep(POLL_INTERVAL);
+            }
+        })?;
+
+    Ok(WatchHandle {
+        recv,
+        stop,
+        thread: Some(thread),
+    })
+}
@@ -1,18 +1,730 @@
-use crate::{EntryType, InternalError, RecvMsg, VfsDriver, VfsError};
+use crate::stream::{DecoderFactory, DecoderStream, SubReader};
+use crate::{
+    Buffer, DirEntry, EntryType, InternalError, Metadata, MsgSender, ReadSeek, VfsDriver, VfsError,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+#[cfg(feature = "mmap")]
+use std::convert::TryFrom;
 use std::fs::File;
-use std::io::Read;
-use zip;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::SystemTime;
+use zip::result::ZipError;
+use zip::write::FileOptions;
+use zip::CompressionMethod;
 
+type Entries = BTreeMap<String, EntryType>;
+
+type Archive = zip::ZipArchive<BufReader<ArchiveReader>>;
+
+/// Length and modification time of the archive file, used to notice when it has been replaced
+type Stamp = (u64, Option<SystemTime>);
+
+/// Shared so the provider of a driver can be changed after archives have been mounted with it
+pub(crate) type PasswordSlot = Arc<RwLock<Option<Arc<dyn PasswordProvider>>>>;
+
+/// Bit in the general purpose flags of PKWARE strong encryption, which isn't supported
+const STRONG_ENCRYPTION_FLAG: u16 = 1 << 6;
+
+/// Supplies passwords for encrypted zip entries. Both ZipCrypto and AES encrypted entries are
+/// supported. Closures taking the archive filename and the path of the entry implement this.
+pub trait PasswordProvider: Send + Sync {
+    /// Returns the password for the entry `path` in the archive `archive`, or `None` if it
+    /// isn't known
+    fn password(&self, archive: &str, path: &str) -> Option<Vec<u8>>;
+}
+
+impl<F> PasswordProvider for F
+where
+    F: Fn(&str, &str) -> Option<Vec<u8>> + Send + Sync,
+{
+    fn password(&self, archive: &str, path: &str) -> Option<Vec<u8>> {
+        self(archive, path)
+    }
+}
+
+/// Reads the archive from its file, or from memory for archives inside other mounts
+enum ArchiveReader {
+    File(File),
+    Memory(Cursor<Buffer>),
+}
+
+impl ArchiveReader {
+    /// Opens the archive at `filename` unless its data is already in memory
+    fn open(filename: &str, data: Option<&Buffer>) -> io::Result<ArchiveReader> {
+        match data {
+            Some(data) => Ok(ArchiveReader::Memory(Cursor::new(data.clone()))),
+            None => Ok(ArchiveReader::File(File::open(filename)?)),
+        }
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        match self {
+            ArchiveReader::File(file) => Ok(file.metadata()?.len()),
+            ArchiveReader::Memory(data) => Ok(data.get_ref().len() as u64),
+        }
+    }
+
+    /// Archives in memory never change so only their length is used
+    fn stamp(&self) -> io::Result<Stamp> {
+        match self {
+            ArchiveReader::File(file) => {
+                let metadata = file.metadata()?;
+                Ok((metadata.len(), metadata.modified().ok()))
+            }
+            ArchiveReader::Memory(data) => Ok((data.get_ref().len() as u64, None)),
+        }
+    }
+}
+
+impl Read for ArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ArchiveReader::File(file) => file.read(buf),
+            ArchiveReader::Memory(data) => data.read(buf),
+        }
+    }
+}
+
+impl Seek for ArchiveReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            ArchiveReader::File(file) => file.seek(pos),
+            ArchiveReader::Memory(data) => data.seek(pos),
+        }
+    }
+}
+
+/// Data of a file that has been written or renamed but not committed yet
+#[derive(Clone)]
+enum Staged {
+    Data(Buffer),
+    /// Unchanged entry of the archive that has been renamed. The name is the one in the archive.
+    Archive(String),
+}
+
+/// Where the data of a file is read from
+enum Location {
+    /// Name of the entry in the archive
+    Archive(String),
+    Staged(Buffer),
+}
+
+/// Changes to a writable zip that haven't been committed
+#[derive(Default)]
+struct Staging {
+    /// Files written or renamed since the last commit
+    files: BTreeMap<String, Staged>,
+    /// Directories created since the last commit
+    dirs: BTreeSet<String>,
+    /// Paths whose entries in the archive are removed or replaced, including everything below
+    removed: BTreeSet<String>,
+}
+
+impl Staging {
+    fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.dirs.is_empty() && self.removed.is_empty()
+    }
+
+    /// Returns true if the archive entry at `path` is removed or replaced
+    fn hides(&self, path: &str) -> bool {
+        let mut path = path;
+
+        loop {
+            if self.removed.contains(path) {
+                return true;
+            }
+
+            match path.rfind('/') {
+                Some(end) => path = &path[..end],
+                None => return false,
+            }
+        }
+    }
+
+    /// Removes `path` and everything below it
+    fn remove(&mut self, path: &str) {
+        let prefix = format!("{}/", path);
+        let keep = |name: &String| name != path && !name.starts_with(&prefix);
+
+        self.files.retain(|name, _| keep(name));
+        self.dirs.retain(|name| keep(name));
+        self.removed.insert(path.to_owned());
+    }
+}
+
+/// Parsed archives that aren't in use. Parsing the central directory is slow for archives with
+/// many entries so the archives are reused until the file changes.
+#[derive(Default)]
+struct ArchiveCache {
+    stamp: Option<Stamp>,
+    archives: Vec<Archive>,
+    /// Entries of the archive without uncommitted changes
+    entries: Option<Arc<Entries>>,
+}
+
+/// Archive taken from the cache. It's put back when dropped unless the file has changed.
+struct CachedArchive<'a> {
+    archive: Option<Archive>,
+    stamp: Stamp,
+    cache: &'a Mutex<ArchiveCache>,
+}
+
+impl Deref for CachedArchive<'_> {
+    type Target = Archive;
+
+    fn deref(&self) -> &Archive {
+        self.archive.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for CachedArchive<'_> {
+    fn deref_mut(&mut self) -> &mut Archive {
+        self.archive.as_mut().unwrap()
+    }
+}
+
+impl Drop for CachedArchive<'_> {
+    fn drop(&mut self) {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+
+        if cache.stamp == Some(self.stamp) {
+            cache.archives.extend(self.archive.take());
+        }
+    }
+}
+
+/// Paths are stored without leading or trailing slashes
+fn normalize(path: &str) -> &str {
+    path.trim_matches('/')
+}
+
+fn entry_type(entries: &Entries, path: &str) -> EntryType {
+    if path.is_empty() {
+        return EntryType::Directory;
+    }
+
+    if let Some(&entry_type) = entries.get(path) {
+        return entry_type;
+    }
+
+    // Directories don't need to have their own entries in the archive
+    let prefix = format!("{}/", path);
+
+    match entries.range(prefix.clone()..).next() {
+        Some((name, _)) if name.starts_with(&prefix) => EntryType::Directory,
+        _ => EntryType::NotFound,
+    }
+}
+
+/// Finds the index of the entry `name` without decrypting or decompressing it
+fn raw_index<R: Read + Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    name: &str,
+) -> io::Result<Option<usize>> {
+    for index in 0..archive.len() {
+        if archive.by_index_raw(index).map_err(io::Error::from)?.name() == name {
+            return Ok(Some(index));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Checks that the data of an entry is inside the archive. Sizes and offsets can come from
+/// Zip64 extra fields that aren't validated by the zip crate.
+fn check_bounds(path: &str, start: u64, len: u64, archive_len: u64) -> Result<(), InternalError> {
+    if start.checked_add(len).is_none_or(|end| end > archive_len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} extends past the end of the archive", path),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Driver for zip archives.
+///
+/// Mounts are read-only unless the driver is created with `ZipFs::writable` and mounted with
+/// `Evfs::mount_driver`. Writes, removes and renames are then kept in memory and are visible to
+/// reads right away, but the archive on disk isn't changed until `commit` is called. Clones
+/// share the uncommitted changes so a clone can be mounted while the original is used to commit.
+///
+/// Encrypted entries are decrypted with the password from `password` or `password_provider`.
+/// Archives mounted with `Evfs::mount` use the provider set with `Evfs::set_password_provider`.
+///
+/// Archives inside other mounts (such as `data/outer.zip!/inner.zip/dir`) are read from memory
+/// as they are already loaded.
+#[derive(Clone)]
 pub struct ZipFs {
+    /// Path of the archive inside the other mount for archives read from memory
     filename: String,
+    /// `None` for read-only mounts
+    staging: Option<Arc<Mutex<Staging>>>,
+    passwords: PasswordSlot,
+    /// Data of archives that are inside other mounts
+    data: Option<Buffer>,
+    /// Shared by clones as they use the same file
+    cache: Arc<Mutex<ArchiveCache>>,
 }
 
 impl ZipFs {
     pub fn new() -> ZipFs {
         ZipFs {
             filename: String::new(),
+            staging: None,
+            passwords: PasswordSlot::default(),
+            data: None,
+            cache: Arc::default(),
+        }
+    }
+
+    /// Creates a read-only driver for the archive at `filename`. This is used to mount an archive
+    /// with its own password using `Evfs::mount_driver`.
+    pub fn open(filename: &str) -> Result<ZipFs, VfsError> {
+        zip::ZipArchive::new(File::open(filename)?).map_err(io::Error::from)?;
+
+        Ok(ZipFs {
+            filename: filename.into(),
+            ..ZipFs::new()
+        })
+    }
+
+    /// Creates a writable driver for the archive at `filename`. The archive is created on the
+    /// first commit if it doesn't exist.
+    pub fn writable(filename: &str) -> Result<ZipFs, VfsError> {
+        if std::path::Path::new(filename).exists() {
+            zip::ZipArchive::new(File::open(filename)?).map_err(io::Error::from)?;
+        }
+
+        Ok(ZipFs {
+            filename: filename.into(),
+            staging: Some(Arc::new(Mutex::new(Staging::default()))),
+            passwords: PasswordSlot::default(),
+            data: None,
+            cache: Arc::default(),
+        })
+    }
+
+    /// Decrypts all encrypted entries with `password`
+    pub fn password(self, password: impl Into<Vec<u8>>) -> ZipFs {
+        let password = password.into();
+        self.password_provider(Arc::new(move |_: &str, _: &str| Some(password.clone())))
+    }
+
+    /// Asks `provider` for the password of each encrypted entry when it's read
+    pub fn password_provider(mut self, provider: Arc<dyn PasswordProvider>) -> ZipFs {
+        self.passwords = Arc::new(RwLock::new(Some(provider)));
+        self
+    }
+
+    pub(crate) fn password_slot(&self) -> PasswordSlot {
+        self.passwords.clone()
+    }
+
+    /// Returns true if there are changes that haven't been committed
+    pub fn has_changes(&self) -> bool {
+        self.staging().is_some_and(|staging| !staging.is_empty())
+    }
+
+    /// Writes a new archive with the uncommitted changes and replaces the old one with it.
+    /// The archive is written to a temporary file next to it first so the old archive is kept
+    /// if anything fails. Entries that haven't changed are copied without recompressing them.
+    pub fn commit(&self) -> Result<(), VfsError> {
+        let mut staging = self.staging().ok_or_else(|| VfsError::ReadOnly {
+            path: self.filename.clone(),
+        })?;
+
+        let temp_filename = format!("{}.tmp", self.filename);
+
+        if let Err(e) = self.write_archive(&staging, &temp_filename) {
+            let _ = std::fs::remove_file(&temp_filename);
+            return Err(io::Error::from(e).into());
+        }
+
+        // Cached archives keep the old file open
+        *self.cache() = ArchiveCache::default();
+
+        std::fs::rename(&temp_filename, &self.filename)?;
+        *staging = Staging::default();
+        Ok(())
+    }
+
+    /// Throws away all changes since the last commit
+    pub fn discard(&self) {
+        if let Some(mut staging) = self.staging() {
+            *staging = Staging::default();
+        }
+    }
+
+    fn staging(&self) -> Option<MutexGuard<'_, Staging>> {
+        // The changes are never left in an invalid state so a poisoned lock can still be used
+        self.staging
+            .as_ref()
+            .map(|staging| staging.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn cache(&self) -> MutexGuard<'_, ArchiveCache> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Opens the archive, reusing a cached one if the file hasn't changed. Writable archives may
+    /// not exist yet, in which case `None` is returned.
+    fn archive(&self) -> io::Result<Option<CachedArchive<'_>>> {
+        let file = match ArchiveReader::open(&self.filename, self.data.as_ref()) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && self.staging.is_some() => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let stamp = file.stamp()?;
+
+        let cached = {
+            let mut cache = self.cache();
+
+            if cache.stamp != Some(stamp) {
+                *cache = ArchiveCache {
+                    stamp: Some(stamp),
+                    ..ArchiveCache::default()
+                };
+            }
+
+            cache.archives.pop()
+        };
+
+        let archive = match cached {
+            Some(archive) => archive,
+            // The central directory is parsed with many small reads
+            None => zip::ZipArchive::new(BufReader::new(file)).map_err(io::Error::from)?,
+        };
+
+        Ok(Some(CachedArchive {
+            archive: Some(archive),
+            stamp,
+            cache: &self.cache,
+        }))
+    }
+
+    /// Full paths of the files and explicit directories in the archive
+    fn archive_entries(&self) -> io::Result<Arc<Entries>> {
+        let archive = match self.archive()? {
+            Some(archive) => archive,
+            None => return Ok(Arc::default()),
+        };
+
+        let cached = {
+            let cache = self.cache();
+            cache
+                .entries
+                .clone()
+                .filter(|_| cache.stamp == Some(archive.stamp))
+        };
+
+        if let Some(entries) = cached {
+            return Ok(entries);
+        }
+
+        let mut entries = Entries::new();
+
+        for name in archive.file_names() {
+            let entry_type = if name.ends_with('/') {
+                EntryType::Directory
+            } else {
+                EntryType::File
+            };
+
+            match normalize(name) {
+                "" => (),
+                name => {
+                    entries.insert(name.to_owned(), entry_type);
+                }
+            }
+        }
+
+        let entries = Arc::new(entries);
+        let mut cache = self.cache();
+
+        if cache.stamp == Some(archive.stamp) {
+            cache.entries = Some(entries.clone());
+        }
+
+        Ok(entries)
+    }
+
+    /// Full paths of the files and explicit directories in the archive with the uncommitted
+    /// changes applied
+    fn entries(&self, staging: Option<&Staging>) -> io::Result<Arc<Entries>> {
+        let mut entries = self.archive_entries()?;
+
+        if let Some(staging) = staging.filter(|staging| !staging.is_empty()) {
+            let entries = Arc::make_mut(&mut entries);
+            entries.retain(|name, _| !staging.hides(name));
+
+            for name in &staging.dirs {
+                entries.insert(name.clone(), EntryType::Directory);
+            }
+
+            for name in staging.files.keys() {
+                entries.insert(name.clone(), EntryType::File);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn not_found(&self, path: &str) -> InternalError {
+        if self.has_entry(path) == EntryType::Directory {
+            InternalError::NotFile { path: path.into() }
+        } else {
+            InternalError::PathNotFound { path: path.into() }
         }
     }
+
+    /// Finds where the data of a file is, taking uncommitted changes into account
+    fn locate(&self, path: &str) -> Result<Location, InternalError> {
+        let hidden = match self.staging() {
+            Some(staging) => match staging.files.get(path) {
+                Some(Staged::Data(data)) => return Ok(Location::Staged(data.clone())),
+                Some(Staged::Archive(name)) => return Ok(Location::Archive(name.clone())),
+                None => staging.hides(path),
+            },
+            None => false,
+        };
+
+        if hidden {
+            Err(self.not_found(path))
+        } else {
+            Ok(Location::Archive(path.to_owned()))
+        }
+    }
+
+    fn zip_error(&self, path: &str, e: ZipError) -> InternalError {
+        match e {
+            ZipError::FileNotFound => self.not_found(path),
+            e => io::Error::from(e).into(),
+        }
+    }
+
+    /// Returns true if the entry `name` has to be decrypted
+    fn is_encrypted<R: Read + Seek>(
+        &self,
+        archive: &mut zip::ZipArchive<R>,
+        path: &str,
+        name: &str,
+    ) -> Result<bool, InternalError> {
+        match archive.by_name(name) {
+            Ok(_) => Ok(false),
+            Err(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED)) => Ok(true),
+            Err(e) => Err(self.zip_error(path, e)),
+        }
+    }
+
+    /// Returns true if the entry uses PKWARE strong encryption. The zip crate doesn't check
+    /// for it so the flags are read from the local header.
+    fn uses_strong_encryption<R: Read + Seek>(
+        &self,
+        archive: &mut zip::ZipArchive<R>,
+        name: &str,
+    ) -> io::Result<bool> {
+        let header_start = match raw_index(archive, name)? {
+            Some(index) => archive
+                .by_index_raw(index)
+                .map_err(io::Error::from)?
+                .header_start(),
+            None => return Ok(false),
+        };
+
+        let mut file = ArchiveReader::open(&self.filename, self.data.as_ref())?;
+        let mut flags = [0; 2];
+        file.seek(SeekFrom::Start(header_start + 6))?;
+        file.read_exact(&mut flags)?;
+
+        Ok(u16::from_le_bytes(flags) & STRONG_ENCRYPTION_FLAG != 0)
+    }
+
+    /// Opens the entry `name` for reading, decrypting it if needed
+    fn read_entry<'a, R: Read + Seek>(
+        &self,
+        archive: &'a mut zip::ZipArchive<R>,
+        path: &str,
+        name: &str,
+    ) -> Result<zip::read::ZipFile<'a>, InternalError> {
+        if !self.is_encrypted(archive, path, name)? {
+            return archive.by_name(name).map_err(|e| self.zip_error(path, e));
+        }
+
+        if self.uses_strong_encryption(archive, name)? {
+            return Err(InternalError::UnsupportedEncryption { path: path.into() });
+        }
+
+        let password = self
+            .passwords
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .and_then(|provider| provider.password(&self.filename, path))
+            .ok_or_else(|| InternalError::InvalidPassword { path: path.into() })?;
+
+        match archive.by_name_decrypt(name, &password) {
+            Ok(Ok(file)) => Ok(file),
+            Ok(Err(zip::result::InvalidPassword)) => {
+                Err(InternalError::InvalidPassword { path: path.into() })
+            }
+            Err(ZipError::UnsupportedArchive(_)) => {
+                Err(InternalError::UnsupportedEncryption { path: path.into() })
+            }
+            Err(e) => Err(self.zip_error(path, e)),
+        }
+    }
+
+    fn load_archive_file(
+        &self,
+        path: &str,
+        name: &str,
+        send_msg: &MsgSender,
+    ) -> Result<Buffer, InternalError> {
+        let mut archive = self
+            .archive()?
+            .ok_or_else(|| InternalError::PathNotFound { path: path.into() })?;
+        let mut file = self.read_entry(&mut *archive, path, name)?;
+        let len = file.size();
+
+        send_msg.read_all(&mut file, Some(len))
+    }
+
+    fn open_archive_file(
+        &self,
+        path: &str,
+        name: &str,
+    ) -> Result<Box<dyn ReadSeek>, InternalError> {
+        let read_file = match ArchiveReader::open(&self.filename, self.data.as_ref()) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(InternalError::PathNotFound { path: path.into() })
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let (method, start, compressed_size, size) = {
+            let mut archive = self
+                .archive()?
+                .ok_or_else(|| InternalError::PathNotFound { path: path.into() })?;
+
+            // Encrypted entries are decrypted into memory
+            if self.is_encrypted(&mut *archive, path, name)? {
+                let mut file = self.read_entry(&mut *archive, path, name)?;
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)?;
+                return Ok(Box::new(Cursor::new(data)));
+            }
+
+            let file = archive.by_name(name).map_err(|e| self.zip_error(path, e))?;
+            (
+                file.compression(),
+                file.data_start(),
+                file.compressed_size(),
+                file.size(),
+            )
+        };
+
+        check_bounds(path, start, compressed_size, read_file.len()?)?;
+
+        if method == CompressionMethod::Stored {
+            return Ok(Box::new(SubReader::new(read_file, start, size)?));
+        }
+
+        let filename = self.filename.clone();
+        let data = self.data.clone();
+
+        let factory: DecoderFactory = match method {
+            CompressionMethod::Deflated => Box::new(move || {
+                let reader = ArchiveReader::open(&filename, data.as_ref())?;
+                let raw = SubReader::new(reader, start, compressed_size)?;
+                Ok(Box::new(flate2::read::DeflateDecoder::new(raw)))
+            }),
+            CompressionMethod::Bzip2 => Box::new(move || {
+                let reader = ArchiveReader::open(&filename, data.as_ref())?;
+                let raw = SubReader::new(reader, start, compressed_size)?;
+                Ok(Box::new(bzip2::read::BzDecoder::new(raw)))
+            }),
+            _ => {
+                return Err(io::Error::other(format!(
+                    "Unsupported compression method {} for {}",
+                    method, path
+                ))
+                .into())
+            }
+        };
+
+        Ok(Box::new(DecoderStream::new(factory, size)?))
+    }
+
+    /// Writes the archive with the changes applied to `filename`
+    fn write_archive(&self, staging: &Staging, filename: &str) -> zip::result::ZipResult<()> {
+        let mut writer = zip::ZipWriter::new(File::create(filename)?);
+        let mut archive = self.archive()?;
+        let mut indices = HashMap::new();
+        let mut written = HashSet::new();
+
+        if let Some(archive) = &mut archive {
+            for index in 0..archive.len() {
+                let file = archive.by_index_raw(index)?;
+                let name = normalize(file.name()).to_owned();
+                indices.insert(name.clone(), index);
+
+                if name.is_empty() || staging.hides(&name) {
+                    continue;
+                }
+
+                if file.is_dir() {
+                    writer.add_directory(file.name(), FileOptions::default())?;
+                } else {
+                    writer.raw_copy_file(file)?;
+                }
+
+                written.insert(name);
+            }
+        }
+
+        for dir in &staging.dirs {
+            if written.insert(dir.clone()) {
+                writer.add_directory(format!("{}/", dir), FileOptions::default())?;
+            }
+        }
+
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        for (name, staged) in &staging.files {
+            match staged {
+                Staged::Data(data) => {
+                    writer.start_file(name, options)?;
+                    writer.write_all(data)?;
+                }
+                Staged::Archive(source) => {
+                    let index = indices.get(source).ok_or(ZipError::FileNotFound)?;
+                    let file = archive
+                        .as_mut()
+                        .ok_or(ZipError::FileNotFound)?
+                        .by_index_raw(*index)?;
+                    writer.raw_copy_file_rename(file, name.as_str())?;
+                }
+            }
+        }
+
+        writer.finish()?.sync_all()?;
+        Ok(())
+    }
+}
+
+impl Default for ZipFs {
+    fn default() -> ZipFs {
+        ZipFs::new()
+    }
 }
 
 impl VfsDriver for ZipFs {
@@ -36,66 +748,325 @@ impl VfsDriver for ZipFs {
     fn new_from_path(&self, filename: &str) -> Result<Box<dyn VfsDriver>, VfsError> {
         Ok(Box::new(ZipFs {
             filename: filename.into(),
+            staging: None,
+            passwords: self.passwords.clone(),
+            data: None,
+            cache: Arc::default(),
         }))
     }
 
     ///
-    /// Read a file from the local filesystem.
-    /// TODO: Make the 5 meg size configurable
-    fn load_file(
-        &self,
-        path: &str,
-        send_msg: &crossbeam_channel::Sender<RecvMsg>,
-    ) -> Result<Box<[u8]>, InternalError> {
-        let read_file = File::open(&self.filename)?;
-        // TODO: We should cache the archive and not reopen it
-        // TODO: Handle error better here
-        let mut archive = zip::ZipArchive::new(read_file).unwrap();
-        let mut file = archive.by_name(path).unwrap();
-        let len = file.size() as usize;
-        let mut output_data = vec![0u8; len];
-
-        // if file is small than 10k we just unpack it directly without progress
-        if len < 10 * 1024 {
-            send_msg.send(RecvMsg::ReadProgress(0.0))?;
-            file.read_to_end(&mut output_data)?;
-        } else {
-            // above 10k we read in 10 chunks
-            let loop_count = 10;
-            let block_len = len / loop_count;
-            let mut percent = 0.0;
-            let percent_step = 1.0 / loop_count as f32;
+    /// The archive is read from `data` and uses the same passwords as this driver.
+    fn new_from_data(&self, path: &str, data: Buffer) -> Result<Box<dyn VfsDriver>, InternalError> {
+        zip::ZipArchive::new(Cursor::new(data.clone())).map_err(io::Error::from)?;
+
+        Ok(Box::new(ZipFs {
+            filename: path.into(),
+            staging: None,
+            passwords: self.passwords.clone(),
+            data: Some(data),
+            cache: Arc::default(),
+        }))
+    }
 
-            for i in 0..loop_count {
-                let block_offset = i * block_len;
-                let read_amount = usize::min(len - block_offset, block_len);
-                file.read_exact(&mut output_data[block_offset..block_offset + read_amount])?;
-                send_msg.send(RecvMsg::ReadProgress(percent))?;
-                percent += percent_step;
+    ///
+    /// Read and unpack a file from the archive.
+    fn load_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        match self.locate(path)? {
+            Location::Archive(name) => self.load_archive_file(path, &name, send_msg),
+            Location::Staged(data) => {
+                send_msg.finish(data.len() as u64)?;
+                Ok(data)
+            }
+        }
+    }
+
+    ///
+    /// Entries that are stored without compression are returned as a slice of the mapped
+    /// archive. Compressed entries are unpacked the same way as `load_file` does it.
+    #[cfg(feature = "mmap")]
+    fn map_file(&self, path: &str, send_msg: &MsgSender) -> Result<Buffer, InternalError> {
+        let name = match self.locate(path)? {
+            Location::Archive(name) => name,
+            Location::Staged(_) => return self.load_file(path, send_msg),
+        };
+
+        let read_file = match ArchiveReader::open(&self.filename, self.data.as_ref()) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(InternalError::PathNotFound { path: path.into() })
             }
+            Err(e) => return Err(e.into()),
+        };
+        let mut archive = self
+            .archive()?
+            .ok_or_else(|| InternalError::PathNotFound { path: path.into() })?;
+
+        if self.is_encrypted(&mut *archive, path, &name)? {
+            return self.load_archive_file(path, &name, send_msg);
         }
 
-        //send_msg.send(RecvMsg::ReadDone(output_data.into_boxed_slice()))?;
+        let file = archive
+            .by_name(&name)
+            .map_err(|e| self.zip_error(path, e))?;
+
+        if file.compression() != CompressionMethod::Stored {
+            drop(file);
+            return self.load_archive_file(path, &name, send_msg);
+        }
 
-        Ok(output_data.into_boxed_slice())
+        let (start, size) = (file.data_start(), file.size());
+        check_bounds(path, start, size, read_file.len()?)?;
+
+        // Archives larger than the address space can't be mapped on 32-bit targets
+        let (start, end) = match (usize::try_from(start), usize::try_from(start + size)) {
+            (Ok(start), Ok(end)) => (start, end),
+            _ => {
+                drop(file);
+                return self.load_archive_file(path, &name, send_msg);
+            }
+        };
+
+        let data = match read_file {
+            // Archives in memory are sliced directly
+            ArchiveReader::Memory(data) => data.into_inner(),
+            // SAFETY: The entry is only read through the returned slice. Replacing or truncating
+            // the archive while the slice is alive raises SIGBUS or changes the data under it, as
+            // documented on `Evfs::load_file_mapped`.
+            ArchiveReader::File(file) => Buffer::from_owner(unsafe { memmap2::Mmap::map(&file)? }),
+        };
+
+        send_msg.finish(size)?;
+        Ok(data.slice(start..end))
+    }
+
+    ///
+    /// Stored entries are read directly from the archive while compressed entries are
+    /// decompressed on the fly as the stream is read.
+    fn open(&self, path: &str) -> Result<Box<dyn ReadSeek>, InternalError> {
+        match self.locate(path)? {
+            Location::Archive(name) => self.open_archive_file(path, &name),
+            Location::Staged(data) => Ok(Box::new(Cursor::new(data))),
+        }
     }
 
     /// This is used to figure out if a certain mount can be done
     fn has_entry(&self, path: &str) -> EntryType {
-        // TODO: Fix unwrap
-        let read_file = File::open(&self.filename).unwrap();
-        let mut archive = zip::ZipArchive::new(read_file).unwrap();
+        let staging = self.staging();
 
-        if archive.by_name(path).is_ok() {
-            EntryType::File
-        } else {
-            EntryType::NotFound
+        match self.entries(staging.as_deref()) {
+            Ok(entries) => entry_type(&entries, normalize(path)),
+            Err(_) => EntryType::NotFound,
+        }
+    }
+
+    ///
+    /// Sizes are read from the central directory so files don't have to be unpacked.
+    fn metadata(&self, path: &str) -> Result<Metadata, InternalError> {
+        let entry_type = self.has_entry(path);
+
+        let len = match entry_type {
+            EntryType::Directory => 0,
+            EntryType::NotFound => return Err(InternalError::PathNotFound { path: path.into() }),
+            EntryType::File => match self.locate(path)? {
+                Location::Staged(data) => data.len() as u64,
+                Location::Archive(name) => {
+                    let mut archive = self
+                        .archive()?
+                        .ok_or_else(|| InternalError::PathNotFound { path: path.into() })?;
+
+                    if !self.is_encrypted(&mut *archive, path, &name)? {
+                        let file = archive
+                            .by_name(&name)
+                            .map_err(|e| self.zip_error(path, e))?;
+                        return Ok(Metadata {
+                            entry_type,
+                            len: file.size(),
+                            modified: None,
+                        });
+                    }
+
+                    // Encrypted entries can only be opened raw without a password
+                    let index = raw_index(&mut *archive, &name)?
+                        .ok_or_else(|| InternalError::PathNotFound { path: path.into() })?;
+                    let file = archive
+                        .by_index_raw(index)
+                        .map_err(|e| self.zip_error(path, e))?;
+                    file.size()
+                }
+            },
+        };
+
+        Ok(Metadata {
+            entry_type,
+            len,
+            modified: None,
+        })
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, InternalError> {
+        let entries = self.entries(self.staging().as_deref())?;
+
+        let prefix = match normalize(path) {
+            "" => String::new(),
+            dir => format!("{}/", dir),
+        };
+
+        let mut dir_entries = BTreeMap::new();
+
+        for (name, &entry_type) in entries.range(prefix.clone()..) {
+            let rest = match name.strip_prefix(&prefix) {
+                Some(rest) => rest,
+                None => break,
+            };
+
+            match rest.find('/') {
+                Some(end) => {
+                    dir_entries.insert(rest[..end].to_owned(), EntryType::Directory);
+                }
+                None => {
+                    dir_entries.entry(rest.to_owned()).or_insert(entry_type);
+                }
+            }
+        }
+
+        Ok(dir_entries
+            .into_iter()
+            .map(|(name, entry_type)| DirEntry { name, entry_type })
+            .collect())
+    }
+
+    fn write_file(&self, path: &str, data: Buffer) -> Result<(), InternalError> {
+        let path = normalize(path);
+        let mut staging = self
+            .staging()
+            .ok_or_else(|| InternalError::ReadOnly { path: path.into() })?;
+        let entries = self.entries(Some(&staging))?;
+
+        if entry_type(&entries, path) == EntryType::Directory {
+            return Err(InternalError::NotFile { path: path.into() });
+        }
+
+        let mut parent = path;
+        while let Some(end) = parent.rfind('/') {
+            parent = &parent[..end];
+
+            if entries.get(parent) == Some(&EntryType::File) {
+                return Err(InternalError::NotFile {
+                    path: parent.to_owned(),
+                });
+            }
+        }
+
+        staging.removed.insert(path.to_owned());
+        staging.files.insert(path.to_owned(), Staged::Data(data));
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), InternalError> {
+        let path = normalize(path);
+        let mut staging = self
+            .staging()
+            .ok_or_else(|| InternalError::ReadOnly { path: path.into() })?;
+
+        match entry_type(&*self.entries(Some(&staging))?, path) {
+            EntryType::Directory => Ok(()),
+            EntryType::File => Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists as a file", path),
+            )
+            .into()),
+            EntryType::NotFound => {
+                staging.dirs.insert(path.to_owned());
+                Ok(())
+            }
+        }
+    }
+
+    ///
+    /// Directories are removed together with everything in them.
+    fn remove(&self, path: &str) -> Result<(), InternalError> {
+        let path = normalize(path);
+        let mut staging = self
+            .staging()
+            .ok_or_else(|| InternalError::ReadOnly { path: path.into() })?;
+
+        if path.is_empty()
+            || entry_type(&*self.entries(Some(&staging))?, path) == EntryType::NotFound
+        {
+            return Err(InternalError::PathNotFound { path: path.into() });
+        }
+
+        staging.remove(path);
+        Ok(())
+    }
+
+    ///
+    /// Renamed entries that haven't been changed are copied from the old archive on commit.
+    fn rename(&self, from: &str, to: &str) -> Result<(), InternalError> {
+        let (from, to) = (normalize(from), normalize(to));
+        let mut staging = self
+            .staging()
+            .ok_or_else(|| InternalError::ReadOnly { path: to.into() })?;
+        let entries = self.entries(Some(&staging))?;
+
+        if from.is_empty() || entry_type(&entries, from) == EntryType::NotFound {
+            return Err(InternalError::PathNotFound { path: from.into() });
+        }
+
+        if to.is_empty() || entry_type(&entries, to) == EntryType::Directory {
+            return Err(InternalError::NotFile { path: to.into() });
+        }
+
+        // Everything at or below `from` moves to the same place below `to`
+        let prefix = format!("{}/", from);
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+
+        for (name, &entry_type) in entries.iter() {
+            let new_name = if name == from {
+                to.to_owned()
+            } else if let Some(rest) = name.strip_prefix(&prefix) {
+                format!("{}/{}", to, rest)
+            } else {
+                continue;
+            };
+
+            if entry_type == EntryType::Directory {
+                dirs.push(new_name);
+            } else {
+                let staged = staging
+                    .files
+                    .get(name)
+                    .cloned()
+                    .unwrap_or_else(|| Staged::Archive(name.clone()));
+                files.push((new_name, staged));
+            }
         }
+
+        // Directories without entries of their own still have to exist after the rename
+        if files.is_empty() && dirs.is_empty() {
+            dirs.push(to.to_owned());
+        }
+
+        staging.remove(from);
+        staging.remove(to);
+
+        for dir in dirs {
+            staging.dirs.insert(dir);
+        }
+
+        for (name, staged) in files {
+            staging.files.insert(name, staged);
+        }
+
+        Ok(())
     }
 
     // local fs can't decompress anything
-    fn can_decompress(&self, _data: &[u8]) -> bool {
-        false
+    fn can_decompress(&self, data: &[u8]) -> bool {
+        // Local file header, or the end of central directory record of an empty archive
+        data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06")
     }
 
     // local fs support any file ext